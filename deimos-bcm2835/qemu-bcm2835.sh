@@ -0,0 +1,29 @@
+#!/bin/bash
+
+# Runs a kernel ELF under QEMU's raspi0 machine with the Mini UART on stdio, and exits with the
+# status the kernel reported through `exit::exit`. Use it in place of upload-bcm2835.sh with e.g.
+#   CARGO_TARGET_ARMV6ZK_NONE_EABIHF_RUNNER=./qemu-bcm2835.sh cargo test
+# Set DEIMOS_QEMU_MACHINE to run on another board instead, e.g. raspi2b or raspi3ap.
+# Kernels built with the `semihosting` feature print to the same stdout and make QEMU exit by
+# themselves, so this works for them too. Set DEIMOS_QEMU_DISPLAY (e.g. to `gtk`) to see the
+# framebuffer.
+
+elf_path=$1
+status=1
+machine=${DEIMOS_QEMU_MACHINE:-raspi0}
+# the Pi 3 machines only come with the 64-bit emulator, which boots 32-bit ELFs in AArch32
+case $machine in
+  raspi3*) qemu=qemu-system-aarch64 ;;
+  *) qemu=qemu-system-arm ;;
+esac
+
+# -no-reboot turns the watchdog reset at the end of the run into a QEMU exit.
+while IFS= read -r line; do
+  printf '%s\n' "$line"
+  if [[ $line =~ deimos:\ exit\ status\ ([0-9]+) ]]; then
+    status=${BASH_REMATCH[1]}
+  fi
+done < <("$qemu" -M "$machine" -kernel "$elf_path" -display "${DEIMOS_QEMU_DISPLAY:-none}" -no-reboot \
+  -serial null -serial stdio -semihosting-config enable=on,target=native)
+
+exit "$status"
@@ -0,0 +1,161 @@
+use core::fmt::{Debug, Display, Formatter};
+
+use crate::board::{self, PAC_PERIPHERAL_BASE};
+
+// The VideoCore sees SDRAM through four 1GB aliases, selected by the top two bits of a bus
+// address. The alias determines how the access interacts with the VC's L2 cache:
+//  0x0 => L1 and L2 cached
+//  0x4 => L2 cache coherent (non-allocating)
+//  0x8 => L2 cached only
+//  0xC => direct, uncached
+// On 2835, the ARM has no L2, so the ARM CPU is made to use the GPU L2 cache, which basically ends
+// up meaning that the VideoCore MMU maps the ARM's view of memory to the 0x4 alias.
+// Peripherals live at 0x7e00_0000 on the bus, and wherever the board puts them for the ARM (see
+// `board`). Addresses in the PAC's window at 0x2000_0000 count as peripheral addresses too, so
+// that register addresses taken from the PAC translate on any board.
+
+const ALIAS_MASK: u32 = 0xc000_0000;
+pub const PERIPHERAL_BASE_BUS: u32 = 0x7e00_0000;
+pub const PERIPHERAL_SIZE: u32 = 0x0100_0000;
+
+fn in_peripherals(addr: u32, base: u32) -> bool {
+    base <= addr && addr - base < PERIPHERAL_SIZE
+}
+
+/// Which of the VideoCore's views of SDRAM a bus address goes through.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BusAlias {
+    /// `0x0...`: cached in both the VC L1 and L2.
+    L1AndL2 = 0x0000_0000,
+    /// `0x4...`: coherent with the L2, but does not allocate into it. This is the alias that the
+    /// ARM's own accesses go through.
+    L2Coherent = 0x4000_0000,
+    /// `0x8...`: cached in the L2 only.
+    L2Only = 0x8000_0000,
+    /// `0xC...`: direct, uncached.
+    Direct = 0xc000_0000,
+}
+impl BusAlias {
+    pub const fn bits(self) -> u32 {
+        self as u32
+    }
+    pub const fn from_bits(bits: u32) -> Self {
+        match bits & ALIAS_MASK {
+            0x0000_0000 => Self::L1AndL2,
+            0x4000_0000 => Self::L2Coherent,
+            0x8000_0000 => Self::L2Only,
+            _ => Self::Direct,
+        }
+    }
+}
+
+/// An address in the ARM's physical address space.
+#[repr(transparent)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PhysAddr(u32);
+
+/// An address on the VideoCore bus, as seen by the DMA engine and the mailbox.
+#[repr(transparent)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct BusAddr(u32);
+
+impl PhysAddr {
+    pub const fn new(addr: u32) -> Self {
+        Self(addr)
+    }
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self(ptr.expose_provenance() as u32)
+    }
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+    pub fn as_ptr<T>(self) -> *mut T {
+        core::ptr::with_exposed_provenance_mut(self.0 as usize)
+    }
+    pub fn is_sdram(self) -> bool {
+        self.0 < board::current().sdram_end && !in_peripherals(self.0, PAC_PERIPHERAL_BASE)
+    }
+    pub fn is_peripheral(self) -> bool {
+        self.peripheral_offset().is_some()
+    }
+    fn peripheral_offset(self) -> Option<u32> {
+        [board::current().peripheral_base, PAC_PERIPHERAL_BASE]
+            .into_iter()
+            .find(|&base| in_peripherals(self.0, base))
+            .map(|base| self.0 - base)
+    }
+
+    /// Translate to a bus address. SDRAM is placed in `alias`; peripherals have a single bus
+    /// mapping, so `alias` is ignored for them. Returns `None` if the address has no bus mapping.
+    pub fn to_bus(self, alias: BusAlias) -> Option<BusAddr> {
+        if self.is_sdram() {
+            Some(BusAddr(self.0 | alias.bits()))
+        } else {
+            self.peripheral_offset()
+                .map(|offset| BusAddr(PERIPHERAL_BASE_BUS + offset))
+        }
+    }
+}
+
+impl BusAddr {
+    pub const fn new(addr: u32) -> Self {
+        Self(addr)
+    }
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+    pub const fn is_peripheral(self) -> bool {
+        PERIPHERAL_BASE_BUS <= self.0 && self.0 - PERIPHERAL_BASE_BUS < PERIPHERAL_SIZE
+    }
+    /// The SDRAM alias this address goes through, or `None` for peripheral addresses.
+    pub const fn alias(self) -> Option<BusAlias> {
+        if self.is_peripheral() {
+            None
+        } else {
+            Some(BusAlias::from_bits(self.0))
+        }
+    }
+    /// The same location, seen through a different SDRAM alias. Peripheral addresses are
+    /// returned unchanged.
+    pub const fn with_alias(self, alias: BusAlias) -> Self {
+        if self.is_peripheral() {
+            self
+        } else {
+            Self((self.0 & !ALIAS_MASK) | alias.bits())
+        }
+    }
+
+    /// Translate back to an ARM physical address, regardless of alias. Returns `None` if the
+    /// location is not visible to the ARM.
+    pub fn to_phys(self) -> Option<PhysAddr> {
+        if self.is_peripheral() {
+            Some(PhysAddr(
+                self.0 - PERIPHERAL_BASE_BUS + board::current().peripheral_base,
+            ))
+        } else {
+            Some(PhysAddr(self.0 & !ALIAS_MASK)).filter(|phys| phys.is_sdram())
+        }
+    }
+}
+
+impl Debug for PhysAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PhysAddr({:08x})", self.0)
+    }
+}
+impl Display for PhysAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+impl Debug for BusAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "BusAddr({:08x})", self.0)
+    }
+}
+impl Display for BusAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
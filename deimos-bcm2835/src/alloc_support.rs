@@ -1,15 +1,296 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::{Cell, RefCell},
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+use critical_section::Mutex;
+
+use crate::{board, boot, mailbox, println};
+
+// The heap gets whatever ARM memory is left between `__exec_end` and the top of the ARM's share of
+// SDRAM, as the mailbox reports it. `__exec_end` is past the kernel image and the IRQ and
+// supervisor stacks, which bcm2835.ld puts after the image in that order. Carve-outs that callers
+// ask for at `heap_init` come off the top first. Everything the heap doesn't get is listed in the
+// memory map, along with the parts of the image that are worth knowing the location of (the
+// stacks and the MMU's translation table).
+
 unsafe extern "C" {
+    static __exec_start: [u32; 0];
     static __exec_end: [u32; 0];
+    static __stack_start: [u32; 0];
+    static __stack_end: [u32; 0];
+    static __svc_stack_start: [u32; 0];
+    static __svc_stack_end: [u32; 0];
+}
+
+/// Used if neither the mailbox nor the boot info say where ARM memory ends.
+const DEFAULT_ARM_END: usize = 0x0800_0000;
+const HEAP_ALIGN: usize = 0x1000;
+const MAX_RESERVED: usize = 8;
+
+/// A region of memory that the heap stays out of.
+#[derive(Debug, Clone)]
+pub struct Reserved {
+    pub name: &'static str,
+    pub region: Range<usize>,
+}
+
+struct MemoryMap {
+    arm_end: usize,
+    heap: Range<usize>,
+    reserved: [Option<Reserved>; MAX_RESERVED],
+}
+
+static MEMORY_MAP: Mutex<RefCell<MemoryMap>> = Mutex::new(RefCell::new(MemoryMap {
+    arm_end: 0,
+    heap: 0..0,
+    reserved: [const { None }; MAX_RESERVED],
+}));
+
+fn arm_end() -> usize {
+    let end = match mailbox::query::<mailbox::GetArmMemory>(()) {
+        Ok(memory) => memory.base as usize + memory.size as usize,
+        Err(e) => {
+            println!("ARM memory size unknown ({e}), going by the boot info");
+            boot::info()
+                .memory
+                .map_or(DEFAULT_ARM_END, |memory| memory.end() as usize)
+        }
+    };
+    end.min(board::current().sdram_end as usize)
+}
+
+/// Set up the heap, after taking each of `carve_outs` off the top of ARM memory. They can be
+/// found again with [`reserved`].
+pub fn heap_init(carve_outs: &[(&'static str, Layout)]) {
+    let exec_end = (&raw const __exec_end).addr();
+    let arm_end = arm_end();
+
+    let mut reserved = [const { None }; MAX_RESERVED];
+    let mut entries = 0;
+    let mut add = |name, region: Range<usize>| {
+        assert!(entries < MAX_RESERVED, "too many reserved regions");
+        reserved[entries] = Some(Reserved { name, region });
+        entries += 1;
+    };
+    add("firmware", 0..(&raw const __exec_start).addr());
+    add(
+        "kernel image",
+        (&raw const __exec_start).addr()..(&raw const __stack_start).addr(),
+    );
+    add(
+        "IRQ stack",
+        (&raw const __stack_start).addr()..(&raw const __stack_end).addr(),
+    );
+    add(
+        "supervisor stack",
+        (&raw const __svc_stack_start).addr()..(&raw const __svc_stack_end).addr(),
+    );
+    add(
+        "MMU translation table",
+        crate::mmu_support::translation_table(),
+    );
+    let mut top = arm_end;
+    for &(name, layout) in carve_outs {
+        let start = top
+            .checked_sub(layout.size())
+            .map(|start| start & !(layout.align() - 1))
+            .filter(|&start| start >= exec_end)
+            .unwrap_or_else(|| panic!("no room to reserve {name} ({layout:?})"));
+        add(name, start..top);
+        top = start;
+    }
+
+    let heap = exec_end.next_multiple_of(HEAP_ALIGN)..(top & !(HEAP_ALIGN - 1));
+    assert!(heap.end > heap.start, "no memory for the heap");
+    unsafe { HEAP.inner.init(heap.start, heap.len()) };
+    critical_section::with(|cs| {
+        HEAP.stats.borrow(cs).set(HeapStats {
+            size: heap.len(),
+            ..HeapStats::EMPTY
+        });
+        *MEMORY_MAP.borrow_ref_mut(cs) = MemoryMap {
+            arm_end,
+            heap,
+            reserved,
+        };
+    });
+}
+
+/// The region reserved under `name`, either by [`heap_init`] or for the kernel itself.
+pub fn reserved(name: &str) -> Option<Range<usize>> {
+    critical_section::with(|cs| {
+        MEMORY_MAP
+            .borrow_ref(cs)
+            .reserved
+            .iter()
+            .flatten()
+            .find(|reserved| reserved.name == name)
+            .map(|reserved| reserved.region.clone())
+    })
+}
+
+pub fn dump_memory_map() {
+    critical_section::with(|cs| {
+        let map = MEMORY_MAP.borrow_ref(cs);
+        println!("Memory map (ARM memory ends at {:08x}):", map.arm_end);
+        for reserved in map.reserved.iter().flatten() {
+            println!(
+                "  {:08x}..{:08x} {}",
+                reserved.region.start, reserved.region.end, reserved.name
+            );
+        }
+        println!("  {:08x}..{:08x} heap", map.heap.start, map.heap.end);
+    })
+}
+
+/// A snapshot of the heap. Sizes are what was asked for, not counting the allocator's own
+/// overhead or padding.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    /// The most `used` has been since [`heap_init`] or [`reset_high_water_mark`].
+    pub high_water_mark: usize,
+    /// Allocations not yet freed.
+    pub live_allocations: usize,
+    pub total_allocations: usize,
+    pub failed_allocations: usize,
+    /// The largest allocation that would succeed right now, to within TLSF's size classes. Only
+    /// filled in by [`stats`], since it has to be searched for.
+    pub largest_free_block: usize,
+}
+impl HeapStats {
+    const EMPTY: Self = Self {
+        size: 0,
+        used: 0,
+        high_water_mark: 0,
+        live_allocations: 0,
+        total_allocations: 0,
+        failed_allocations: 0,
+        largest_free_block: 0,
+    };
+
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+}
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes used ({} live allocations, high water mark {}), {} free, largest \
+             free block {}, {} allocations, {} failed",
+            self.used,
+            self.size,
+            self.live_allocations,
+            self.high_water_mark,
+            self.free(),
+            self.largest_free_block,
+            self.total_allocations,
+            self.failed_allocations
+        )
+    }
+}
+
+/// TLSF, plus the bookkeeping behind [`HeapStats`].
+struct Heap {
+    inner: embedded_alloc::TlsfHeap,
+    stats: Mutex<Cell<HeapStats>>,
+}
+
+impl Heap {
+    fn update(&self, f: impl FnOnce(&mut HeapStats)) {
+        critical_section::with(|cs| {
+            let cell = self.stats.borrow(cs);
+            let mut stats = cell.get();
+            f(&mut stats);
+            cell.set(stats);
+        })
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        self.update(|stats| {
+            if ptr.is_null() {
+                stats.failed_allocations += 1;
+            } else {
+                stats.used += layout.size();
+                stats.high_water_mark = stats.high_water_mark.max(stats.used);
+                stats.live_allocations += 1;
+                stats.total_allocations += 1;
+            }
+        });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.update(|stats| {
+            stats.used -= layout.size();
+            stats.live_allocations -= 1;
+        });
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        self.update(|stats| {
+            if new.is_null() {
+                stats.failed_allocations += 1;
+            } else {
+                stats.used = stats.used - layout.size() + new_size;
+                stats.high_water_mark = stats.high_water_mark.max(stats.used);
+            }
+        });
+        new
+    }
 }
 
 #[global_allocator]
-static HEAP: embedded_alloc::TlsfHeap = embedded_alloc::TlsfHeap::empty();
-pub fn heap_init() {
-    // XXX: not sure if this is well-defined or not.
-    // let heap_begin = (&raw const __exec_end) as usize;
-    let heap_begin = 0x0200_0000;
-    let heap_end = 0x0800_0000;
-    unsafe {
-        HEAP.init(heap_begin, heap_end - heap_begin);
+static HEAP: Heap = Heap {
+    inner: embedded_alloc::TlsfHeap::empty(),
+    stats: Mutex::new(Cell::new(HeapStats::EMPTY)),
+};
+
+/// Search for the largest allocation the heap can make, by making and freeing them. Bypasses the
+/// statistics so as not to disturb them.
+fn largest_free_block() -> usize {
+    let fits = |size| {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let ptr = unsafe { HEAP.inner.alloc(layout) };
+        if !ptr.is_null() {
+            unsafe { HEAP.inner.dealloc(ptr, layout) };
+        }
+        !ptr.is_null()
+    };
+    let (mut low, mut high) = (0, stats_snapshot().size);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
     }
+    low
+}
+
+fn stats_snapshot() -> HeapStats {
+    critical_section::with(|cs| HEAP.stats.borrow(cs).get())
+}
+
+pub fn stats() -> HeapStats {
+    // in one go, so that nothing allocates between the search and the snapshot
+    critical_section::with(|_| HeapStats {
+        largest_free_block: largest_free_block(),
+        ..stats_snapshot()
+    })
+}
+
+pub fn reset_high_water_mark() {
+    HEAP.update(|stats| stats.high_water_mark = stats.used);
 }
@@ -2,6 +2,8 @@ use crate::define_coprocessor_registers;
 use core::arch::asm;
 
 define_coprocessor_registers! {
+    [safe read] main_id => p15 0 c0 c0 0;
+
     translation_table_base_0 => p15 0 c2 c0 0;
     translation_table_base_1 => p15 0 c2 c0 1;
     translation_table_base_control => p15 0 c2 c0 2;
@@ -14,13 +16,23 @@ define_coprocessor_registers! {
     [safe write] flush_entire_btac => p15 0 c7 c5 6;
 
     [safe write] invalidate_entire_dcache => p15 0 c7 c6 0;
+    [safe write] invalidate_dcache_line_mva => p15 0 c7 c6 1;
 
     [safe write] invalidate_both_caches => p15 0 c7 c7 0;
     [safe write] clean_entire_dcache => p15 0 c7 c10 0;
+    [safe write] clean_dcache_line_mva => p15 0 c7 c10 1;
     [safe write] dsb => p15 0 c7 c10 4;
     [safe write] dmb => p15 0 c7 c10 5;
 
     [safe write] clean_and_invalidate_entire_dcache => p15 0 c7 c14 0;
+    [safe write] clean_and_invalidate_dcache_line_mva => p15 0 c7 c14 1;
+
+    vector_base_address => p15 0 c12 c0 0;
+
+    // ARM1176 only; the ARMv7 cores have `pmccntr` instead
+    [safe read] cycle_counter => p15 0 c15 c12 1;
+
+    [safe read] pmccntr => p15 0 c9 c13 0;
 }
 
 #[inline]
@@ -0,0 +1,143 @@
+use alloc::{string::String, vec::Vec};
+
+use sulfur::stats::CSV_HEADER;
+pub use sulfur::stats::Stats;
+
+use crate::{mailbox::thermal, print, println};
+
+/// How results are written to the console.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    /// One header line, then one comma-separated line per benchmark.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// A named benchmark. Each iteration produces one sample (typically a cycle count); the first
+/// `warmup` iterations are run but discarded.
+pub struct Bench {
+    name: String,
+    warmup: usize,
+    iterations: usize,
+    reject_outliers: bool,
+}
+
+impl Bench {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            warmup: 3,
+            iterations: 25,
+            reject_outliers: true,
+        }
+    }
+    pub fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        assert!(iterations > 0);
+        self.iterations = iterations;
+        self
+    }
+    /// Discard samples outside Tukey's fences (1.5 IQR beyond the quartiles) before computing
+    /// statistics. On by default.
+    pub fn reject_outliers(mut self, reject_outliers: bool) -> Self {
+        self.reject_outliers = reject_outliers;
+        self
+    }
+
+    pub fn run(&self, mut f: impl FnMut() -> u32) -> Stats {
+        for _ in 0..self.warmup {
+            let _ = f();
+        }
+        let mut samples: Vec<u32> = (0..self.iterations).map(|_| f()).collect();
+        Stats::new(&mut samples, self.reject_outliers)
+    }
+}
+
+/// Writes [`Stats`] to the console in a machine-readable format.
+pub struct Reporter {
+    format: Format,
+    wrote_header: bool,
+    record_temperature: bool,
+}
+
+impl Reporter {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            wrote_header: false,
+            record_temperature: false,
+        }
+    }
+
+    /// Add the SoC temperature (in thousandths of a degree Celsius) at the end of each benchmark
+    /// to its results, left empty if the firmware won't say.
+    pub fn record_temperature(mut self, record_temperature: bool) -> Self {
+        self.record_temperature = record_temperature;
+        self
+    }
+
+    pub fn report(&mut self, name: &str, stats: &Stats) {
+        let s = stats;
+        let temperature = self
+            .record_temperature
+            .then(|| thermal::temperature().ok())
+            .flatten();
+        match self.format {
+            Format::Csv => {
+                if !self.wrote_header {
+                    print!("{CSV_HEADER}");
+                    if self.record_temperature {
+                        print!(",temp_mc");
+                    }
+                    println!();
+                    self.wrote_header = true;
+                }
+                print!("{}", s.csv(name));
+                if self.record_temperature {
+                    print!(",");
+                    if let Some(temperature) = temperature {
+                        print!("{temperature}");
+                    }
+                }
+                println!();
+            }
+            Format::JsonLines => {
+                print!(
+                    "{{\"name\":{:?},\"n\":{},\"rejected\":{},\"min\":{},\"p5\":{},\"p25\":{},\
+                     \"median\":{},\"p75\":{},\"p95\":{},\"max\":{},\"mean\":{:.2},\"stddev\":{:.2}",
+                    name,
+                    s.n,
+                    s.rejected,
+                    s.min,
+                    s.p5,
+                    s.p25,
+                    s.median,
+                    s.p75,
+                    s.p95,
+                    s.max,
+                    s.mean,
+                    s.stddev
+                );
+                match (self.record_temperature, temperature) {
+                    (false, _) => {}
+                    (true, Some(temperature)) => print!(",\"temp_mc\":{temperature}"),
+                    (true, None) => print!(",\"temp_mc\":null"),
+                }
+                println!("}}");
+            }
+        }
+        // don't let the UART's TX interrupt land in the middle of the next measurement
+        print::Console::get().drain();
+    }
+
+    /// Run `bench` and report the result.
+    pub fn run(&mut self, bench: Bench, f: impl FnMut() -> u32) -> Stats {
+        let stats = bench.run(f);
+        self.report(&bench.name, &stats);
+        stats
+    }
+}
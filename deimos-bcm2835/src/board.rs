@@ -0,0 +1,136 @@
+use core::cell::SyncUnsafeCell;
+
+use crate::{
+    arch::{cycle_counter, main_id, pmccntr},
+    mailbox::revision::BoardProcessor,
+};
+
+// The SoCs differ (as far as the kernel is concerned) in where the ARM finds the peripherals, and
+// so how much SDRAM sits below them. On the bus the peripherals are always at 0x7e00_0000.
+//
+// The PAC (`bcm2835_lpa`) has the BCM2835's peripheral addresses baked in, so on the other SoCs
+// the MMU maps its 16MiB window at 0x2000_0000 onto the real peripherals as well as mapping them
+// where they are. The SDRAM underneath the window is given up; the firmware keeps the top of
+// SDRAM for itself, and the kernel and heap are near the bottom, so nothing ends up there.
+//
+// The board is picked before the MMU is turned on and before anything touches a peripheral: from
+// the `bcm2836` or `bcm2837` cargo feature if one is enabled, and otherwise from the CPU's main ID
+// register, with the peripheral base the firmware's device tree gives (see `boot`) taking the
+// place of the table's. The table also says which cycle counter the CPU has, for `dma::Timing`.
+
+#[cfg(all(feature = "bcm2836", feature = "bcm2837"))]
+compile_error!("the `bcm2836` and `bcm2837` features are mutually exclusive");
+
+/// Where the PAC expects the peripherals to be.
+pub const PAC_PERIPHERAL_BASE: u32 = 0x2000_0000;
+
+/// Which cycle counter the CPU has: the ARM1176's own, or the ARMv7 performance monitors' (which
+/// the Cortex-A53 keeps in AArch32).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CycleCounter {
+    Arm1176,
+    Pmccntr,
+}
+
+impl CycleCounter {
+    pub fn read(self) -> u32 {
+        match self {
+            Self::Arm1176 => cycle_counter::read_raw(),
+            Self::Pmccntr => pmccntr::read_raw(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Board {
+    pub processor: BoardProcessor,
+    /// ARM physical address of the peripherals.
+    pub peripheral_base: u32,
+    /// End of the SDRAM that the ARM can reach, and so of what the kernel maps.
+    pub sdram_end: u32,
+    pub cycle_counter: CycleCounter,
+}
+
+impl Board {
+    /// Whether the PAC's window needs mapping separately from the peripherals themselves.
+    pub fn has_pac_window(&self) -> bool {
+        self.peripheral_base != PAC_PERIPHERAL_BASE
+    }
+}
+
+const BOARDS: [Board; 3] = [
+    Board {
+        processor: BoardProcessor::Bcm2835,
+        peripheral_base: 0x2000_0000,
+        sdram_end: 0x2000_0000,
+        cycle_counter: CycleCounter::Arm1176,
+    },
+    Board {
+        processor: BoardProcessor::Bcm2836,
+        peripheral_base: 0x3f00_0000,
+        sdram_end: 0x3f00_0000,
+        cycle_counter: CycleCounter::Pmccntr,
+    },
+    Board {
+        processor: BoardProcessor::Bcm2837,
+        peripheral_base: 0x3f00_0000,
+        sdram_end: 0x3f00_0000,
+        cycle_counter: CycleCounter::Pmccntr,
+    },
+];
+
+static CURRENT: SyncUnsafeCell<Board> = SyncUnsafeCell::new(BOARDS[0]);
+
+/// Primary part numbers from the main ID register.
+const PART_ARM1176: u32 = 0xb76;
+const PART_CORTEX_A7: u32 = 0xc07;
+const PART_CORTEX_A53: u32 = 0xd03;
+
+const FORCED: bool = cfg!(any(feature = "bcm2836", feature = "bcm2837"));
+
+fn detect() -> usize {
+    if cfg!(feature = "bcm2836") {
+        return 1;
+    }
+    if cfg!(feature = "bcm2837") {
+        return 2;
+    }
+    match (main_id::read_raw() >> 4) & 0xfff {
+        PART_ARM1176 => 0,
+        PART_CORTEX_A7 => 1,
+        PART_CORTEX_A53 => 2,
+        // nothing to report it on yet, so carry on as a BCM2835 and hope for the best
+        _ => 0,
+    }
+}
+
+/// The board as far as the features and the CPU can tell, without anything from the firmware.
+pub fn probe() -> Board {
+    BOARDS[detect()]
+}
+
+/// Work out which board this is, preferring `peripheral_base` (if the firmware said) to the
+/// table unless a feature picked the board.
+///
+/// # Safety
+///
+/// Must be called once, before the MMU is set up, and before anything touches a peripheral or
+/// calls [`current`].
+pub unsafe fn init(peripheral_base: Option<u32>) -> &'static Board {
+    let mut board = probe();
+    if !FORCED && let Some(base) = peripheral_base {
+        board.peripheral_base = base;
+        board.sdram_end = board.sdram_end.min(base);
+    }
+    unsafe { *CURRENT.get() = board };
+    current()
+}
+
+pub fn current() -> &'static Board {
+    unsafe { &*CURRENT.get() }
+}
+
+/// A pointer to the peripheral register at `offset` from the start of the peripherals.
+pub fn peripheral<T>(offset: usize) -> *mut T {
+    core::ptr::with_exposed_provenance_mut(current().peripheral_base as usize + offset)
+}
@@ -0,0 +1,250 @@
+use core::cell::SyncUnsafeCell;
+
+use crate::{addr::PERIPHERAL_BASE_BUS, board, print::ConsoleUart};
+
+mod atags;
+mod fdt;
+#[cfg(test)]
+mod tests;
+
+pub use fdt::FdtError;
+
+// What the firmware tells the kernel at boot. It starts the kernel with r0 = 0, r1 = the machine
+// type and r2 = the address of either a flattened device tree or an ATAG list; `_start` hands all
+// three to `__kernel_start` untouched. The blob is read once, before the MMU and heap are set up,
+// and everything the kernel wants from it is copied out, so the heap is free to reuse its memory
+// afterwards.
+//
+// Under QEMU with an ELF kernel and no `-dtb`, r2 is whatever QEMU left there, so the address is
+// only trusted if it is aligned, inside SDRAM, and has a device tree's magic or starts with
+// ATAG_CORE. Anything not found falls back to the values the kernel would otherwise assume.
+
+pub const CMDLINE_CAPACITY: usize = 1024;
+// Generous for a Pi DTB (around 30KiB) while keeping a stray r2 from claiming all of SDRAM.
+const MAX_BLOB_SIZE: u32 = 1 << 20;
+const MAX_ATAGS_SIZE: u32 = 16 << 10;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Source {
+    /// Nothing recognizable at r2.
+    None,
+    DeviceTree,
+    Atags,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+impl MemoryRegion {
+    pub fn end(&self) -> u32 {
+        self.base.saturating_add(self.size)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Console {
+    pub uart: ConsoleUart,
+    /// From the options after the path in `stdout-path`, like `serial0:115200n8`.
+    pub baud: Option<u32>,
+}
+
+pub struct BootInfo {
+    pub source: Source,
+    /// The registers the kernel was started with: r0, r1 (the machine type) and r2.
+    pub registers: [u32; 3],
+    /// Where the device tree or ATAGs were.
+    pub blob: Option<MemoryRegion>,
+    /// Why the device tree at r2 couldn't be read, if it couldn't.
+    pub error: Option<FdtError>,
+    /// The first region of memory the ARM is given.
+    pub memory: Option<MemoryRegion>,
+    /// ARM physical address of the peripherals, from the `ranges` of `/soc`.
+    pub peripheral_base: Option<u32>,
+    /// The serial console, from `stdout-path` in `/chosen`.
+    pub console: Option<Console>,
+    /// The command line was longer than [`CMDLINE_CAPACITY`] and has been cut short.
+    pub cmdline_truncated: bool,
+    cmdline: [u8; CMDLINE_CAPACITY],
+    cmdline_len: usize,
+}
+
+impl BootInfo {
+    const EMPTY: Self = Self {
+        source: Source::None,
+        registers: [0; 3],
+        blob: None,
+        error: None,
+        memory: None,
+        peripheral_base: None,
+        console: None,
+        cmdline_truncated: false,
+        cmdline: [0; CMDLINE_CAPACITY],
+        cmdline_len: 0,
+    };
+
+    /// The kernel command line, from `bootargs` in `/chosen` or ATAG_CMDLINE. Empty if there
+    /// wasn't one.
+    pub fn cmdline(&self) -> &str {
+        core::str::from_utf8(&self.cmdline[..self.cmdline_len]).unwrap_or("")
+    }
+
+    fn set_cmdline(&mut self, cmdline: &[u8]) {
+        let len = cmdline.len().min(CMDLINE_CAPACITY);
+        // don't leave half a character behind if it had to be cut
+        let len = match core::str::from_utf8(&cmdline[..len]) {
+            Ok(s) => s.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        self.cmdline[..len].copy_from_slice(&cmdline[..len]);
+        self.cmdline_len = len;
+        self.cmdline_truncated = len < cmdline.len();
+    }
+}
+
+static INFO: SyncUnsafeCell<BootInfo> = SyncUnsafeCell::new(BootInfo::EMPTY);
+
+/// Read whatever the firmware left at `registers[2]`.
+///
+/// # Safety
+///
+/// Must be called once, first thing in `__kernel_start`, while the MMU is still off and before
+/// anything calls [`info`].
+pub unsafe fn init(registers: [u32; 3]) -> &'static BootInfo {
+    let info = unsafe { &mut *INFO.get() };
+    info.registers = registers;
+    let addr = registers[2];
+    let limit = board::probe().sdram_end;
+    if addr != 0 && addr.is_multiple_of(4) && addr < limit {
+        read_blob(info, addr, limit - addr);
+    }
+    info
+}
+
+pub fn info() -> &'static BootInfo {
+    unsafe { &*INFO.get() }
+}
+
+fn read_blob(info: &mut BootInfo, addr: u32, available: u32) {
+    let ptr: *const u8 = core::ptr::with_exposed_provenance(addr as usize);
+    if available >= fdt::HEADER_SIZE as u32
+        && let Some(size) = unsafe { fdt::total_size(ptr) }
+    {
+        if size > available.min(MAX_BLOB_SIZE) {
+            info.error = Some(FdtError::Truncated);
+            return;
+        }
+        info.blob = Some(MemoryRegion { base: addr, size });
+        let blob = unsafe { core::slice::from_raw_parts(ptr, size as usize) };
+        match fdt::Fdt::new(blob) {
+            Ok(fdt) => {
+                info.source = Source::DeviceTree;
+                read_fdt(info, &fdt);
+            }
+            Err(e) => info.error = Some(e),
+        }
+        return;
+    }
+
+    let words = unsafe {
+        core::slice::from_raw_parts(
+            ptr.cast::<u32>(),
+            (available.min(MAX_ATAGS_SIZE) / 4) as usize,
+        )
+    };
+    if !atags::is_atags(words) {
+        return;
+    }
+    info.source = Source::Atags;
+    let mut size = 0;
+    for tag in atags::tags(words) {
+        size += (tag.data.len() as u32 + 2) * 4;
+        if let Some((size, base)) = tag.mem()
+            && info.memory.is_none()
+        {
+            info.memory = Some(MemoryRegion { base, size });
+        }
+        if let Some(cmdline) = tag.cmdline() {
+            info.set_cmdline(cmdline);
+        }
+    }
+    info.blob = Some(MemoryRegion { base: addr, size });
+}
+
+fn read_fdt(info: &mut BootInfo, fdt: &fdt::Fdt) {
+    let root = fdt.root();
+    let (address_cells, size_cells) = root.cell_sizes();
+
+    if let Some(reg) = fdt
+        .find("/memory")
+        .and_then(|memory| memory.property("reg"))
+    {
+        let mut cells = reg.cells();
+        if let (Some(base), Some(size)) = (
+            fdt::read_cells(&mut cells, address_cells),
+            fdt::read_cells(&mut cells, size_cells),
+        ) && let (Ok(base), Ok(size)) = (u32::try_from(base), u32::try_from(size))
+        {
+            info.memory = Some(MemoryRegion { base, size });
+        }
+    }
+
+    // each entry in `ranges` maps a child bus address to one of the parent's
+    if let Some(soc) = fdt.find("/soc")
+        && let Some(ranges) = soc.property("ranges")
+    {
+        let (child_cells, child_size_cells) = soc.cell_sizes();
+        let mut cells = ranges.cells();
+        // entries of no cells at all would never use the property up
+        while child_cells | address_cells | child_size_cells != 0
+            && let (Some(child), Some(parent), Some(_)) = (
+                fdt::read_cells(&mut cells, child_cells),
+                fdt::read_cells(&mut cells, address_cells),
+                fdt::read_cells(&mut cells, child_size_cells),
+            )
+        {
+            if child == PERIPHERAL_BASE_BUS as u64 {
+                info.peripheral_base = u32::try_from(parent).ok();
+                break;
+            }
+        }
+    }
+
+    let chosen = fdt.find("/chosen");
+    if let Some(bootargs) = chosen.and_then(|chosen| chosen.property("bootargs")) {
+        info.set_cmdline(bootargs.as_str().unwrap_or("").as_bytes());
+    }
+    info.console = chosen
+        .and_then(|chosen| {
+            chosen
+                .property("stdout-path")
+                .or_else(|| chosen.property("linux,stdout-path"))
+        })
+        .and_then(|stdout_path| stdout_path.as_str())
+        .and_then(|stdout_path| read_console(fdt, stdout_path));
+}
+
+fn read_console(fdt: &fdt::Fdt, stdout_path: &str) -> Option<Console> {
+    let (path, options) = stdout_path.split_once(':').unwrap_or((stdout_path, ""));
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        fdt.find("/aliases")?.property(path)?.as_str()?
+    };
+    let node = fdt.find(path)?;
+    let uart = if node.is_compatible("arm,pl011") {
+        ConsoleUart::Uart0
+    } else if node.is_compatible("brcm,bcm2835-aux-uart") {
+        ConsoleUart::Uart1
+    } else {
+        return None;
+    };
+    let digits = options
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(options.len());
+    Some(Console {
+        uart,
+        baud: options[..digits].parse().ok(),
+    })
+}
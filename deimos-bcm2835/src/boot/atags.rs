@@ -0,0 +1,54 @@
+// ATAGs, the tagged list the firmware passes instead of a DTB when the device tree is switched off
+// (`device_tree=` in config.txt). Each tag is a header of two words, the size of the tag in words
+// and its type, followed by the tag's data. The list starts with ATAG_CORE and ends with
+// ATAG_NONE.
+
+pub const ATAG_NONE: u32 = 0x0000_0000;
+pub const ATAG_CORE: u32 = 0x5441_0001;
+pub const ATAG_MEM: u32 = 0x5441_0002;
+pub const ATAG_CMDLINE: u32 = 0x5441_0009;
+
+pub struct Tag<'a> {
+    pub tag: u32,
+    /// Everything after the header.
+    pub data: &'a [u32],
+}
+
+/// Whether `words` starts like an ATAG list. ATAG_CORE may come without its data, in which case
+/// it is two words long.
+pub fn is_atags(words: &[u32]) -> bool {
+    matches!(words, [2 | 5, ATAG_CORE, ..])
+}
+
+/// The tags in `words`, up to ATAG_NONE or the end of `words`, whichever comes first.
+pub fn tags(words: &[u32]) -> impl Iterator<Item = Tag<'_>> {
+    let mut rest = words;
+    core::iter::from_fn(move || {
+        let (&[size, tag], after) = rest.split_first_chunk::<2>()?;
+        let data = after.get(..(size as usize).checked_sub(2)?)?;
+        if tag == ATAG_NONE {
+            return None;
+        }
+        rest = &after[data.len()..];
+        Some(Tag { tag, data })
+    })
+}
+
+impl<'a> Tag<'a> {
+    /// For ATAG_MEM: the size and start of a region of memory.
+    pub fn mem(&self) -> Option<(u32, u32)> {
+        match (self.tag, self.data) {
+            (ATAG_MEM, &[size, start, ..]) => Some((size, start)),
+            _ => None,
+        }
+    }
+    /// For ATAG_CMDLINE: the command line, up to its terminating NUL.
+    pub fn cmdline(&self) -> Option<&'a [u8]> {
+        if self.tag != ATAG_CMDLINE {
+            return None;
+        }
+        let bytes: &'a [u8] = bytemuck::cast_slice(self.data);
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Some(&bytes[..len])
+    }
+}
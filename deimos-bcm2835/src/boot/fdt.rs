@@ -0,0 +1,343 @@
+use core::fmt::{Display, Formatter};
+
+// A reader for flattened device trees (the DTB the firmware hands over), following the devicetree
+// specification, chapter 5. Everything in the blob is big-endian. The whole structure block is
+// checked once up front, so walking it afterwards doesn't have to worry about running off the
+// end.
+
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+pub const HEADER_SIZE: usize = 40;
+/// The oldest layout with everything in the header that this reads.
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+
+pub const FDT_BEGIN_NODE: u32 = 0x1;
+pub const FDT_END_NODE: u32 = 0x2;
+pub const FDT_PROP: u32 = 0x3;
+pub const FDT_NOP: u32 = 0x4;
+pub const FDT_END: u32 = 0x9;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FdtError {
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    /// A block or token runs past the end of the blob.
+    Truncated,
+    BadToken {
+        offset: usize,
+        token: u32,
+    },
+    /// Nodes don't nest properly, or the root isn't the only top-level node.
+    Unbalanced,
+    /// A node or property name isn't NUL-terminated UTF-8.
+    BadName {
+        offset: usize,
+    },
+}
+impl Display for FdtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadMagic(magic) => write!(f, "bad magic {magic:08x}"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported version {v}"),
+            Self::Truncated => write!(f, "truncated"),
+            Self::BadToken { offset, token } => write!(f, "bad token {token:#x} at {offset:#x}"),
+            Self::Unbalanced => write!(f, "unbalanced nodes"),
+            Self::BadName { offset } => write!(f, "bad name at {offset:#x}"),
+        }
+    }
+}
+impl core::error::Error for FdtError {}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(word.try_into().unwrap()))
+}
+
+fn cstr(bytes: &[u8], offset: usize) -> Option<&str> {
+    let rest = bytes.get(offset..)?;
+    let len = rest.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&rest[..len]).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// The total size of the blob at `ptr` according to its header, or `None` if there is no device
+/// tree there.
+///
+/// # Safety
+///
+/// `ptr` must be 4-byte aligned and readable for [`HEADER_SIZE`] bytes.
+pub unsafe fn total_size(ptr: *const u8) -> Option<u32> {
+    let header = unsafe { core::slice::from_raw_parts(ptr, HEADER_SIZE) };
+    (be32(header, 0)? == FDT_MAGIC).then(|| be32(header, 4))?
+}
+
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(blob: &'a [u8]) -> Result<Self, FdtError> {
+        let field = |index: usize| be32(blob, index * 4).ok_or(FdtError::Truncated);
+        let magic = field(0)?;
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+        let total_size = field(1)? as usize;
+        let version = field(5)?;
+        if version < LAST_COMPATIBLE_VERSION {
+            return Err(FdtError::UnsupportedVersion(version));
+        }
+        let block = |offset: u32, size: u32| {
+            let start = offset as usize;
+            let end = start
+                .checked_add(size as usize)
+                .ok_or(FdtError::Truncated)?;
+            blob.get(..total_size)
+                .and_then(|blob| blob.get(start..end))
+                .ok_or(FdtError::Truncated)
+        };
+        let fdt = Self {
+            structs: block(field(2)?, field(9)?)?,
+            strings: block(field(3)?, field(8)?)?,
+        };
+        fdt.check()?;
+        Ok(fdt)
+    }
+
+    /// Walk the whole structure block, so that nothing after this has to.
+    fn check(&self) -> Result<(), FdtError> {
+        let mut offset = 0;
+        let mut depth = 0usize;
+        let mut seen_root = false;
+        loop {
+            let token = be32(self.structs, offset).ok_or(FdtError::Truncated)?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    if depth == 0 && seen_root {
+                        return Err(FdtError::Unbalanced);
+                    }
+                    seen_root = true;
+                    depth += 1;
+                    let name =
+                        cstr(self.structs, offset + 4).ok_or(FdtError::BadName { offset })?;
+                    offset = align4(offset + 4 + name.len() + 1);
+                }
+                FDT_END_NODE => {
+                    depth = depth.checked_sub(1).ok_or(FdtError::Unbalanced)?;
+                    offset += 4;
+                }
+                FDT_PROP => {
+                    if depth == 0 {
+                        return Err(FdtError::Unbalanced);
+                    }
+                    let len = be32(self.structs, offset + 4).ok_or(FdtError::Truncated)?;
+                    let name_offset = be32(self.structs, offset + 8).ok_or(FdtError::Truncated)?;
+                    cstr(self.strings, name_offset as usize).ok_or(FdtError::BadName { offset })?;
+                    let end = (offset + 12)
+                        .checked_add(len as usize)
+                        .filter(|&end| end <= self.structs.len())
+                        .ok_or(FdtError::Truncated)?;
+                    offset = align4(end);
+                }
+                FDT_NOP => offset += 4,
+                FDT_END if depth == 0 && seen_root => return Ok(()),
+                FDT_END => return Err(FdtError::Unbalanced),
+                token => return Err(FdtError::BadToken { offset, token }),
+            }
+        }
+    }
+
+    pub fn root(&self) -> Node<'_, 'a> {
+        let mut offset = 0;
+        while be32(self.structs, offset) == Some(FDT_NOP) {
+            offset += 4;
+        }
+        self.node_at(offset)
+    }
+
+    fn node_at(&self, offset: usize) -> Node<'_, 'a> {
+        let name = cstr(self.structs, offset + 4).unwrap_or("");
+        Node {
+            fdt: self,
+            name,
+            body: align4(offset + 4 + name.len() + 1),
+        }
+    }
+
+    /// The node at an absolute `path` like `/soc/serial@7e201000`. Components without a unit
+    /// address match any unit address, the way `/memory` finds `memory@0`.
+    pub fn find(&self, path: &str) -> Option<Node<'_, 'a>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self.root(), |node, component| node.child(component))
+    }
+
+    /// Decode the token at `offset`, returning it and the offset of the next one.
+    fn token(&self, offset: usize) -> (Token<'a>, usize) {
+        let structs = self.structs;
+        match be32(structs, offset).unwrap_or(FDT_END) {
+            FDT_BEGIN_NODE => {
+                let name_len = cstr(structs, offset + 4).map_or(0, str::len);
+                (Token::BeginNode, align4(offset + 4 + name_len + 1))
+            }
+            FDT_PROP => {
+                let len = be32(structs, offset + 4).unwrap_or(0) as usize;
+                let name_offset = be32(structs, offset + 8).unwrap_or(0) as usize;
+                let property = Property {
+                    name: cstr(self.strings, name_offset).unwrap_or(""),
+                    value: &structs[offset + 12..offset + 12 + len],
+                };
+                (Token::Prop(property), align4(offset + 12 + len))
+            }
+            FDT_NOP => (Token::Nop, offset + 4),
+            // FDT_END_NODE, and FDT_END (which `check` only allows after the root)
+            _ => (Token::EndNode, offset + 4),
+        }
+    }
+}
+
+enum Token<'a> {
+    BeginNode,
+    EndNode,
+    Prop(Property<'a>),
+    Nop,
+}
+
+#[derive(Copy, Clone)]
+pub struct Node<'f, 'a> {
+    fdt: &'f Fdt<'a>,
+    /// Includes the unit address, if any. Empty for the root.
+    pub name: &'a str,
+    // Offset of the first token after FDT_BEGIN_NODE and the name.
+    body: usize,
+}
+
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// The value as a single string, without the terminating NUL.
+    pub fn as_str(self) -> Option<&'a str> {
+        cstr(self.value, 0)
+    }
+    /// The value as a list of strings, like `compatible`.
+    pub fn strings(self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+    /// The value as a list of big-endian cells.
+    pub fn cells(self) -> impl Iterator<Item = u32> {
+        self.value
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|&cell| u32::from_be_bytes(cell))
+    }
+    pub fn as_u32(self) -> Option<u32> {
+        be32(self.value, 0)
+    }
+}
+
+impl<'f, 'a> Node<'f, 'a> {
+    pub fn properties(self) -> impl Iterator<Item = Property<'a>> {
+        let fdt = self.fdt;
+        let mut offset = self.body;
+        core::iter::from_fn(move || {
+            loop {
+                let (token, next) = fdt.token(offset);
+                match token {
+                    Token::Prop(property) => {
+                        offset = next;
+                        return Some(property);
+                    }
+                    Token::Nop => offset = next,
+                    Token::BeginNode | Token::EndNode => return None,
+                }
+            }
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    pub fn children(self) -> impl Iterator<Item = Node<'f, 'a>> {
+        let fdt = self.fdt;
+        let mut offset = self.body;
+        core::iter::from_fn(move || {
+            loop {
+                let (token, next) = fdt.token(offset);
+                match token {
+                    Token::Prop(_) | Token::Nop => offset = next,
+                    Token::BeginNode => {
+                        let child = fdt.node_at(offset);
+                        offset = child.end();
+                        return Some(child);
+                    }
+                    Token::EndNode => return None,
+                }
+            }
+        })
+    }
+
+    /// The child called `name`, where a `name` without a unit address matches any unit address.
+    pub fn child(&self, name: &str) -> Option<Node<'f, 'a>> {
+        self.children().find(|child| {
+            child.name == name
+                || (!name.contains('@')
+                    && child
+                        .name
+                        .split_once('@')
+                        .is_some_and(|(base, _)| base == name))
+        })
+    }
+
+    /// Offset just past this node's FDT_END_NODE.
+    fn end(&self) -> usize {
+        let mut offset = self.body;
+        let mut depth = 1;
+        while depth > 0 {
+            let (token, next) = self.fdt.token(offset);
+            match token {
+                Token::BeginNode => depth += 1,
+                Token::EndNode => depth -= 1,
+                Token::Prop(_) | Token::Nop => {}
+            }
+            offset = next;
+        }
+        offset
+    }
+
+    /// Whether `compatible` lists `compatible`.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|p| p.strings().any(|s| s == compatible))
+    }
+
+    /// `#address-cells` and `#size-cells` for this node's children, with the spec's defaults.
+    pub fn cell_sizes(&self) -> (usize, usize) {
+        let cells = |name, default| {
+            self.property(name)
+                .and_then(|p| p.as_u32())
+                .map_or(default, |n| n as usize)
+        };
+        (cells("#address-cells", 2), cells("#size-cells", 1))
+    }
+}
+
+/// Read an address or size made of `count` cells.
+pub fn read_cells(cells: &mut impl Iterator<Item = u32>, count: usize) -> Option<u64> {
+    let mut value = 0u64;
+    for _ in 0..count {
+        value = (value << 32) | cells.next()? as u64;
+    }
+    Some(value)
+}
@@ -0,0 +1,223 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    addr::PERIPHERAL_BASE_BUS,
+    boot::{
+        BootInfo, MemoryRegion,
+        atags::{self, ATAG_CMDLINE, ATAG_CORE, ATAG_MEM, ATAG_NONE},
+        fdt::{self, FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_PROP, Fdt, FdtError},
+        read_fdt,
+    },
+};
+
+/// Builds a device tree blob: the header, an empty memory reservation block, the structure block
+/// and then the strings.
+#[derive(Default)]
+struct Blob {
+    structs: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+impl Blob {
+    fn token(&mut self, token: u32) -> &mut Self {
+        self.structs.extend_from_slice(&token.to_be_bytes());
+        self
+    }
+    fn begin(&mut self, name: &str) -> &mut Self {
+        self.token(FDT_BEGIN_NODE);
+        self.structs.extend_from_slice(name.as_bytes());
+        self.structs.push(0);
+        self.pad()
+    }
+    fn end(&mut self) -> &mut Self {
+        self.token(FDT_END_NODE)
+    }
+    fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        let name_offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.token(FDT_PROP)
+            .token(value.len() as u32)
+            .token(name_offset);
+        self.structs.extend_from_slice(value);
+        self.pad()
+    }
+    fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.prop(name, &value)
+    }
+    fn pad(&mut self) -> &mut Self {
+        self.structs
+            .resize(self.structs.len().next_multiple_of(4), 0);
+        self
+    }
+    fn finish(&self) -> Vec<u8> {
+        let structs_offset = fdt::HEADER_SIZE + 16;
+        let strings_offset = structs_offset + self.structs.len();
+        let total_size = strings_offset + self.strings.len();
+        let header = [
+            fdt::FDT_MAGIC,
+            total_size as u32,
+            structs_offset as u32,
+            strings_offset as u32,
+            fdt::HEADER_SIZE as u32,
+            17,
+            16,
+            0,
+            self.strings.len() as u32,
+            self.structs.len() as u32,
+        ];
+        let mut blob: Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+        blob.extend_from_slice(&[0; 16]);
+        blob.extend_from_slice(&self.structs);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+/// A tree like the firmware's, cut down to what `read_fdt` looks at.
+fn minimal_tree() -> Vec<u8> {
+    Blob::default()
+        .begin("")
+        .cells("#address-cells", &[1])
+        .cells("#size-cells", &[1])
+        .begin("memory@0")
+        .cells("reg", &[0, 0x1000_0000])
+        .end()
+        .begin("chosen")
+        .prop("bootargs", b"deimos.reboot=0\0")
+        .end()
+        .begin("soc")
+        .cells("#address-cells", &[1])
+        .cells("#size-cells", &[1])
+        .cells("ranges", &[PERIPHERAL_BASE_BUS, 0x3f00_0000, 0x100_0000])
+        .end()
+        .end()
+        .token(FDT_END)
+        .finish()
+}
+
+#[test_case]
+fn reads_minimal_tree() {
+    let blob = minimal_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert!(fdt.find("/memory").is_some());
+    assert!(fdt.find("/nonexistent").is_none());
+    let mut info = BootInfo::EMPTY;
+    read_fdt(&mut info, &fdt);
+    assert_eq!(
+        info.memory,
+        Some(MemoryRegion {
+            base: 0,
+            size: 0x1000_0000
+        })
+    );
+    assert_eq!(info.peripheral_base, Some(0x3f00_0000));
+    assert_eq!(info.cmdline(), "deimos.reboot=0");
+}
+
+#[test_case]
+fn stops_on_ranges_without_cells() {
+    let blob = Blob::default()
+        .begin("")
+        .cells("#address-cells", &[0])
+        .begin("soc")
+        .cells("#address-cells", &[0])
+        .cells("#size-cells", &[0])
+        .cells("ranges", &[PERIPHERAL_BASE_BUS])
+        .end()
+        .end()
+        .token(FDT_END)
+        .finish();
+    let fdt = Fdt::new(&blob).unwrap();
+    let mut info = BootInfo::EMPTY;
+    read_fdt(&mut info, &fdt);
+    assert_eq!(info.peripheral_base, None);
+}
+
+#[test_case]
+fn rejects_bad_magic() {
+    let mut blob = minimal_tree();
+    blob[0] = 0;
+    assert!(matches!(Fdt::new(&blob), Err(FdtError::BadMagic(_))));
+}
+
+#[test_case]
+fn rejects_truncated_tree() {
+    let blob = minimal_tree();
+    assert!(matches!(
+        Fdt::new(&blob[..blob.len() - 4]),
+        Err(FdtError::Truncated)
+    ));
+    assert!(matches!(Fdt::new(&blob[..8]), Err(FdtError::Truncated)));
+}
+
+#[test_case]
+fn rejects_unbalanced_tree() {
+    let unclosed = Blob::default().begin("").token(FDT_END).finish();
+    assert!(matches!(Fdt::new(&unclosed), Err(FdtError::Unbalanced)));
+    let two_roots = Blob::default()
+        .begin("")
+        .end()
+        .begin("")
+        .end()
+        .token(FDT_END)
+        .finish();
+    assert!(matches!(Fdt::new(&two_roots), Err(FdtError::Unbalanced)));
+}
+
+#[test_case]
+fn rejects_bad_token() {
+    let blob = Blob::default()
+        .begin("")
+        .token(0x7)
+        .end()
+        .token(FDT_END)
+        .finish();
+    assert!(matches!(
+        Fdt::new(&blob),
+        Err(FdtError::BadToken {
+            offset: 8,
+            token: 0x7
+        })
+    ));
+}
+
+#[test_case]
+fn reads_minimal_atags() {
+    let mut words = vec![5, ATAG_CORE, 0, 0, 0];
+    words.extend([4, ATAG_MEM, 0x1000_0000, 0]);
+    words.extend([
+        4,
+        ATAG_CMDLINE,
+        u32::from_le_bytes(*b"a=1 "),
+        u32::from_le_bytes(*b"b\0\0\0"),
+    ]);
+    words.extend([0, ATAG_NONE]);
+    assert!(atags::is_atags(&words));
+    let tags: Vec<_> = atags::tags(&words).collect();
+    assert_eq!(tags.len(), 3);
+    assert_eq!(tags[1].mem(), Some((0x1000_0000, 0)));
+    assert_eq!(tags[2].cmdline(), Some(&b"a=1 b"[..]));
+}
+
+#[test_case]
+fn stops_at_truncated_atag() {
+    // the second tag claims more words than there are
+    let words = [2, ATAG_CORE, 6, ATAG_MEM, 0x1000_0000, 0];
+    assert!(atags::is_atags(&words));
+    assert_eq!(atags::tags(&words).count(), 1);
+}
+
+#[test_case]
+fn stops_at_atag_too_short_for_its_header() {
+    let words = [2, ATAG_CORE, 1, ATAG_MEM, 0, 0];
+    assert_eq!(atags::tags(&words).count(), 1);
+}
+
+#[test_case]
+fn rejects_list_not_starting_with_core() {
+    assert!(!atags::is_atags(&[4, ATAG_MEM, 0x1000_0000, 0]));
+    assert!(!atags::is_atags(&[3, ATAG_CORE, 0]));
+    assert!(!atags::is_atags(&[]));
+}
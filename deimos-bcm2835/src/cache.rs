@@ -0,0 +1,62 @@
+use crate::arch::{
+    clean_and_invalidate_dcache_line_mva, clean_dcache_line_mva, dsb, invalidate_dcache_line_mva,
+};
+
+/// Line size of the ARM1176 L1 data cache.
+pub const DCACHE_LINE_SIZE: usize = 32;
+const LINE_MASK: usize = DCACHE_LINE_SIZE - 1;
+
+fn for_each_line(ptr: *const u8, len: usize, mut f: impl FnMut(usize)) {
+    if len == 0 {
+        return;
+    }
+    let end = ptr.addr() + len;
+    let mut line = ptr.addr() & !LINE_MASK;
+    while line < end {
+        f(line);
+        line += DCACHE_LINE_SIZE;
+    }
+}
+
+/// Write back any dirty lines covering `ptr..ptr+len`, so that the memory system (and therefore
+/// the DMA engine and the VC) sees the CPU's writes.
+pub fn clean_range(ptr: *const u8, len: usize) {
+    dsb();
+    for_each_line(ptr, len, |line| {
+        clean_dcache_line_mva::write_raw(line as u32)
+    });
+    dsb();
+}
+
+/// Write back and then discard any lines covering `ptr..ptr+len`.
+pub fn clean_and_invalidate_range(ptr: *const u8, len: usize) {
+    dsb();
+    for_each_line(ptr, len, |line| {
+        clean_and_invalidate_dcache_line_mva::write_raw(line as u32)
+    });
+    dsb();
+}
+
+/// Discard any lines covering `ptr..ptr+len`, so that subsequent reads observe writes made by the
+/// DMA engine or the VC. Lines that only partially overlap the range are cleaned first, so data
+/// sharing a line with either end of the range is not lost.
+///
+/// # Safety
+///
+/// Any CPU writes to `ptr..ptr+len` that have not been cleaned are discarded.
+pub unsafe fn invalidate_range(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let begin = ptr.addr();
+    let end = begin + len;
+    dsb();
+    for_each_line(ptr, len, |line| {
+        if line < begin || line + DCACHE_LINE_SIZE > end {
+            clean_and_invalidate_dcache_line_mva::write_raw(line as u32)
+        } else {
+            invalidate_dcache_line_mva::write_raw(line as u32)
+        }
+    });
+    dsb();
+}
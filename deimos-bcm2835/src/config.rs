@@ -0,0 +1,307 @@
+use core::cell::{Cell, SyncUnsafeCell};
+use core::fmt::{Display, Formatter};
+
+use critical_section::Mutex;
+
+use crate::{
+    boot::{self, Console},
+    mailbox::{
+        MailboxError,
+        command_line::{self, CAPACITY},
+    },
+    mmu_support::MmuConfig,
+    print::ConsoleUart,
+    println,
+};
+
+#[cfg(test)]
+mod tests;
+
+// Options from the kernel command line, which comes from `/chosen/bootargs` or ATAG_CMDLINE if the
+// firmware passed one, and otherwise from the mailbox. It is the usual space-separated list;
+// anything that isn't one of these is the firmware's or Linux's and is left alone:
+//
+//   deimos.console=uart0|uart1[,<baud>]  the console UART (uart0 is the PL011, and not with remote)
+//   console=ttyAMA0|ttyS0[,<baud>]       the same, as Linux spells it; other consoles are ignored
+//   deimos.suites=<suite>,...            which of `Suites` to run, or `all` or `none`
+//   deimos.dma_channel=<n>               the channel to benchmark on, instead of the first free one
+//   deimos.cache=<feature>,...           which of dcache, icache and brpdx to enable, or `none`
+//   deimos.reboot=0|1                    whether `exit` resets the board or just stops
+//   deimos.dma_pool=<size>[K|M]          how much memory to set aside for `dma::pool`
+//
+// Later options win over earlier ones.
+
+/// Which parts of a run to do. The benchmarks are the ones in `dma::bench`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Suites(u32);
+impl Suites {
+    pub const NONE: Self = Self(0);
+    /// The `#[test_case]`s, in test builds.
+    pub const TESTS: Self = Self(1 << 0);
+    pub const LENGTH: Self = Self(1 << 1);
+    pub const UNALIGNED: Self = Self(1 << 2);
+    pub const CACHING: Self = Self(1 << 3);
+    pub const VC_MEMORY: Self = Self(1 << 4);
+    pub const TRACE: Self = Self(1 << 5);
+    pub const PROFILE: Self = Self(1 << 6);
+    pub const ALL: Self = Self((1 << 7) - 1);
+
+    const NAMES: [(&str, Self); 7] = [
+        ("tests", Self::TESTS),
+        ("length", Self::LENGTH),
+        ("unaligned", Self::UNALIGNED),
+        ("caching", Self::CACHING),
+        ("vc_memory", Self::VC_MEMORY),
+        ("trace", Self::TRACE),
+        ("profile", Self::PROFILE),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+impl Display for Suites {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut names = Self::NAMES
+            .iter()
+            .filter(|(_, suite)| self.contains(*suite))
+            .map(|(name, _)| name);
+        match names.next() {
+            None => write!(f, "none"),
+            Some(first) => {
+                write!(f, "{first}")?;
+                names.try_for_each(|name| write!(f, ",{name}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// Beats the console from the device tree, and the `uart0-console` feature.
+    pub console: Option<Console>,
+    pub suites: Suites,
+    pub dma_channel: Option<usize>,
+    pub cache: MmuConfig,
+    /// Reset the board at the end of a run, rather than stopping.
+    pub reboot: bool,
+    /// Bytes set aside for `dma::pool`, out of what would otherwise be heap.
+    pub dma_pool_size: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConfigError<'a> {
+    UnknownOption(&'a str),
+    BadValue { option: &'a str, value: &'a str },
+}
+impl Display for ConfigError<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnknownOption(option) => write!(f, "unknown option {option}"),
+            Self::BadValue { option, value } => write!(f, "bad value {value:?} for {option}"),
+        }
+    }
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        console: None,
+        suites: Suites::ALL,
+        dma_channel: None,
+        cache: MmuConfig {
+            dcache: Some(false),
+            icache: Some(false),
+            brpdx: Some(false),
+        },
+        reboot: true,
+        dma_pool_size: 16 << 20,
+    };
+
+    /// The options in `cmdline` on top of the defaults, calling `on_error` for each one that is
+    /// ours but can't be used.
+    pub fn parse<'a>(cmdline: &'a str, mut on_error: impl FnMut(ConfigError<'a>)) -> Self {
+        let mut config = Self::DEFAULT;
+        for word in cmdline.split_ascii_whitespace() {
+            let (option, value) = word.split_once('=').unwrap_or((word, ""));
+            if option != "console" && !option.starts_with("deimos.") {
+                continue;
+            }
+            if let Err(e) = config.apply(option, value) {
+                on_error(e);
+            }
+        }
+        config
+    }
+
+    fn apply<'a>(&mut self, option: &'a str, value: &'a str) -> Result<(), ConfigError<'a>> {
+        let bad_value = ConfigError::BadValue { option, value };
+        match option {
+            "deimos.console" | "console" => {
+                let (device, options) = value.split_once(',').unwrap_or((value, ""));
+                let uart = match (option, device) {
+                    ("deimos.console", "uart0") | ("console", "ttyAMA0") => ConsoleUart::Uart0,
+                    ("deimos.console", "uart1") | ("console", "ttyS0") => ConsoleUart::Uart1,
+                    // Linux has plenty of consoles that aren't UARTs
+                    ("console", _) => return Ok(()),
+                    _ => return Err(bad_value),
+                };
+                // the PL011 would take GPIO 14/15 away from the remote link
+                if cfg!(feature = "remote") && uart == ConsoleUart::Uart0 {
+                    return Err(bad_value);
+                }
+                let digits = options
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(options.len());
+                let baud = match &options[..digits] {
+                    "" => None,
+                    digits => Some(digits.parse().ok().filter(|&b| b > 0).ok_or(bad_value)?),
+                };
+                self.console = Some(Console { uart, baud });
+            }
+            "deimos.suites" => {
+                let mut suites = Suites::NONE;
+                for name in value.split(',') {
+                    suites.0 |= match name {
+                        "all" => Suites::ALL,
+                        "none" => Suites::NONE,
+                        name => {
+                            Suites::NAMES
+                                .iter()
+                                .find(|(known, _)| *known == name)
+                                .ok_or(bad_value)?
+                                .1
+                        }
+                    }
+                    .0;
+                }
+                self.suites = suites;
+            }
+            "deimos.dma_channel" => {
+                self.dma_channel = Some(value.parse().ok().filter(|&c| c < 16).ok_or(bad_value)?);
+            }
+            "deimos.cache" => {
+                let mut cache = MmuConfig {
+                    dcache: Some(false),
+                    icache: Some(false),
+                    brpdx: Some(false),
+                };
+                for feature in value.split(',') {
+                    match feature {
+                        "dcache" => cache.dcache = Some(true),
+                        "icache" => cache.icache = Some(true),
+                        "brpdx" => cache.brpdx = Some(true),
+                        "none" => {}
+                        _ => return Err(bad_value),
+                    }
+                }
+                self.cache = cache;
+            }
+            "deimos.dma_pool" => {
+                let (digits, shift) = match value.as_bytes().last() {
+                    Some(b'K') => (&value[..value.len() - 1], 10),
+                    Some(b'M') => (&value[..value.len() - 1], 20),
+                    _ => (value, 0),
+                };
+                self.dma_pool_size = digits
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|size| size.checked_mul(1 << shift))
+                    .filter(|&size| size > 0)
+                    .ok_or(bad_value)?;
+            }
+            "deimos.reboot" => {
+                self.reboot = match value {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(bad_value),
+                }
+            }
+            _ => return Err(ConfigError::UnknownOption(option)),
+        }
+        Ok(())
+    }
+}
+
+static CONFIG: Mutex<Cell<Config>> = Mutex::new(Cell::new(Config::DEFAULT));
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CmdlineSource {
+    /// The device tree or ATAGs.
+    Boot,
+    Mailbox,
+    /// There wasn't one, so everything is the default.
+    None,
+}
+
+struct Cmdline {
+    source: CmdlineSource,
+    text: &'static str,
+    mailbox_error: Option<MailboxError>,
+}
+
+// Written once by `init`, before anything reads it.
+static CMDLINE: SyncUnsafeCell<Cmdline> = SyncUnsafeCell::new(Cmdline {
+    source: CmdlineSource::None,
+    text: "",
+    mailbox_error: None,
+});
+static FETCHED: SyncUnsafeCell<[u8; CAPACITY]> = SyncUnsafeCell::new([0; CAPACITY]);
+
+/// Find the command line and set the configuration from it. Problems with it are kept for
+/// [`report`], since this has to run before the console is up.
+///
+/// # Safety
+///
+/// Must be called once, after the MMU is set up and before anything calls [`cmdline`] or
+/// [`report`].
+pub unsafe fn init() -> Config {
+    let cmdline = unsafe { &mut *CMDLINE.get() };
+    let from_boot = boot::info().cmdline();
+    if !from_boot.is_empty() {
+        cmdline.source = CmdlineSource::Boot;
+        cmdline.text = from_boot;
+    } else {
+        match command_line::query(unsafe { &mut *FETCHED.get() }) {
+            Ok(text) if !text.is_empty() => {
+                cmdline.source = CmdlineSource::Mailbox;
+                cmdline.text = text;
+            }
+            Ok(_) => {}
+            Err(e) => cmdline.mailbox_error = Some(e),
+        }
+    }
+    let config = Config::parse(cmdline.text, |_| {});
+    critical_section::with(|cs| CONFIG.borrow(cs).set(config));
+    config
+}
+
+/// The configuration [`init`] came up with, or the defaults before then.
+pub fn get() -> Config {
+    critical_section::with(|cs| CONFIG.borrow(cs).get())
+}
+
+pub fn cmdline() -> (CmdlineSource, &'static str) {
+    let cmdline = unsafe { &*CMDLINE.get() };
+    (cmdline.source, cmdline.text)
+}
+
+/// Print where the command line came from, and a warning for each option in it that was ignored.
+pub fn report() {
+    let cmdline = unsafe { &*CMDLINE.get() };
+    match cmdline.source {
+        CmdlineSource::Boot | CmdlineSource::Mailbox => {
+            println!("command line ({:?}): {}", cmdline.source, cmdline.text);
+            if cmdline.source == CmdlineSource::Boot && boot::info().cmdline_truncated {
+                println!(
+                    "warning: command line cut short at {} bytes",
+                    cmdline.text.len()
+                );
+            }
+        }
+        CmdlineSource::None => match cmdline.mailbox_error {
+            Some(e) => println!("no command line: {e}"),
+            None => println!("no command line"),
+        },
+    }
+    Config::parse(cmdline.text, |e| println!("warning: {e}"));
+}
@@ -0,0 +1,161 @@
+use alloc::{string::ToString as _, vec, vec::Vec};
+
+use crate::{
+    boot::Console,
+    config::{Config, ConfigError, Suites},
+    mmu_support::MmuConfig,
+    print::ConsoleUart,
+};
+
+/// The config from `cmdline`, and the errors that came up on the way.
+fn parse(cmdline: &str) -> (Config, Vec<ConfigError<'_>>) {
+    let mut errors = Vec::new();
+    let config = Config::parse(cmdline, |e| errors.push(e));
+    (config, errors)
+}
+
+fn bad_value<'a>(option: &'a str, value: &'a str) -> Vec<ConfigError<'a>> {
+    vec![ConfigError::BadValue { option, value }]
+}
+
+#[test_case]
+fn defaults_without_options() {
+    assert_eq!(parse(""), (Config::DEFAULT, vec![]));
+    assert_eq!(
+        parse("console=tty1 root=/dev/mmcblk0p2 quiet"),
+        (Config::DEFAULT, vec![])
+    );
+}
+
+#[test_case]
+fn parses_console() {
+    let uart1 = |baud| {
+        Some(Console {
+            uart: ConsoleUart::Uart1,
+            baud,
+        })
+    };
+    assert_eq!(parse("deimos.console=uart1").0.console, uart1(None));
+    assert_eq!(
+        parse("deimos.console=uart1,115200").0.console,
+        uart1(Some(115200))
+    );
+    assert_eq!(parse("console=ttyS0,9600n8").0.console, uart1(Some(9600)));
+    if !cfg!(feature = "remote") {
+        assert_eq!(
+            parse("console=ttyAMA0").0.console,
+            Some(Console {
+                uart: ConsoleUart::Uart0,
+                baud: None,
+            })
+        );
+    }
+}
+
+#[test_case]
+fn rejects_bad_console() {
+    let (config, errors) = parse("deimos.console=uart2");
+    assert_eq!(config.console, None);
+    assert_eq!(errors, bad_value("deimos.console", "uart2"));
+    let (config, errors) = parse("deimos.console=uart1,0");
+    assert_eq!(config.console, None);
+    assert_eq!(errors, bad_value("deimos.console", "uart1,0"));
+    assert_eq!(
+        parse("deimos.console=uart1,99999999999").1,
+        bad_value("deimos.console", "uart1,99999999999")
+    );
+    // the PL011 would take the remote link's pins
+    assert_eq!(
+        parse("deimos.console=uart0").1.is_empty(),
+        !cfg!(feature = "remote")
+    );
+}
+
+#[test_case]
+fn parses_suites() {
+    assert_eq!(parse("deimos.suites=none").0.suites, Suites::NONE);
+    assert_eq!(parse("deimos.suites=all").0.suites, Suites::ALL);
+    let suites = parse("deimos.suites=tests,trace").0.suites;
+    assert_eq!(suites, Suites(Suites::TESTS.0 | Suites::TRACE.0));
+    assert_eq!(suites.to_string(), "tests,trace");
+}
+
+#[test_case]
+fn keeps_suites_on_bad_name() {
+    let (config, errors) = parse("deimos.suites=tests deimos.suites=length,bogus");
+    assert_eq!(config.suites, Suites::TESTS);
+    assert_eq!(errors, bad_value("deimos.suites", "length,bogus"));
+}
+
+#[test_case]
+fn parses_dma_channel() {
+    assert_eq!(parse("deimos.dma_channel=5").0.dma_channel, Some(5));
+    for cmdline in [
+        "deimos.dma_channel=16",
+        "deimos.dma_channel=-1",
+        "deimos.dma_channel",
+    ] {
+        let (config, errors) = parse(cmdline);
+        assert_eq!(config.dma_channel, None);
+        assert_eq!(errors.len(), 1);
+    }
+}
+
+#[test_case]
+fn parses_cache() {
+    assert_eq!(
+        parse("deimos.cache=dcache,brpdx").0.cache,
+        MmuConfig {
+            dcache: Some(true),
+            icache: Some(false),
+            brpdx: Some(true),
+        }
+    );
+    assert_eq!(
+        parse("deimos.cache=icache deimos.cache=none").0.cache,
+        Config::DEFAULT.cache
+    );
+    let (config, errors) = parse("deimos.cache=icache,l2");
+    assert_eq!(config.cache, Config::DEFAULT.cache);
+    assert_eq!(errors, bad_value("deimos.cache", "icache,l2"));
+}
+
+#[test_case]
+fn parses_reboot() {
+    assert!(!parse("deimos.reboot=0").0.reboot);
+    assert!(parse("deimos.reboot=0 deimos.reboot=1").0.reboot);
+    assert_eq!(
+        parse("deimos.reboot=yes").1,
+        bad_value("deimos.reboot", "yes")
+    );
+}
+
+#[test_case]
+fn parses_dma_pool() {
+    assert_eq!(parse("deimos.dma_pool=4096").0.dma_pool_size, 4096);
+    assert_eq!(parse("deimos.dma_pool=64K").0.dma_pool_size, 64 << 10);
+    assert_eq!(parse("deimos.dma_pool=2M").0.dma_pool_size, 2 << 20);
+    for cmdline in [
+        "deimos.dma_pool=0",
+        "deimos.dma_pool=M",
+        "deimos.dma_pool=4G",
+        "deimos.dma_pool=99999999999999999999M",
+    ] {
+        let (config, errors) = parse(cmdline);
+        assert_eq!(config.dma_pool_size, Config::DEFAULT.dma_pool_size);
+        assert_eq!(errors.len(), 1);
+    }
+}
+
+#[test_case]
+fn reports_unknown_options() {
+    let (config, errors) = parse("deimos.bogus=1 deimos.reboot=0 deimos.verbose");
+    assert!(!config.reboot);
+    assert_eq!(
+        errors,
+        vec![
+            ConfigError::UnknownOption("deimos.bogus"),
+            ConfigError::UnknownOption("deimos.verbose"),
+        ]
+    );
+}
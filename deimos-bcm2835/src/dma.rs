@@ -1,9 +1,25 @@
-use core::{alloc::Layout, arch::asm, ptr::NonNull};
+use core::{
+    alloc::Layout,
+    arch::asm,
+    fmt::{Display, Formatter},
+    ptr::NonNull,
+};
 
 use crate::{
+    addr::{BusAddr, BusAlias, PhysAddr},
     arch::dsb,
-    dma::registers::{CS, TI},
-    mailbox, println,
+    board::{self, CycleCounter},
+    cache,
+    config::Config,
+    dma::{
+        arena::{Arena, OverBudget},
+        registers::{CS, TI},
+    },
+    mailbox::{
+        self, MailboxError,
+        memory::{MEM_FLAG_DIRECT, MEM_FLAG_ZERO, VcBuffer},
+    },
+    println,
 };
 use alloc::{
     string::{String, ToString as _},
@@ -11,36 +27,73 @@ use alloc::{
 };
 use bcm2835_lpa::Peripherals;
 use hashbrown::HashMap;
-use sulfur::dilf::{DataRef, Hole, Loader, Op, OpField, OpFieldId, OpFieldRef};
+use sulfur::dilf::{
+    CHUNK_FLAGS_KNOWN, CHUNK_FLAGS_L2_CACHED, CHUNK_FLAGS_VC_MEMORY, DataRef, Dilf, Hole, Loader,
+    OP_FLAGS_DST_DREQ, OP_FLAGS_DST_NO_INC, OP_FLAGS_SRC_DREQ, OP_FLAGS_SRC_NO_INC,
+    OP_FLAGS_TDMODE, Op, OpField, OpFieldId, OpFieldRef,
+};
 use tock_registers::LocalRegisterCopy;
 
+mod arena;
+mod bench;
+mod build;
+pub mod pool;
+mod profile;
 mod raw;
 mod registers;
+#[cfg(test)]
 mod tests;
+mod trace;
 
-pub fn run_all(_peri: &Peripherals) {
-    unsafe {
-        asm!(
-            r#"
-                mrc p15, 0, {t0}, c15, c12, 0
-                orr {t0}, {t0}, #1
-                mcr p15, 0, {t0}, c15, c12, 0
-                mcr p15, 0, {z}, c15, c12, 1
-            "#,
-            t0 = out(reg) _,
-            z = in(reg) 0,
-        )
+/// Start the board's cycle counter, which [`Timing`] is measured with, and reset it to zero.
+pub fn enable_cycle_counter() {
+    match board::current().cycle_counter {
+        CycleCounter::Arm1176 => unsafe {
+            asm!(
+                r#"
+                    mrc p15, 0, {t0}, c15, c12, 0
+                    orr {t0}, {t0}, #1
+                    mcr p15, 0, {t0}, c15, c12, 0
+                    mcr p15, 0, {z}, c15, c12, 1
+                "#,
+                t0 = out(reg) _,
+                z = in(reg) 0,
+            )
+        },
+        CycleCounter::Pmccntr => unsafe {
+            asm!(
+                r#"
+                    mrc p15, 0, {t0}, c9, c12, 0 // PMCR
+                    orr {t0}, {t0}, #5 // enable, reset the cycle counter
+                    bic {t0}, {t0}, #8 // count every cycle, not every 64th
+                    mcr p15, 0, {t0}, c9, c12, 0
+                    mov {t0}, #0x80000000
+                    mcr p15, 0, {t0}, c9, c12, 1 // PMCNTENSET: the cycle counter
+                "#,
+                t0 = out(reg) _,
+            )
+        },
     }
+}
 
-    let channels = mailbox::dma_channels::query();
+pub fn run_all(_peri: &Peripherals, config: &Config) {
+    enable_cycle_counter();
+
+    let channels = mailbox::dma_channels::query().expect("mailbox should answer");
     println!("Available DMA channels: {}", channels);
-    let channel = channels
-        .iter()
-        .find(|c| *c > 3)
+    let chosen = config.dma_channel.filter(|&c| {
+        let available = channels.iter().any(|free| free == c);
+        if !available {
+            println!("DMA channel {c} from the command line is not available");
+        }
+        available
+    });
+    let channel = chosen
+        .or_else(|| channels.iter().find(|c| *c > 3))
         .expect("at least one DMA channel should be available");
     println!("Selected DMA channel: {}", channel);
 
-    tests::all(channel);
+    bench::all(channel, config.suites);
 
     // let mut executives = vec![];
 
@@ -159,19 +212,112 @@ struct CB {
 }
 const _: () = assert!(size_of::<CB>() == 0x20);
 
+/// Control blocks, indirection words and the void are always fetched by the DMA engine through
+/// the uncached alias, since the ARM writes them directly.
+const CB_ALIAS: BusAlias = BusAlias::Direct;
+
+struct Chunk {
+    base: NonNull<u8>,
+    layout: Layout,
+    /// The bus alias through which the DMA engine accesses this chunk.
+    alias: BusAlias,
+    /// Where the chunk lives if it was loaded with [`CHUNK_FLAGS_VC_MEMORY`], rather than in the
+    /// executive's arena.
+    vc: Option<VcBuffer>,
+}
+
+/// Everything a single op may touch, as recorded when it is translated.
+#[derive(Debug, Default, Clone)]
+struct OpUses {
+    chunks: Vec<usize>,
+    ops: Vec<usize>,
+    void: bool,
+}
+
+/// The chunks and CBs reachable from a routine's entry op. Anything an op refers to is assumed to
+/// be reachable, since a self-modifying routine may jump to any op it can write to. Fixed
+/// addresses and parameters are not tracked.
+#[derive(Debug, Default, Clone)]
+struct Footprint {
+    chunks: Vec<usize>,
+    ops: Vec<usize>,
+    void: bool,
+}
+impl Footprint {
+    /// Add whatever of `other` isn't already here.
+    fn merge(&mut self, other: Footprint) {
+        for op in other.ops {
+            if !self.ops.contains(&op) {
+                self.ops.push(op);
+            }
+        }
+        for chunk in other.chunks {
+            if !self.chunks.contains(&chunk) {
+                self.chunks.push(chunk);
+            }
+        }
+        self.void |= other.void;
+    }
+}
+
+struct Routine {
+    entry: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExecutiveError {
+    /// The DMA pool couldn't supply an arena of `size` bytes.
+    NoArena { size: usize },
+    /// Something needed `requested` bytes of the arena, but only `remaining` were left.
+    OverBudget { requested: usize, remaining: usize },
+    /// The firmware wouldn't provide VC memory for a chunk.
+    VcMemory(MailboxError),
+    /// A chunk had flags other than [`CHUNK_FLAGS_KNOWN`].
+    UnsupportedFlags(u32),
+    /// The void would be bigger than any allocation can be.
+    VoidTooLarge,
+    /// The op at this index refers to data outside its chunk, or to a chunk that isn't loaded.
+    BadDataRef(usize),
+}
+impl Display for ExecutiveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoArena { size } => write!(f, "no room in the DMA pool for a {size}-byte arena"),
+            Self::OverBudget {
+                requested,
+                remaining,
+            } => write!(
+                f,
+                "over budget: needed {requested} bytes of the arena, {remaining} left"
+            ),
+            Self::VcMemory(e) => write!(f, "couldn't allocate VC memory for a chunk: {e}"),
+            Self::UnsupportedFlags(flags) => write!(f, "unsupported chunk flags {flags:08x}"),
+            Self::VoidTooLarge => write!(f, "void too large"),
+            Self::BadDataRef(op) => write!(f, "op {op} refers to data outside its chunk"),
+        }
+    }
+}
+impl From<OverBudget> for ExecutiveError {
+    fn from(e: OverBudget) -> Self {
+        Self::OverBudget {
+            requested: e.requested,
+            remaining: e.remaining,
+        }
+    }
+}
+
 pub struct Executive {
-    // arena: bumpalo::Bump,
-    allocation: usize,
+    /// Where the CBs, the void, indirection words and chunks outside VC memory all live.
+    arena: Arena,
 
-    chunk_map: Vec<(NonNull<u8>, Layout)>,
+    chunk_map: Vec<Chunk>,
     symbol_map: HashMap<String, (NonNull<u8>, usize)>,
-    routine_map: HashMap<String, usize>,
+    routine_map: HashMap<String, Routine>,
     op_count: usize,
-    op_layout: Layout,
+    op_uses: Vec<OpUses>,
     op_arena: NonNull<CB>,
     void: NonNull<u8>,
     void_size: usize,
-    void_layout: Layout,
 }
 #[derive(Debug, Copy, Clone)]
 pub struct Timing {
@@ -185,20 +331,219 @@ impl Timing {
         self.cycle_end.wrapping_sub(self.cycle_begin)
     }
 }
-impl Drop for Executive {
-    fn drop(&mut self) {
-        for (chunk, layout) in self.chunk_map.iter() {
-            unsafe { alloc::alloc::dealloc(chunk.as_ptr().cast(), *layout) }
+/// The value written to CS to start a channel.
+fn start_cs_value() -> u32 {
+    let mut cs_value: LocalRegisterCopy<u32, registers::CS::Register> = LocalRegisterCopy::new(0);
+    #[rustfmt::skip]
+    cs_value.write(
+        CS::ACTIVE::SET
+            + CS::WAIT_FOR_OUTSTANDING_WRITES::SET
+            + CS::PRIORITY::SET
+    );
+    cs_value.get()
+}
+
+/// The part of [`run_chain`] between starting the channel and it going idle, reading the cycle
+/// counter with `mrc p15, 0, _, $cycle_counter` either side. Evaluates to `(cycle_begin,
+/// cycle_end)`.
+macro_rules! timed_chain {
+    ($cycle_counter:literal, $channel_base:expr, $conblk_ad:expr, $cs_value:expr) => {{
+        let cycle_begin: u32;
+        let cycle_end: u32;
+        asm!(
+            r#"
+                mcr p15, 0, {z}, c7, c10, 4 // dsb
+                mov {t0}, #2
+                str {t0}, [{channel_base}, #{CS_OFFSET}]
+                mov {t0}, #7
+                str {t0}, [{channel_base}, #{DEBUG_OFFSET}]
+                str {op_vc_addr}, [{channel_base}, #{CONBLK_AD_OFFSET}]
+
+                mcr p15, 0, {z}, c7, c10, 4 // dsb
+
+            .align 4 // align 2^4 = 16
+                mcr p15, 0, {z}, c7, c10, 4 // dsb
+            "#,
+            concat!("mrc p15, 0, {cc_begin}, ", $cycle_counter, " // read cycle counter"),
+            r#"
+                str {cs_value}, [{channel_base}, #{CS_OFFSET}] // start DMA
+            3:
+                mcr p15, 0, {z}, c7, c10, 4 // dsb
+                ldr {t0}, [{channel_base}, #{CS_OFFSET}]
+                tst {t0}, #1
+                bne 3b // loop while active
+            "#,
+            concat!("mrc p15, 0, {cc_end}, ", $cycle_counter, " // read cycle counter"),
+            r#"
+                // no longer active, clear END bit
+                orr {t0}, {t0}, #2
+                str {t0}, [{channel_base}, #{CS_OFFSET}]
+
+                mcr p15, 0, {z}, c7, c10, 4 // dsb
+            "#,
+            z = inout(reg) 0u32 => _,
+            t0 = out(reg) _,
+
+            cc_begin = out(reg) cycle_begin,
+            cc_end = out(reg) cycle_end,
+
+            channel_base = in(reg) $channel_base,
+            op_vc_addr = in(reg) $conblk_ad,
+            cs_value = in(reg) $cs_value,
+            CS_OFFSET = const 0x00,
+            CONBLK_AD_OFFSET = const 0x04,
+            DEBUG_OFFSET = const 0x20,
+        );
+        (cycle_begin, cycle_end)
+    }};
+}
+
+/// Point `channel` at the CB at bus address `conblk_ad`, start it, and spin until the channel goes
+/// idle. The caller is responsible for cache maintenance.
+fn run_chain(channel: usize, conblk_ad: u32) -> Timing {
+    let channel_base = raw::channel_ptr(channel);
+
+    let cs_value = start_cs_value();
+
+    unsafe {
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+        dsb();
+    }
+
+    let (cycle_begin, cycle_end) = unsafe {
+        match board::current().cycle_counter {
+            CycleCounter::Arm1176 => timed_chain!("c15, c12, 1", channel_base, conblk_ad, cs_value),
+            CycleCounter::Pmccntr => timed_chain!("c9, c13, 0", channel_base, conblk_ad, cs_value),
         }
-        unsafe { alloc::alloc::dealloc(self.op_arena.as_ptr().cast(), self.op_layout) };
-        unsafe { alloc::alloc::dealloc(self.void.as_ptr().cast(), self.void_layout) };
+    };
+
+    dsb();
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+    Timing {
+        cycle_begin,
+        cycle_end,
     }
 }
+/// Puts an [`Executive`] together from chunks, ops and routines that are each given once, with
+/// the budget worked out from them as [`Executive::from_dilf`] does: room for every chunk outside
+/// VC memory, one indirection word per op with an indirect source, and a void longer than any
+/// transfer to or from it.
+#[derive(Default)]
+pub struct ExecutiveBuilder<'a> {
+    chunks: Vec<(Option<&'a str>, u32, Layout, Option<&'a [u8]>)>,
+    ops: Vec<Op>,
+    routines: Vec<(&'a str, usize)>,
+}
+impl<'a> ExecutiveBuilder<'a> {
+    /// Add a chunk, as [`Loader::load_chunk`] would load it. Chunks are numbered in the order
+    /// they're added.
+    pub fn chunk(
+        mut self,
+        symbol: Option<&'a str>,
+        flags: u32,
+        layout: Layout,
+        backing: Option<&'a [u8]>,
+    ) -> Self {
+        self.chunks.push((symbol, flags, layout, backing));
+        self
+    }
+
+    /// Add ops, numbered on from any added before.
+    pub fn ops(mut self, ops: impl IntoIterator<Item = Op>) -> Self {
+        self.ops.extend(ops);
+        self
+    }
+
+    pub fn routine(mut self, name: &'a str, op_idx: usize) -> Self {
+        self.routines.push((name, op_idx));
+        self
+    }
+
+    /// The executive, in an arena just big enough for it, and the addresses of the first `N`
+    /// chunks.
+    pub fn build<const N: usize>(self) -> Result<(Executive, [NonNull<u8>; N]), ExecutiveError> {
+        assert!(
+            N <= self.chunks.len(),
+            "asked for more chunks than were added"
+        );
+        // transfers to and from the void must be strictly shorter than it
+        let max_void = self
+            .ops
+            .iter()
+            .filter_map(Op::void_len)
+            .max()
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or(ExecutiveError::VoidTooLarge)?;
+        let budget = Executive::budget(
+            self.ops.len(),
+            max_void,
+            self.chunks
+                .iter()
+                .filter(|&&(_, flags, _, _)| flags & CHUNK_FLAGS_VC_MEMORY == 0)
+                .map(|&(_, _, layout, _)| layout),
+            self.ops.iter().filter(|op| op.has_indirect_src()).count(),
+        );
+        let mut executive = Executive::new(budget, self.ops.len(), self.chunks.len(), max_void)?;
+        let mut bases = [NonNull::dangling(); N];
+        for (i, (symbol, flags, layout, backing)) in self.chunks.into_iter().enumerate() {
+            let base = executive.load_chunk(symbol, flags, layout, backing)?;
+            if let Some(slot) = bases.get_mut(i) {
+                *slot = base;
+            }
+        }
+        executive.load_ops(self.ops)?;
+        for (name, op_idx) in self.routines {
+            executive.map_routine(name, op_idx);
+        }
+        Ok((executive, bases))
+    }
+}
+
 impl Executive {
-    pub fn new(allocation: usize, op_count: usize, chunk_count: usize, max_void: usize) -> Self {
-        // let arena = Bump::with_capacity(allocation);
-        // println!("executive: allocated arena");
-        // arena.set_allocation_limit(Some(allocation));
+    /// How big an arena to give [`Executive::new`] for `op_count` ops, a void of `max_void` bytes,
+    /// chunks of the given layouts and `indirections` indirection words. Chunks in VC memory don't
+    /// need to be counted.
+    pub fn budget(
+        op_count: usize,
+        max_void: usize,
+        chunks: impl IntoIterator<Item = Layout>,
+        indirections: usize,
+    ) -> usize {
+        // saturating, so that a budget too big to have is refused by `new` rather than wrapping
+        let void = Self::void_layout(max_void).map_or(usize::MAX, arena::footprint);
+        [
+            arena::footprint(Self::op_layout(op_count)),
+            void,
+            indirections.saturating_mul(arena::footprint(Layout::new::<u32>())),
+        ]
+        .into_iter()
+        .chain(chunks.into_iter().map(arena::footprint))
+        .fold(0, usize::saturating_add)
+    }
+
+    /// An executive with everything in `dilf` loaded, in an arena just big enough for it.
+    pub fn from_dilf(dilf: &Dilf) -> Result<Self, ExecutiveError> {
+        // transfers to and from the void must be strictly shorter than it
+        let max_void = dilf
+            .max_void_len()
+            .checked_add(1)
+            .ok_or(ExecutiveError::VoidTooLarge)?;
+        let budget = Self::budget(
+            dilf.op_count(),
+            max_void,
+            dilf.chunks()
+                .filter(|chunk| chunk.flags & CHUNK_FLAGS_VC_MEMORY == 0)
+                .map(|chunk| chunk.layout),
+            dilf.indirection_count(),
+        );
+        let mut executive = Self::new(budget, dilf.op_count(), dilf.chunk_count(), max_void)?;
+        dilf.load(&mut executive)?;
+        Ok(executive)
+    }
+
+    fn op_layout(op_count: usize) -> Layout {
         let (op_layout, stride) = Layout::new::<CB>()
             .repeat(op_count)
             .expect("should not overflow");
@@ -207,16 +552,25 @@ impl Executive {
             size_of::<CB>(),
             "stride in layout is not equal to CB size"
         );
-        // println!("executive: op layout = {layout:?}");
-        let op_arena = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(op_layout) })
-            .expect("OOM")
-            .cast();
-        // println!("executive: allocated op_arena = {op_arena:?}");
-        let void_layout = Layout::from_size_align(max_void, 4).unwrap();
-        let void = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(void_layout) })
-            .expect("OOM")
-            .cast();
-        // println!("executive: allocated void = {void:?}");
+        op_layout
+    }
+
+    fn void_layout(max_void: usize) -> Result<Layout, ExecutiveError> {
+        Layout::from_size_align(max_void, 4).map_err(|_| ExecutiveError::VoidTooLarge)
+    }
+
+    /// An executive whose CBs, void and chunks all come out of one arena of `allocation` bytes
+    /// (see [`Executive::budget`]).
+    pub fn new(
+        allocation: usize,
+        op_count: usize,
+        chunk_count: usize,
+        max_void: usize,
+    ) -> Result<Self, ExecutiveError> {
+        let mut arena =
+            Arena::new(allocation).map_err(|_| ExecutiveError::NoArena { size: allocation })?;
+        let op_arena = arena.alloc(Self::op_layout(op_count))?.cast();
+        let void = arena.alloc(Self::void_layout(max_void)?)?;
 
         let chunk_map = Vec::with_capacity(chunk_count);
         // println!("executive: allocated chunk_map");
@@ -225,125 +579,143 @@ impl Executive {
         let routine_map = HashMap::new();
         // println!("executive: allocated routine_map");
 
-        Self {
-            // arena,
-            allocation,
+        Ok(Self {
+            arena,
             chunk_map,
             symbol_map,
             routine_map,
             op_count,
-            op_layout,
+            op_uses: alloc::vec![OpUses::default(); op_count],
             op_arena,
             void,
             void_size: max_void,
-            void_layout,
-        }
+        })
     }
 
     pub fn execute(&mut self, routine: &str, channel: usize) -> Timing {
-        let op_idx = *self.routine_map.get(routine).expect("unknown routine");
-        let op_ptr = self.resolve_op_ref(op_idx as u32);
-        let op_vc_addr = op_ptr.as_ptr().addr(); //self.ptr_to_vc(op_ptr.as_ptr().cast());
-        let channel_base = raw::channel_ptr(channel);
+        let (entry, footprint) = self.routine(routine);
+        let op_vc_addr = self.op_to_vc(entry);
 
-        // println!("channel_base={channel_base:?}");
-        // println!("op_vc_addr={op_vc_addr:08x}");
+        // The DMA engine can't see the ARM L1, so make sure that everything the routine might
+        // read has been written back, and that nothing it might write is still sitting dirty in
+        // the cache waiting to be evicted over the top of the DMA's results.
+        self.clean_and_invalidate_footprint(&footprint);
 
-        // let st_begin_hi: u32;
-        // let st_begin_lo: u32;
-        // let st_end_hi: u32;
-        // let st_end_lo: u32;
-        let cycle_begin: u32;
-        let cycle_end: u32;
+        let timing = run_chain(channel, op_vc_addr);
 
-        let mut cs_value: LocalRegisterCopy<u32, registers::CS::Register> =
-            LocalRegisterCopy::new(0);
-        #[rustfmt::skip]
-        cs_value.write(
-            CS::ACTIVE::SET
-                + CS::WAIT_FOR_OUTSTANDING_WRITES::SET
-                + CS::PRIORITY::SET
-        );
-        // println!("cs_value = {:08x}", cs_value.get());
+        // SAFETY: the routine's footprint was cleaned before the DMA started, and the CPU has not
+        // touched it since.
+        unsafe { self.invalidate_footprint(&footprint) };
 
-        unsafe { asm!("mcr p15, 0, {z}, c7, c14, 0", z = in(reg) 0) }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
 
-        // crate::timing::delay_millis(&unsafe { bcm2835_lpa::Peripherals::steal() }.SYSTMR, 1000);
+        timing
+    }
 
-        unsafe {
-            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-            dsb();
-        }
+    pub fn has_routine(&self, routine: &str) -> bool {
+        self.routine_map.contains_key(routine)
+    }
 
-        unsafe {
-            asm!(
-                r#"
-                    mcr p15, 0, {z}, c7, c10, 4 // dsb
-                    mcr p15, 0, {z}, c7, c14, 0 // clean and invalidate entire dcache
-                    mov {t0}, #2
-                    str {t0}, [{channel_base}, #{CS_OFFSET}]
-                    mov {t0}, #7
-                    str {t0}, [{channel_base}, #{DEBUG_OFFSET}]
-                    str {op_vc_addr}, [{channel_base}, #{CONBLK_AD_OFFSET}]
-                    mcr p15, 0, {z}, c7, c14, 0 // clean and invalidate entire dcache
-
-                    mcr p15, 0, {z}, c7, c10, 4 // dsb
-
-                .align 4 // align 2^4 = 16
-                    mcr p15, 0, {z}, c7, c10, 4 // dsb
-                    mrc p15, 0, {cc_begin}, c15, c12, 1 // read cycle counter
-                    str {cs_value}, [{channel_base}, #{CS_OFFSET}] // start DMA
-                3:
-                    mcr p15, 0, {z}, c7, c10, 4 // dsb
-                    ldr {t0}, [{channel_base}, #{CS_OFFSET}]
-                    tst {t0}, #1
-                    bne 3b // loop while active
-                    mrc p15, 0, {cc_end}, c15, c12, 1 // read cycle counter
-
-                    // no longer active, clear END bit
-                    orr {t0}, {t0}, #2
-                    str {t0}, [{channel_base}, #{CS_OFFSET}]
-
-                    mcr p15, 0, {z}, c7, c10, 4 // dsb
-                "#,
-                z = inout(reg) 0u32 => _,
-                t0 = out(reg) _,
+    pub fn routines(&self) -> impl Iterator<Item = &str> {
+        self.routine_map.keys().map(String::as_str)
+    }
 
-                // st_begin_hi = out(reg) st_begin_hi,
-                // st_begin_lo = out(reg) st_begin_lo,
-                // st_end_hi = out(reg) st_end_hi,
-                // st_end_lo = out(reg) st_end_lo,
-                cc_begin = out(reg) cycle_begin,
-                cc_end = out(reg) cycle_end,
-
-                channel_base = in(reg) channel_base,
-                op_vc_addr = in(reg) op_vc_addr,
-                cs_value = in(reg) cs_value.get(),
-                CS_OFFSET = const 0x00,
-                CONBLK_AD_OFFSET = const 0x04,
-                DEBUG_OFFSET = const 0x20,
-            );
-        }
+    /// Symbol names and the sizes of their chunks.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.symbol_map
+            .iter()
+            .map(|(name, &(_, size))| (name.as_str(), size))
+    }
 
-        // crate::timing::delay_millis(&unsafe { bcm2835_lpa::Peripherals::steal() }.SYSTMR, 1000);
+    /// The current contents of the chunk loaded under `symbol`.
+    pub fn symbol(&self, symbol: &str) -> Option<&[u8]> {
+        let &(base, size) = self.symbol_map.get(symbol)?;
+        // SAFETY: the chunk lives as long as the executive, and the DMA engine only writes to it
+        // while `execute` (or `trace`, or `profile`) holds `&mut self`.
+        Some(unsafe { core::slice::from_raw_parts(base.as_ptr(), size) })
+    }
 
-        dsb();
+    fn op_to_vc(&self, op_idx: usize) -> u32 {
+        let op_ptr = self.resolve_op_ref(op_idx as u32);
+        self.ptr_to_vc(op_ptr.as_ptr().cast())
+    }
 
-        unsafe {
-            asm!("mcr p15, 0, {t0}, c7, c6, 0", t0 = in(reg) 0);
+    /// The index of the op whose CB lives at bus address `vc`, if it is one of ours.
+    fn vc_to_op(&self, vc: u32) -> Option<usize> {
+        let base = self.op_to_vc(0);
+        let offset = vc.checked_sub(base)? as usize;
+        if offset % size_of::<CB>() != 0 {
+            return None;
         }
+        let op_idx = offset / size_of::<CB>();
+        (op_idx < self.op_count).then_some(op_idx)
+    }
 
-        dsb();
-        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    /// The entry op of `routine`, and its footprint as the executive stands now. It is worked out
+    /// afresh on every run, so chunks and ops loaded after the routine was mapped are covered.
+    fn routine(&self, routine: &str) -> (usize, Footprint) {
+        let entry = self
+            .routine_map
+            .get(routine)
+            .expect("unknown routine")
+            .entry;
+        (entry, self.footprint(entry))
+    }
 
-        // let begin = Instant::from_raw(((st_begin_hi as u64) << 32) | (st_begin_lo as u64));
-        // let end = Instant::from_raw(((st_end_hi as u64) << 32) | (st_end_lo as u64));
+    fn footprint(&self, entry: usize) -> Footprint {
+        let mut footprint = Footprint::default();
+        let mut seen_ops = alloc::vec![false; self.op_count];
+        let mut seen_chunks = alloc::vec![false; self.chunk_map.len()];
+        let mut stack = alloc::vec![entry];
+        seen_ops[entry] = true;
+        while let Some(op_idx) = stack.pop() {
+            footprint.ops.push(op_idx);
+            let uses = &self.op_uses[op_idx];
+            footprint.void |= uses.void;
+            for &chunk in uses.chunks.iter() {
+                if !seen_chunks[chunk] {
+                    seen_chunks[chunk] = true;
+                    footprint.chunks.push(chunk);
+                }
+            }
+            for &op in uses.ops.iter() {
+                if !seen_ops[op] {
+                    seen_ops[op] = true;
+                    stack.push(op);
+                }
+            }
+        }
+        footprint
+    }
+
+    fn clean_and_invalidate_footprint(&self, footprint: &Footprint) {
+        for &op_idx in footprint.ops.iter() {
+            let cb = self.resolve_op_ref(op_idx as u32);
+            cache::clean_and_invalidate_range(cb.as_ptr().cast(), size_of::<CB>());
+        }
+        for &chunk in footprint.chunks.iter() {
+            let chunk = &self.chunk_map[chunk];
+            cache::clean_and_invalidate_range(chunk.base.as_ptr(), chunk.layout.size());
+        }
+        if footprint.void {
+            cache::clean_and_invalidate_range(self.void.as_ptr(), self.void_size);
+        }
+    }
 
-        Timing {
-            // begin,
-            // end,
-            cycle_begin,
-            cycle_end,
+    /// # Safety
+    ///
+    /// The CPU must not have written to the footprint since it was last cleaned.
+    unsafe fn invalidate_footprint(&self, footprint: &Footprint) {
+        for &op_idx in footprint.ops.iter() {
+            let cb = self.resolve_op_ref(op_idx as u32);
+            unsafe { cache::invalidate_range(cb.as_ptr().cast(), size_of::<CB>()) };
+        }
+        for &chunk in footprint.chunks.iter() {
+            let chunk = &self.chunk_map[chunk];
+            unsafe { cache::invalidate_range(chunk.base.as_ptr(), chunk.layout.size()) };
+        }
+        if footprint.void {
+            unsafe { cache::invalidate_range(self.void.as_ptr(), self.void_size) };
         }
     }
 
@@ -356,27 +728,43 @@ impl Executive {
         }
     }
 
+    /// Fixed addresses in ops are ARM physical addresses; SDRAM is reached through the uncached
+    /// alias.
     fn arm_to_vc(&self, arm: u32) -> u32 {
-        if arm < 0x2000_0000 {
-            arm | 0xc000_0000
-        } else if 0x2000_0000 <= arm && arm < 0x2100_0000 {
-            (arm - 0x2000_0000) + 0x7e00_0000
-        } else {
-            panic!("No ARM to VC mapping for: {arm:08x}")
+        match PhysAddr::new(arm).to_bus(BusAlias::Direct) {
+            Some(bus) => bus.as_u32(),
+            None => panic!("No ARM to VC mapping for: {arm:08x}"),
         }
     }
 
-    fn ptr_to_vc(&self, ptr: *mut u8) -> u32 {
-        self.arm_to_vc(ptr.expose_provenance() as u32)
+    fn ptr_to_bus(&self, ptr: *mut u8, alias: BusAlias) -> BusAddr {
+        let phys = PhysAddr::from_ptr(ptr);
+        match phys.to_bus(alias) {
+            Some(bus) => bus,
+            None => panic!("No ARM to VC mapping for: {phys}"),
+        }
+    }
+
+    fn chunk(&self, chunk: u32) -> &Chunk {
+        self.chunk_map
+            .get(chunk as usize)
+            .expect("data_ref.chunk should be in-range")
     }
 
     fn resolve_data_ref(&self, data_ref: DataRef) -> NonNull<u8> {
-        let (chunk_base, chunk_layout) = self
-            .chunk_map
-            .get(data_ref.chunk as usize)
-            .expect("data_ref.chunk should be in-range");
-        assert!((data_ref.offset as usize) < chunk_layout.size());
-        unsafe { chunk_base.add(data_ref.offset as usize) }
+        let chunk = self.chunk(data_ref.chunk);
+        assert!((data_ref.offset as usize) < chunk.layout.size());
+        unsafe { chunk.base.add(data_ref.offset as usize) }
+    }
+
+    fn data_ref_to_vc(&self, data_ref: DataRef) -> u32 {
+        let nn = self.resolve_data_ref(data_ref);
+        self.ptr_to_bus(nn.as_ptr(), self.chunk(data_ref.chunk).alias)
+            .as_u32()
+    }
+
+    fn ptr_to_vc(&self, ptr: *mut u8) -> u32 {
+        self.ptr_to_bus(ptr, CB_ALIAS).as_u32()
     }
 
     fn resolve_op_field_ref(&self, op_field_ref: OpFieldRef) -> NonNull<u32> {
@@ -390,41 +778,51 @@ impl Executive {
         unsafe { self.op_arena.add(op_ref as usize) }
     }
 
-    fn alloc_indirection(&mut self) -> NonNull<u32> {
+    fn alloc_indirection(&mut self) -> Result<NonNull<u32>, ExecutiveError> {
         let layout = Layout::new::<u32>();
-        let nn = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) }).expect("OOM");
-        self.chunk_map.push((nn, layout));
-        nn.cast()
+        let nn = self.arena.alloc(layout)?;
+        self.chunk_map.push(Chunk {
+            base: nn,
+            layout,
+            alias: CB_ALIAS,
+            vc: None,
+        });
+        Ok(nn.cast())
     }
 
-    fn allocate_op_field_ref_indirection(&mut self, op_field_ref: OpFieldRef) -> NonNull<u32> {
+    fn allocate_op_field_ref_indirection(
+        &mut self,
+        op_field_ref: OpFieldRef,
+    ) -> Result<NonNull<u32>, ExecutiveError> {
         let nn = self.resolve_op_field_ref(op_field_ref);
         let as_vc = self.ptr_to_vc(nn.as_ptr().cast());
-        let ind_ptr = self.alloc_indirection();
+        let ind_ptr = self.alloc_indirection()?;
         // println!("allocated OpFieldRef indirection for {op_field_ref:?} = {ind_ptr:?}");
         unsafe { ind_ptr.write_volatile(as_vc) };
-        ind_ptr
+        Ok(ind_ptr)
     }
 
-    fn allocate_data_ref_indirection(&mut self, data_ref: DataRef) -> NonNull<u32> {
-        let nn = self.resolve_data_ref(data_ref);
-        let as_vc = self.ptr_to_vc(nn.as_ptr());
-        let ind_ptr = self.alloc_indirection();
+    fn allocate_data_ref_indirection(
+        &mut self,
+        data_ref: DataRef,
+    ) -> Result<NonNull<u32>, ExecutiveError> {
+        let as_vc = self.data_ref_to_vc(data_ref);
+        let ind_ptr = self.alloc_indirection()?;
         // println!("allocated DataRef indirection for {data_ref:?} = {ind_ptr:?}");
         unsafe { ind_ptr.write_volatile(as_vc) };
-        ind_ptr
+        Ok(ind_ptr)
     }
 
-    fn allocate_op_ref_indirection(&mut self, op_ref: u32) -> NonNull<u32> {
+    fn allocate_op_ref_indirection(&mut self, op_ref: u32) -> Result<NonNull<u32>, ExecutiveError> {
         let nn = self.resolve_op_ref(op_ref);
         let as_vc = self.ptr_to_vc(nn.as_ptr().cast());
-        let ind_ptr = self.alloc_indirection();
+        let ind_ptr = self.alloc_indirection()?;
         // println!("allocated OpRef indirection for {op_ref} = {ind_ptr:?}");
         unsafe { ind_ptr.write_volatile(as_vc) };
-        ind_ptr
+        Ok(ind_ptr)
     }
 
-    fn translate_op(&mut self, op: Op) -> CB {
+    fn translate_op(&mut self, op: Op, uses: &mut OpUses) -> Result<CB, ExecutiveError> {
         let dst = op.dst();
         let src = op.src();
         let len = op.len();
@@ -432,10 +830,11 @@ impl Executive {
 
         let dest_ad = match dst {
             OpField::DataRef(data_ref) => {
-                let nn = self.resolve_data_ref(*data_ref);
-                self.ptr_to_vc(nn.as_ptr())
+                uses.chunks.push(data_ref.chunk as usize);
+                self.data_ref_to_vc(*data_ref)
             }
             OpField::OpFieldRef(op_field_ref) => {
+                uses.ops.push(op_field_ref.op as usize);
                 let nn = self.resolve_op_field_ref(*op_field_ref);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
@@ -448,6 +847,7 @@ impl Executive {
                             (*len as usize) < self.void_size,
                             "Src=!void, but transfer length is greater than void_size"
                         );
+                        uses.void = true;
                         self.ptr_to_vc(self.void.as_ptr())
                     } else {
                         panic!("Src=!void requires fixed-length transfer")
@@ -459,19 +859,24 @@ impl Executive {
         };
         let source_ad = match src {
             OpField::DataRef(data_ref) => {
-                let nn = self.resolve_data_ref(*data_ref);
-                self.ptr_to_vc(nn.as_ptr())
+                uses.chunks.push(data_ref.chunk as usize);
+                self.data_ref_to_vc(*data_ref)
             }
             OpField::DataRefIndirect(data_ref) => {
-                let nn = self.allocate_data_ref_indirection(*data_ref);
+                uses.chunks.push(data_ref.chunk as usize);
+                let nn = self.allocate_data_ref_indirection(*data_ref)?;
+                uses.chunks.push(self.chunk_map.len() - 1);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
             OpField::OpFieldRef(op_field_ref) => {
+                uses.ops.push(op_field_ref.op as usize);
                 let nn = self.resolve_op_field_ref(*op_field_ref);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
             OpField::OpFieldRefIndirect(op_field_ref) => {
-                let nn = self.allocate_op_field_ref_indirection(*op_field_ref);
+                uses.ops.push(op_field_ref.op as usize);
+                let nn = self.allocate_op_field_ref_indirection(*op_field_ref)?;
+                uses.chunks.push(self.chunk_map.len() - 1);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
             OpField::Fixed(fixed) => self.arm_to_vc(*fixed),
@@ -483,6 +888,7 @@ impl Executive {
                             (*len as usize) < self.void_size,
                             "Dst=!void, but transfer length is greater than void_size"
                         );
+                        uses.void = true;
                         self.ptr_to_vc(self.void.as_ptr())
                     } else {
                         panic!("Dst=!void requires fixed-length transfer")
@@ -491,7 +897,9 @@ impl Executive {
                 Hole::Param | Hole::Nil => *hole as u32,
             },
             OpField::OpRefIndirect(op_ref) => {
-                let nn = self.allocate_op_ref_indirection(*op_ref);
+                uses.ops.push(*op_ref as usize);
+                let nn = self.allocate_op_ref_indirection(*op_ref)?;
+                uses.chunks.push(self.chunk_map.len() - 1);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
             _ => unreachable!(),
@@ -513,6 +921,7 @@ impl Executive {
                 Hole::Param | Hole::Nil => *hole as u32,
             },
             OpField::OpRef(op_ref) => {
+                uses.ops.push(*op_ref as usize);
                 let nn = self.resolve_op_ref(*op_ref);
                 self.ptr_to_vc(nn.as_ptr().cast())
             }
@@ -524,37 +933,77 @@ impl Executive {
             TI::NO_WIDE_BURSTS::SET
                 + TI::BURST_LENGTH::CLEAR
                 + TI::WAIT_RESP::SET
-                + TI::DEST_INC::SET
-                + TI::SRC_INC::SET,
+                + TI::PERMAP.val(op.permap()),
         );
+        let flag = |flag: u32| op.flags & flag != 0;
+        if !flag(OP_FLAGS_DST_NO_INC) {
+            ti.modify(TI::DEST_INC::SET);
+        }
+        if !flag(OP_FLAGS_SRC_NO_INC) {
+            ti.modify(TI::SRC_INC::SET);
+        }
+        if flag(OP_FLAGS_DST_DREQ) {
+            ti.modify(TI::DEST_DREQ::SET);
+        }
+        if flag(OP_FLAGS_SRC_DREQ) {
+            ti.modify(TI::SRC_DREQ::SET);
+        }
+        let stride = if flag(OP_FLAGS_TDMODE) {
+            ti.modify(TI::TDMODE::SET);
+            op.stride
+        } else {
+            0 // IGNORE
+        };
 
-        CB {
+        Ok(CB {
             ti,
             source_ad,
             dest_ad,
             txfr_len,
-            stride: 0, // IGNORE
+            stride,
             nextconbk,
             pad: [0u32; 2], // IGNORE,
-        }
+        })
     }
 }
 impl Loader for Executive {
+    type Error = ExecutiveError;
+
     fn load_chunk(
         &mut self,
         symbol: Option<&str>,
         flags: u32,
         layout: core::alloc::Layout,
         backing: Option<&[u8]>,
-    ) -> NonNull<u8> {
-        // let nn = self.arena.alloc_layout(layout);
-        let nn = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) }).expect("OOM");
+    ) -> Result<NonNull<u8>, ExecutiveError> {
+        if flags & !CHUNK_FLAGS_KNOWN != 0 {
+            return Err(ExecutiveError::UnsupportedFlags(flags));
+        }
+        let alias = if flags & CHUNK_FLAGS_L2_CACHED != 0 {
+            BusAlias::L2Coherent
+        } else {
+            BusAlias::Direct
+        };
+        let (nn, vc) = if flags & CHUNK_FLAGS_VC_MEMORY != 0 {
+            // the firmware hands out addresses in whichever alias matches the flags, but the DMA
+            // engine reaches the chunk through `alias` like any other, so all that matters here is
+            // that it's zeroed
+            let buffer = VcBuffer::allocate(
+                layout.size(),
+                layout.align(),
+                MEM_FLAG_DIRECT | MEM_FLAG_ZERO,
+            )
+            .map_err(ExecutiveError::VcMemory)?;
+            let nn = buffer.as_ptr().expect("VC buffer should be locked");
+            (nn, Some(buffer))
+        } else {
+            (self.arena.alloc(layout)?, None)
+        };
         // println!("allocated chunk {} = {nn:?}", self.chunk_map.len());
         if let Some(symbol) = symbol {
             self.symbol_map
                 .insert(symbol.to_string(), (nn, layout.size()));
         }
-        assert_eq!(flags, 0, "unsupported flags: {flags:08x}");
         if let Some(backing) = backing {
             assert_eq!(backing.len(), layout.size());
             for (i, &b) in backing.iter().enumerate() {
@@ -562,28 +1011,44 @@ impl Loader for Executive {
                 unsafe { nn.add(i).write_volatile(b) }
             }
         }
-        self.chunk_map.push((nn, layout));
-        nn
+        self.chunk_map.push(Chunk {
+            base: nn,
+            layout,
+            alias,
+            vc,
+        });
+        Ok(nn)
     }
 
-    fn load_ops<I: IntoIterator<Item = sulfur::dilf::Op>>(&mut self, ops: I) {
+    fn load_ops<I: IntoIterator<Item = sulfur::dilf::Op>>(
+        &mut self,
+        ops: I,
+    ) -> Result<(), ExecutiveError> {
         for (op_idx, op) in ops.into_iter().enumerate() {
             // println!("op_idx={op_idx}, op_count={}", self.op_count);
             assert!(op_idx < self.op_count);
+            let chunk_size = |chunk: u32| Some(self.chunk_map.get(chunk as usize)?.layout.size());
+            if !op.data_refs_fit(chunk_size) {
+                return Err(ExecutiveError::BadDataRef(op_idx));
+            }
             // SAFETY: the allocation is sized for op_count CB's, so we're not going to
             // overrun the array.
             let op_mem: NonNull<CB> = unsafe { self.op_arena.add(op_idx) };
-            let cb = self.translate_op(op);
+            let mut uses = OpUses::default();
+            let cb = self.translate_op(op, &mut uses)?;
+            self.op_uses[op_idx] = uses;
             // println!("op {op_idx} -> {cb:08x?}");
             // SAFETY: `op_arena` is properly aligned for values of type CB, and `add()`
             // will produce a pointer that is equally aligned, since we check that the stride of
             // the layout is equal to the CB size. Furthermore, we the write is valid.
             unsafe { op_mem.write_volatile(cb) };
         }
+        Ok(())
     }
 
     fn map_routine(&mut self, name: &str, op_idx: usize) {
         assert!(op_idx < self.op_count);
-        self.routine_map.insert(name.to_string(), op_idx);
+        self.routine_map
+            .insert(name.to_string(), Routine { entry: op_idx });
     }
 }
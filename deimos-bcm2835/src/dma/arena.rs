@@ -0,0 +1,73 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+use crate::{cache::DCACHE_LINE_SIZE, dma::pool::DmaPool};
+
+// An executive's working set: one zeroed block from the DMA pool, handed out front to back and
+// given back all at once. Like the pool's own allocations, everything handed out starts on a cache
+// line and takes up whole lines, so cleaning or invalidating one chunk leaves its neighbours alone.
+
+/// How much of an [`Arena`] an allocation of `layout` uses up, at most.
+pub fn footprint(layout: Layout) -> usize {
+    let lines = layout.size().next_multiple_of(DCACHE_LINE_SIZE);
+    // the arena is only line-aligned, so anything more strictly aligned may have to skip ahead
+    lines + layout.align().saturating_sub(DCACHE_LINE_SIZE)
+}
+
+/// An allocation of `requested` bytes (after padding) didn't fit in the `remaining` bytes of an
+/// [`Arena`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OverBudget {
+    pub requested: usize,
+    pub remaining: usize,
+}
+
+pub struct Arena {
+    base: NonNull<u8>,
+    layout: Layout,
+    used: usize,
+}
+
+impl Arena {
+    /// An arena of `capacity` bytes, rounded up to whole cache lines.
+    pub fn new(capacity: usize) -> Result<Self, AllocError> {
+        let layout = Layout::from_size_align(capacity, DCACHE_LINE_SIZE)
+            .map_err(|_| AllocError)?
+            .pad_to_align();
+        let base = DmaPool.allocate_zeroed(layout)?.cast();
+        Ok(Self {
+            base,
+            layout,
+            used: 0,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Zeroed memory for `layout`, which lives as long as the arena.
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, OverBudget> {
+        let align = layout.align().max(DCACHE_LINE_SIZE);
+        let addr = self.base.addr().get();
+        let start = (addr + self.used).next_multiple_of(align) - addr;
+        let end = start + layout.size().next_multiple_of(DCACHE_LINE_SIZE);
+        if end > self.capacity() {
+            return Err(OverBudget {
+                requested: end - self.used,
+                remaining: self.capacity() - self.used,
+            });
+        }
+        self.used = end;
+        // SAFETY: `start` is within the arena, as checked above
+        Ok(unsafe { self.base.add(start) })
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { DmaPool.deallocate(self.base, self.layout) };
+    }
+}
@@ -0,0 +1,275 @@
+use alloc::format;
+use sulfur::dilf::{
+    CHUNK_FLAGS_L2_CACHED, CHUNK_FLAGS_VC_MEMORY, Dst, Len, Nxt, Op, OpFieldId, Src,
+};
+
+use crate::{
+    alloc_support,
+    bench::{Bench, Format, Reporter},
+    config::Suites,
+    dma::{
+        ExecutiveBuilder, ExecutiveError,
+        build::{copy_op, layout},
+    },
+    mailbox::clocks::{self, ClockId, PinnedClock},
+    print, println,
+};
+
+/// `count` copies of the same transfer, chained in order.
+fn chain(count: usize, dst: Dst, src: Src, len: usize) -> impl Iterator<Item = Op> {
+    (0..count).map(move |i| copy_op(dst, src, len, (i + 1 < count).then_some(i + 1)))
+}
+
+fn bench_rt_from_length(reporter: &mut Reporter, sizes: &[usize], channel: usize) {
+    for &size in sizes {
+        let (mut executive, [dst, src]) = ExecutiveBuilder::default()
+            .chunk(Some("dst"), 0, layout::<u8>(size), None)
+            .chunk(Some("src"), 0, layout::<u8>(size), None)
+            .ops(chain(1, Dst::data_ref(0, 0), Src::data_ref(1, 0), size))
+            .routine("main", 0)
+            .build()
+            .unwrap();
+
+        reporter.run(Bench::new(format!("dma.length/{size}")), || {
+            for i in 0..size {
+                unsafe { dst.byte_add(i).write_volatile(0) };
+                unsafe { src.byte_add(i).write_volatile((i & 0xff) as u8) };
+            }
+            executive.execute("main", channel).cycles()
+        });
+    }
+}
+
+fn bench_rt_unaligned(reporter: &mut Reporter, channel: usize) {
+    fn bench(
+        reporter: &mut Reporter,
+        name: &str,
+        dst_align_offset: usize,
+        src_align_offset: usize,
+        len: usize,
+        channel: usize,
+    ) {
+        let (mut executive, []) = ExecutiveBuilder::default()
+            .chunk(Some("dst"), 0, layout::<u32>(4), None)
+            .chunk(Some("src"), 0, layout::<u32>(4), None)
+            .ops(chain(
+                128,
+                Dst::data_ref(0, dst_align_offset),
+                Src::data_ref(1, src_align_offset),
+                len,
+            ))
+            .routine("main", 0)
+            .build()
+            .unwrap();
+        reporter.run(Bench::new(name), || {
+            executive.execute("main", channel).cycles()
+        });
+    }
+    for align in [0usize, 1, 2, 3] {
+        let name = format!("dma.unaligned_dst.4b/{align}");
+        bench(reporter, &name, align, 0, 4, channel);
+    }
+    for align in [0usize, 1, 2, 3] {
+        let name = format!("dma.unaligned_dst.1b/{align}");
+        bench(reporter, &name, align, 0, 1, channel);
+    }
+    bench(reporter, "dma.two_word", 0, 0, 8, channel);
+}
+
+fn bench_rt_caching_behaviour(reporter: &mut Reporter, channel: usize) {
+    {
+        let mut builder = ExecutiveBuilder::default();
+        for _ in 0..2 * 128 {
+            builder = builder.chunk(None, 0, layout::<u128>(16), None);
+        }
+        let (mut executive, []) = builder
+            .ops((0..128).map(|i| Op {
+                flags: if i == 127 { 0x5400 } else { 0x6400 },
+                dst: Dst::data_ref(i * 2, 0),
+                src: Src::data_ref(i * 2 + 1, 0),
+                len: Len::fixed(16 * 16),
+                nxt: if i == 127 {
+                    Nxt::end()
+                } else {
+                    Nxt::op_ref(i + 1)
+                },
+                stride: 0,
+            }))
+            .routine("main", 0)
+            .build()
+            .unwrap();
+        reporter.run(Bench::new("dma.caching.all_different"), || {
+            executive.execute("main", channel).cycles()
+        });
+    }
+    for (name, chunk_flags) in [
+        ("dma.caching.all_same", 0),
+        ("dma.caching.all_same_l2", CHUNK_FLAGS_L2_CACHED),
+    ] {
+        let (mut executive, []) = ExecutiveBuilder::default()
+            .chunk(Some("dst"), chunk_flags, layout::<u128>(16), None)
+            .chunk(Some("src"), chunk_flags, layout::<u128>(16), None)
+            .ops(chain(
+                128,
+                Dst::data_ref(0, 0),
+                Src::data_ref(1, 0),
+                16 * 16,
+            ))
+            .routine("main", 0)
+            .build()
+            .unwrap();
+        reporter.run(Bench::new(name), || {
+            executive.execute("main", channel).cycles()
+        });
+    }
+}
+
+fn bench_rt_vc_memory(reporter: &mut Reporter, sizes: &[usize], channel: usize) {
+    for &size in sizes {
+        for (name, dst_flags, src_flags) in [
+            ("arm_to_arm", 0, 0),
+            ("vc_to_arm", 0, CHUNK_FLAGS_VC_MEMORY),
+            ("arm_to_vc", CHUNK_FLAGS_VC_MEMORY, 0),
+            ("vc_to_vc", CHUNK_FLAGS_VC_MEMORY, CHUNK_FLAGS_VC_MEMORY),
+        ] {
+            let name = format!("dma.memory.{name}/{size}");
+            let built = ExecutiveBuilder::default()
+                .chunk(Some("dst"), dst_flags, layout::<u128>(size / 16), None)
+                .chunk(Some("src"), src_flags, layout::<u128>(size / 16), None)
+                .ops(chain(1, Dst::data_ref(0, 0), Src::data_ref(1, 0), size))
+                .routine("main", 0)
+                .build();
+            let (mut executive, []) = match built {
+                Ok(built) => built,
+                // QEMU's firmware doesn't hand out VC memory
+                Err(ExecutiveError::VcMemory(e)) => {
+                    println!("{name}: skipped, no VC memory ({e})");
+                    continue;
+                }
+                Err(e) => panic!("{name}: {e}"),
+            };
+            reporter.run(Bench::new(name), || {
+                executive.execute("main", channel).cycles()
+            });
+        }
+    }
+}
+
+fn trace_chain(channel: usize) {
+    // Three-op chain: copy `src` to `tmp`, copy `tmp` to `dst`, then overwrite the second op's
+    // length field with the word in `len`.
+    let (mut executive, []) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(4), None)
+        .chunk(Some("tmp"), 0, layout::<u32>(4), None)
+        .chunk(
+            Some("src"),
+            0,
+            layout::<u32>(4),
+            Some(bytemuck::cast_slice(&[0xdeadbeefu32, 1, 2, 3])),
+        )
+        .chunk(Some("len"), 0, layout::<u32>(1), Some(&u32::to_ne_bytes(4)))
+        .ops([
+            Op {
+                flags: 0x6400,
+                dst: Dst::data_ref(1, 0),
+                src: Src::data_ref(2, 0),
+                len: Len::fixed(16),
+                nxt: Nxt::op_ref(1),
+                stride: 0,
+            },
+            Op {
+                flags: 0x6400,
+                dst: Dst::data_ref(0, 0),
+                src: Src::data_ref(1, 0),
+                len: Len::fixed(16),
+                nxt: Nxt::op_ref(2),
+                stride: 0,
+            },
+            Op {
+                flags: 0x5402,
+                dst: Dst::op_field_ref(1, OpFieldId::Len),
+                src: Src::data_ref(3, 0),
+                len: Len::fixed(4),
+                nxt: Nxt::end(),
+                stride: 0,
+            },
+        ])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    println!();
+    println!("Single-step trace");
+    print!("{}", executive.trace("main", channel));
+}
+
+fn profile_lengths(channel: usize) {
+    // One op per power-of-two length from 16B to 64KiB, chained in order.
+    const OPS: usize = 13;
+    let (mut executive, []) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u8>(16 << (OPS - 1)), None)
+        .chunk(Some("src"), 0, layout::<u8>(16 << (OPS - 1)), None)
+        .ops((0..OPS).map(|i| Op {
+            flags: if i == OPS - 1 { 0x5400 } else { 0x6400 },
+            dst: Dst::data_ref(0, 0),
+            src: Src::data_ref(1, 0),
+            len: Len::fixed(16 << i),
+            nxt: if i == OPS - 1 {
+                Nxt::end()
+            } else {
+                Nxt::op_ref(i + 1)
+            },
+            stride: 0,
+        }))
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    println!();
+    println!("Per-op profile (16B..64KiB)");
+    print!("{}", executive.profile("main", channel));
+}
+
+pub fn all(channel: usize, suites: Suites) {
+    println!();
+    // The DMA engine runs off the core clock, so hold it at its maximum for the whole run rather
+    // than let turbo or throttling move it between (or during) benchmarks.
+    let pinned =
+        clocks::max_rate(ClockId::Core).and_then(|max| PinnedClock::new(ClockId::Core, max));
+    match &pinned {
+        Ok(pinned) => println!("Core clock pinned at {} Hz", pinned.rate()),
+        Err(e) => println!("Core clock not pinned: {e}"),
+    }
+
+    // measure the peak over the benchmarks alone; leaks show up as live allocations left over
+    alloc_support::reset_high_water_mark();
+    println!("Heap before: {}", alloc_support::stats());
+
+    let mut reporter = Reporter::new(Format::Csv).record_temperature(true);
+    if suites.contains(Suites::LENGTH) {
+        bench_rt_from_length(
+            &mut reporter,
+            &[
+                1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192,
+            ],
+            channel,
+        );
+    }
+    if suites.contains(Suites::UNALIGNED) {
+        bench_rt_unaligned(&mut reporter, channel);
+    }
+    if suites.contains(Suites::CACHING) {
+        bench_rt_caching_behaviour(&mut reporter, channel);
+    }
+    if suites.contains(Suites::VC_MEMORY) {
+        bench_rt_vc_memory(&mut reporter, &[1024, 16384, 65536], channel);
+    }
+    if suites.contains(Suites::TRACE) {
+        trace_chain(channel);
+    }
+    if suites.contains(Suites::PROFILE) {
+        profile_lengths(channel);
+    }
+    println!("Heap after: {}", alloc_support::stats());
+
+    // putting the core clock back resyncs UART1 along with it (see `clocks::set_rate`)
+    drop(pinned);
+}
@@ -0,0 +1,24 @@
+use core::alloc::Layout;
+
+use sulfur::dilf::{Dst, Len, Nxt, Op, Src};
+
+// Shorthands for putting executives together by hand, for the benchmarks and tests.
+
+/// The layout of `n` `T`s.
+pub fn layout<T>(n: usize) -> Layout {
+    let (layout, stride) = Layout::new::<T>().repeat(n).unwrap();
+    assert_eq!(stride, size_of::<T>());
+    layout
+}
+
+/// A plain copy of `len` bytes, which either chains to op `nxt` or ends the routine.
+pub fn copy_op(dst: Dst, src: Src, len: usize, nxt: Option<usize>) -> Op {
+    Op {
+        flags: if nxt.is_some() { 0x6400 } else { 0x5400 },
+        dst,
+        src,
+        len: Len::fixed(len),
+        nxt: nxt.map_or(Nxt::end(), Nxt::op_ref),
+        stride: 0,
+    }
+}
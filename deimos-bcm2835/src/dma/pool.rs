@@ -0,0 +1,61 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+
+use crate::{alloc_support, cache::DCACHE_LINE_SIZE};
+
+// Memory that the DMA engine or the VideoCore reads and writes behind the CPU's back: CBs,
+// indirection words, chunks, and the mailbox's message buffer. It comes from its own region,
+// carved out of ARM memory by `alloc_support::heap_init`, so that it never shares a cache line
+// with ordinary heap data. The region is mapped like the rest of SDRAM, so every allocation is
+// also aligned to and padded out to whole cache lines; cleaning or invalidating an allocation
+// then can't write back or throw away anything that isn't part of it.
+
+/// What the pool's region is called in the memory map.
+pub const RESERVATION: &str = "DMA pool";
+
+static POOL: embedded_alloc::TlsfHeap = embedded_alloc::TlsfHeap::empty();
+
+/// The carve-out to pass to `alloc_support::heap_init` for a pool of `size` bytes.
+pub fn reservation(size: usize) -> (&'static str, Layout) {
+    (
+        RESERVATION,
+        Layout::from_size_align(size, DCACHE_LINE_SIZE).expect("pool size should be sane"),
+    )
+}
+
+/// Hand the region reserved for the pool to it. Until then, every allocation fails.
+pub fn init() {
+    let region = alloc_support::reserved(RESERVATION).expect("DMA pool should be reserved");
+    unsafe { POOL.init(region.start, region.len()) };
+}
+
+/// `layout`, grown to cover whole cache lines.
+fn padded(layout: Layout) -> Layout {
+    layout
+        .align_to(DCACHE_LINE_SIZE)
+        .expect("alignment should be sane")
+        .pad_to_align()
+}
+
+/// The DMA pool, as an [`Allocator`].
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DmaPool;
+
+unsafe impl Allocator for DmaPool {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0));
+        }
+        let layout = padded(layout);
+        let ptr = NonNull::new(unsafe { POOL.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { POOL.dealloc(ptr.as_ptr(), padded(layout)) }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use core::fmt::{Display, Formatter};
+
+use alloc::vec::Vec;
+
+use crate::{
+    arch::dsb,
+    board,
+    dma::{Executive, Timing, raw, start_cs_value},
+};
+
+/// Upper bound on the number of samples kept for a single run. Once the buffer is full, the
+/// channel is still waited on, but the remainder of the run is attributed to the last op seen.
+pub const MAX_PROFILE_SAMPLES: usize = 0x4000;
+
+const CS_OFFSET: usize = 0x00;
+const CONBLK_AD_OFFSET: usize = 0x04;
+const DEBUG_OFFSET: usize = 0x20;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct OpProfile {
+    /// Number of samples in which the channel was working on this op.
+    pub samples: u32,
+    /// Cycles attributed to this op: each sample is charged the time until the next one.
+    pub cycles: u64,
+    /// Number of times the channel was seen moving onto this op.
+    pub visits: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub timing: Timing,
+    pub ops: Vec<OpProfile>,
+    /// Time spent on CBs that don't belong to the executive, or with no CB loaded.
+    pub other: OpProfile,
+    pub samples: usize,
+    /// Set if the sample buffer filled up before the channel went idle.
+    pub saturated: bool,
+}
+
+impl Executive {
+    /// Run `routine` while sampling the channel's `CONBLK_AD` and the cycle counter in a tight
+    /// loop, and build a histogram of where the time went. Each sample costs a few dozen cycles
+    /// of bus traffic, so ops much shorter than that will be under-sampled or missed.
+    pub fn profile(&mut self, routine: &str, channel: usize) -> Profile {
+        let (entry, footprint) = self.routine(routine);
+        let conblk_ad = self.op_to_vc(entry);
+        let base = raw::channel_ptr(channel);
+        let mut samples: Vec<(u32, u32)> = Vec::with_capacity(MAX_PROFILE_SAMPLES);
+
+        self.clean_and_invalidate_footprint(&footprint);
+
+        let reg = |offset: usize| unsafe { base.byte_add(offset) };
+        let cycle_counter = board::current().cycle_counter;
+        let cycle_begin;
+        let cycle_end;
+        unsafe {
+            dsb();
+            reg(CS_OFFSET).write_volatile(2);
+            reg(DEBUG_OFFSET).write_volatile(7);
+            reg(CONBLK_AD_OFFSET).write_volatile(conblk_ad);
+            dsb();
+            cycle_begin = cycle_counter.read();
+            reg(CS_OFFSET).write_volatile(start_cs_value());
+            loop {
+                let cb = reg(CONBLK_AD_OFFSET).read_volatile();
+                let now = cycle_counter.read();
+                if samples.len() < MAX_PROFILE_SAMPLES {
+                    samples.push((cb, now));
+                }
+                if reg(CS_OFFSET).read_volatile() & 1 == 0 {
+                    cycle_end = cycle_counter.read();
+                    break;
+                }
+            }
+            // no longer active, clear END bit
+            reg(CS_OFFSET).write_volatile(2);
+            dsb();
+        }
+
+        // SAFETY: the CPU has not touched the footprint since it was cleaned.
+        unsafe { self.invalidate_footprint(&footprint) };
+
+        let mut ops = alloc::vec![OpProfile::default(); self.op_count];
+        let mut other = OpProfile::default();
+        let mut previous = None;
+        for (i, &(cb, at)) in samples.iter().enumerate() {
+            let until = samples.get(i + 1).map_or(cycle_end, |&(_, next)| next);
+            let entry = match self.vc_to_op(cb) {
+                Some(op) => &mut ops[op],
+                None => &mut other,
+            };
+            entry.samples += 1;
+            entry.cycles += until.wrapping_sub(at) as u64;
+            if previous != Some(cb) {
+                entry.visits += 1;
+                previous = Some(cb);
+            }
+        }
+
+        Profile {
+            timing: Timing {
+                cycle_begin,
+                cycle_end,
+            },
+            ops,
+            other,
+            samples: samples.len(),
+            saturated: samples.len() == MAX_PROFILE_SAMPLES,
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        const BAR_WIDTH: u64 = 40;
+        let total = self.timing.cycles() as u64;
+        writeln!(
+            f,
+            "{} cycles, {} samples{}",
+            total,
+            self.samples,
+            if self.saturated { " (saturated)" } else { "" }
+        )?;
+        let rows = self
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (Some(i), op))
+            .chain([(None, &self.other)]);
+        for (op_idx, op) in rows.filter(|(_, op)| op.samples > 0) {
+            let per_mille = (op.cycles * 1000).checked_div(total).unwrap_or(0);
+            match op_idx {
+                Some(i) => write!(f, "\top {i:<4}")?,
+                None => write!(f, "\tother  ")?,
+            }
+            write!(
+                f,
+                "\t{}cy\t{}.{}%\t{}x\t",
+                op.cycles,
+                per_mille / 10,
+                per_mille % 10,
+                op.visits
+            )?;
+            for _ in 0..(per_mille * BAR_WIDTH / 1000) {
+                write!(f, "#")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
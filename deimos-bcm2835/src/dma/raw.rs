@@ -1,7 +1,6 @@
 const CHANNEL_0_14_OFFSET_FROM_PERI_BASE: usize = 0x00_7000;
 const CHANNEL_15_OFFSET_FROM_PERI_BASE: usize = 0xE0_5000;
 const CHANNEL_STRIDE: usize = 0x100;
-const PERI_BASE_ARM: *mut u32 = core::ptr::with_exposed_provenance_mut(0x2000_0000);
 
 pub fn channel_ptr(channel: usize) -> *mut u32 {
     let channel_offset = if channel <= 14 {
@@ -11,5 +10,5 @@ pub fn channel_ptr(channel: usize) -> *mut u32 {
     } else {
         panic!("no channels >15");
     };
-    unsafe { PERI_BASE_ARM.byte_add(channel_offset) }
+    crate::board::peripheral(channel_offset)
 }
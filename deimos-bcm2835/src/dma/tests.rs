@@ -1,204 +1,323 @@
-use core::alloc::Layout;
-
 use alloc::vec::Vec;
-use sulfur::dilf::{Dst, Len, Loader, Nxt, Op, Src};
+use sulfur::dilf::{
+    CHUNK_FLAGS_L2_CACHED, CHUNK_FLAGS_VC_MEMORY, DILF32_ARCH_BCM2835, DILF32_MAGIC, DataRef, Dilf,
+    Dst, Len, Loader, Nxt, OP_FLAGS_SRC_NO_INC, OP_FLAGS_TDMODE, Op, OpFieldId, Src,
+};
 
 use crate::{
-    dma::{Executive, Timing},
-    print, println,
+    dma::{
+        Executive, ExecutiveBuilder, ExecutiveError,
+        build::{copy_op, layout},
+    },
+    mailbox::{
+        self, MailboxError,
+        memory::{MEM_FLAG_DIRECT, VcBuffer},
+    },
+    print,
 };
 
-fn layout<T>(n: usize) -> Layout {
-    let (layout, stride) = Layout::new::<T>().repeat(n).unwrap();
-    assert_eq!(stride, size_of::<T>());
-    layout
+fn channel() -> usize {
+    mailbox::dma_channels::query()
+        .unwrap()
+        .iter()
+        .find(|c| *c > 3)
+        .expect("at least one DMA channel should be available")
 }
 
-fn test_rt_from_length(sizes: &[usize], count: usize, channel: usize) {
-    fn test(size: usize, count: usize, channel: usize) -> Vec<Timing> {
-        let mut executive = Executive::new(0, 1, 2, 0);
-
-        let dst = executive.load_chunk(Some("dst"), 0, layout::<u8>(size), None);
-        let src = executive.load_chunk(Some("src"), 0, layout::<u8>(size), None);
-        executive.load_ops([Op {
-            flags: 0x5400,
-            dst: Dst::data_ref(0, 0),
-            src: Src::data_ref(1, 0),
-            len: Len::fixed(size),
-            nxt: Nxt::end(),
-        }]);
-        executive.map_routine("main", 0);
+fn copies(chunk_flags: u32) {
+    const SIZE: usize = 0x1000;
+    let (mut executive, [dst, src]) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), chunk_flags, layout::<u8>(SIZE), None)
+        .chunk(Some("src"), chunk_flags, layout::<u8>(SIZE), None)
+        .ops([copy_op(
+            Dst::data_ref(0, 0),
+            Src::data_ref(1, 0),
+            SIZE,
+            None,
+        )])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    for i in 0..SIZE {
+        unsafe { src.add(i).write_volatile((i % 251) as u8) };
+    }
+    executive.execute("main", channel());
+    for i in 0..SIZE {
+        assert_eq!(
+            unsafe { dst.add(i).read_volatile() },
+            (i % 251) as u8,
+            "mismatch at {i}"
+        );
+    }
+}
 
-        let mut timings = Vec::new();
+#[test_case]
+fn copies_uncached_chunks() {
+    copies(0);
+}
 
-        for _ in 0..count {
-            for i in 0..size {
-                unsafe { dst.byte_add(i).write_volatile(0) };
-                unsafe { src.byte_add(i).write_volatile((i & 0xff) as u8) };
-            }
-            timings.push(executive.execute("main", channel));
-        }
+#[test_case]
+fn copies_l2_cached_chunks() {
+    copies(CHUNK_FLAGS_L2_CACHED);
+}
 
-        timings
+/// Whether the firmware hands out VC memory at all. QEMU's doesn't implement the tag, and either
+/// leaves the response empty or refuses it.
+fn has_vc_memory() -> bool {
+    match VcBuffer::allocate(1, 1, MEM_FLAG_DIRECT) {
+        Ok(_) => true,
+        Err(MailboxError::TooShort { .. } | MailboxError::Refused { .. }) => false,
+        Err(e) => panic!("couldn't allocate VC memory: {e}"),
     }
+}
 
-    println!();
-    println!("Transfer Length / Runtime Correlation");
-    for (i, &size) in sizes.iter().enumerate() {
-        if i.is_multiple_of(2) {
-            print!("\x1b[42m");
-        }
-        let timings = test(size, count, channel);
-        print!("\t{size}");
-        for timing in timings {
-            print!("\t{}", timing.cycles());
-        }
-        println!("\x1b[0m");
+#[test_case]
+fn copies_vc_memory_chunks() {
+    if !has_vc_memory() {
+        print!("(skipped, no VC memory) ");
+        return;
     }
+    copies(CHUNK_FLAGS_VC_MEMORY);
 }
 
-fn test_rt_unaligned(count: usize, channel: usize) {
-    fn test(
-        dst_align_offset: usize,
-        src_align_offset: usize,
-        len: usize,
-        count: usize,
-        channel: usize,
-    ) -> Vec<Timing> {
-        let mut executive = Executive::new(0, 128, 2, 0);
-        let _dst = executive.load_chunk(Some("dst"), 0, layout::<u32>(4), None);
-        let _src = executive.load_chunk(Some("src"), 0, layout::<u32>(4), None);
-        for i in 0..128 {
-            executive.load_ops([Op {
-                flags: if i == 127 { 0x5400 } else { 0x6400 },
-                dst: Dst::data_ref(0, dst_align_offset),
-                src: Src::data_ref(1, src_align_offset),
-                len: Len::fixed(len),
-                nxt: if i == 127 {
-                    Nxt::end()
-                } else {
-                    Nxt::op_ref(i + 1)
-                },
-            }]);
-        }
-        executive.map_routine("main", 0);
-        let mut timings = Vec::new();
-        for _ in 0..count {
-            timings.push(executive.execute("main", channel));
-        }
-        timings
-    }
-    println!();
-    println!("Unaligned-Dst Transfers (4B)");
-    for align in [0usize, 1, 2, 3] {
-        if align.is_multiple_of(2) {
-            print!("\x1b[42m");
-        }
-        let timings = test(align, 0, 4, count, channel);
-        print!("\t{align}");
-        for timing in timings {
-            print!("\t{}", timing.cycles());
-        }
-        println!("\x1b[0m");
-    }
-    println!();
-    println!("Unaligned-Dst Transfers (1B)");
-    for align in [0usize, 1, 2, 3] {
-        if align.is_multiple_of(2) {
-            print!("\x1b[42m");
-        }
-        let timings = test(align, 0, 1, count, channel);
-        print!("\t{align}");
-        for timing in timings {
-            print!("\t{}", timing.cycles());
-        }
-        println!("\x1b[0m");
-    }
-    println!();
-    println!("2-Word Transfer");
-    print!("\x1b[42m");
-    let timings = test(0, 0, 8, count, channel);
-    for timing in timings {
-        print!("\t{}", timing.cycles());
-    }
-    println!("\x1b[0m");
+#[test_case]
+fn follows_chain() {
+    // copy the words in reverse order, one op per word
+    let (mut executive, [dst]) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(3), None)
+        .chunk(
+            Some("src"),
+            0,
+            layout::<u32>(3),
+            Some(bytemuck::cast_slice(&[1u32, 2, 3])),
+        )
+        .ops([
+            copy_op(Dst::data_ref(0, 0), Src::data_ref(1, 8), 4, Some(1)),
+            copy_op(Dst::data_ref(0, 4), Src::data_ref(1, 4), 4, Some(2)),
+            copy_op(Dst::data_ref(0, 8), Src::data_ref(1, 0), 4, None),
+        ])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    executive.execute("main", channel());
+    let dst = dst.cast::<u32>();
+    let words = [0, 1, 2].map(|i| unsafe { dst.add(i).read_volatile() });
+    assert_eq!(words, [3, 2, 1]);
 }
 
-fn test_rt_caching_behaviour(count: usize, channel: usize) {
-    fn test_all_different(count: usize, channel: usize) -> Vec<Timing> {
-        let mut executive = Executive::new(0, 128, 2 * 128, 0);
-        for _ in 0..256 {
-            let _ = executive.load_chunk(None, 0, layout::<u128>(16), None);
-        }
-        for i in 0..128 {
-            executive.load_ops([Op {
-                flags: if i == 127 { 0x5400 } else { 0x6400 },
-                dst: Dst::data_ref(i * 2, 0),
-                src: Src::data_ref(i * 2 + 1, 0),
-                len: Len::fixed(16 * 16),
-                nxt: if i == 127 {
-                    Nxt::end()
-                } else {
-                    Nxt::op_ref(i + 1)
-                },
-            }]);
+#[test_case]
+fn unaligned_destination() {
+    for offset in 1..4 {
+        let (mut executive, [dst]) = ExecutiveBuilder::default()
+            .chunk(Some("dst"), 0, layout::<u32>(2), None)
+            .chunk(
+                Some("src"),
+                0,
+                layout::<u32>(1),
+                Some(&u32::to_le_bytes(0x0403_0201)),
+            )
+            .ops([copy_op(
+                Dst::data_ref(0, offset),
+                Src::data_ref(1, 0),
+                4,
+                None,
+            )])
+            .routine("main", 0)
+            .build()
+            .unwrap();
+        executive.execute("main", channel());
+        let bytes = core::array::from_fn::<u8, 8, _>(|i| unsafe { dst.add(i).read_volatile() });
+        for (i, &b) in bytes.iter().enumerate() {
+            let expected = if (offset..offset + 4).contains(&i) {
+                (i - offset + 1) as u8
+            } else {
+                0
+            };
+            assert_eq!(b, expected, "offset {offset}, byte {i}");
         }
-        executive.map_routine("main", 0);
-        let mut timings = Vec::new();
-        for _ in 0..count {
-            timings.push(executive.execute("main", channel));
-        }
-        timings
     }
-    fn test_all_same(count: usize, channel: usize) -> Vec<Timing> {
-        let mut executive = Executive::new(0, 128, 2 * 128, 0);
-        let _dst = executive.load_chunk(Some("dst"), 0, layout::<u128>(16), None);
-        let _src = executive.load_chunk(Some("src"), 0, layout::<u128>(16), None);
-        for i in 0..128 {
-            executive.load_ops([Op {
-                flags: if i == 127 { 0x5400 } else { 0x6400 },
-                dst: Dst::data_ref(0, 0),
-                src: Src::data_ref(1, 0),
-                len: Len::fixed(16 * 16),
-                nxt: if i == 127 {
-                    Nxt::end()
-                } else {
-                    Nxt::op_ref(i + 1)
-                },
-            }]);
-        }
-        executive.map_routine("main", 0);
-        let mut timings = Vec::new();
-        for _ in 0..count {
-            timings.push(executive.execute("main", channel));
+}
+
+fn self_modifying() -> Executive {
+    // op 0 shortens op 1's transfer from 8 bytes to 4 before it runs
+    let (executive, []) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(2), None)
+        .chunk(
+            Some("src"),
+            0,
+            layout::<u32>(2),
+            Some(bytemuck::cast_slice(&[0xaaaa_aaaau32, 0xbbbb_bbbb])),
+        )
+        .chunk(Some("len"), 0, layout::<u32>(1), Some(&u32::to_ne_bytes(4)))
+        .ops([
+            Op {
+                flags: 0x6402,
+                dst: Dst::op_field_ref(1, OpFieldId::Len),
+                src: Src::data_ref(2, 0),
+                len: Len::fixed(4),
+                nxt: Nxt::op_ref(1),
+                stride: 0,
+            },
+            copy_op(Dst::data_ref(0, 0), Src::data_ref(1, 0), 8, None),
+        ])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    executive
+}
+
+fn read_dst(executive: &Executive) -> [u32; 2] {
+    let (dst, _) = executive.symbol_map["dst"];
+    let dst = dst.cast::<u32>();
+    [0, 1].map(|i| unsafe { dst.add(i).read_volatile() })
+}
+
+#[test_case]
+fn self_modifying_length() {
+    let mut executive = self_modifying();
+    executive.execute("main", channel());
+    assert_eq!(read_dst(&executive), [0xaaaa_aaaa, 0]);
+}
+
+#[test_case]
+fn trace_matches_execute() {
+    let mut executive = self_modifying();
+    let trace = executive.trace("main", channel());
+    assert!(!trace.truncated);
+    let ops = trace.steps.iter().map(|step| step.op).collect::<Vec<_>>();
+    assert_eq!(ops, [Some(0), Some(1)]);
+    assert_eq!(read_dst(&executive), [0xaaaa_aaaa, 0]);
+}
+
+/// A version 1 DILF file (28-byte ops) with a zero-filled `dst`, a `src` holding four words, and a
+/// `main` routine that copies the one to the other.
+fn copy_image() -> Vec<u8> {
+    let mut file = DILF32_MAGIC.to_vec();
+    let mut words = |words: &[u32]| {
+        for w in words {
+            file.extend_from_slice(&w.to_le_bytes());
         }
-        timings
-    }
-    println!();
-    println!("All-different transfer (256B)");
-    let timings = test_all_different(count, channel);
-    print!("\x1b[42m");
-    for timing in timings {
-        print!("\t{}", timing.cycles());
+    };
+    words(&[DILF32_ARCH_BCM2835 as u32 | 1 << 16, 0]);
+    // segments: code at 88 (1 op), data at 40 (2 chunks), routine map at 116 (1 routine)
+    words(&[88, 28, 40, 48, 116, 8]);
+    // chunks: symbol, flags, file offset, file size, mem size, align
+    words(&[124, 0, 0, 0, 16, 4]);
+    words(&[128, 0, 140, 16, 16, 4]);
+    // op: dst = chunk 0, src = chunk 1, len = 16, nxt = end
+    words(&[0x5400, 0, 0, 1, 0, 16, 0]);
+    // routine: name, entry
+    words(&[132, 0]);
+    file.extend_from_slice(b"dst\0src\0main\0\0\0\0");
+    for w in [0x1111_1111u32, 0x2222_2222, 0x3333_3333, 0x4444_4444] {
+        file.extend_from_slice(&w.to_le_bytes());
     }
-    println!("\x1b[0m");
-    println!();
-    println!("All-same transfer (256B)");
-    let timings = test_all_same(count, channel);
-    for timing in timings {
-        print!("\t{}", timing.cycles());
+    assert_eq!(file.len(), 156);
+    file
+}
+
+#[test_case]
+fn loads_dilf_image() {
+    let image = copy_image();
+    let dilf = Dilf::parse(&image).expect("image should parse");
+    let mut executive = Executive::from_dilf(&dilf).unwrap();
+    executive.execute("main", channel());
+    assert_eq!(
+        executive.symbol("dst"),
+        Some(bytemuck::cast_slice(&[
+            0x1111_1111u32,
+            0x2222_2222,
+            0x3333_3333,
+            0x4444_4444
+        ]))
+    );
+}
+
+#[test_case]
+fn fills_rectangle_in_2d_mode() {
+    // a 4x4 grid of words, with the 2x2 block in the middle filled from a single source word
+    let (mut executive, [dst]) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(16), None)
+        .chunk(
+            Some("src"),
+            0,
+            layout::<u32>(1),
+            Some(&0xaaaa_aaaau32.to_ne_bytes()),
+        )
+        .ops([Op {
+            flags: 0x5400 | OP_FLAGS_TDMODE | OP_FLAGS_SRC_NO_INC,
+            dst: Dst::data_ref(0, 5 * 4),
+            src: Src::data_ref(1, 0),
+            len: Len::fixed(1 << 16 | 8),
+            nxt: Nxt::end(),
+            stride: 8 << 16,
+        }])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    executive.execute("main", channel());
+    for i in 0..16 {
+        let expected = if [5, 6, 9, 10].contains(&i) {
+            0xaaaa_aaaa
+        } else {
+            0
+        };
+        assert_eq!(
+            unsafe { dst.cast::<u32>().add(i).read_volatile() },
+            expected,
+            "mismatch at word {i}"
+        );
     }
-    println!();
 }
 
-pub fn all(channel: usize) {
-    test_rt_from_length(
-        &[
-            1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192,
-        ],
-        25,
-        channel,
+#[test_case]
+fn reports_exceeded_budget() {
+    // room for the CB and one chunk, but not a second
+    let budget = Executive::budget(1, 0, [layout::<u32>(4)], 0);
+    let mut executive = Executive::new(budget, 1, 2, 0).unwrap();
+    executive
+        .load_chunk(Some("dst"), 0, layout::<u32>(4), None)
+        .unwrap();
+    assert_eq!(
+        executive
+            .load_chunk(Some("src"), 0, layout::<u32>(4), None)
+            .err(),
+        Some(ExecutiveError::OverBudget {
+            requested: 32,
+            remaining: 0
+        })
+    );
+}
+
+#[test_case]
+fn budgets_indirect_source() {
+    // copies the address of the source word rather than the word itself
+    let (mut executive, [dst]) = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(1), None)
+        .chunk(Some("src"), 0, layout::<u32>(2), None)
+        .ops([Op {
+            flags: 0x5410,
+            ..copy_op(Dst::data_ref(0, 0), Src::data_ref(1, 0), 4, None)
+        }])
+        .routine("main", 0)
+        .build()
+        .unwrap();
+    executive.execute("main", channel());
+    assert_eq!(
+        unsafe { dst.cast::<u32>().read_volatile() },
+        executive.data_ref_to_vc(DataRef {
+            chunk: 1,
+            offset: 0
+        })
     );
-    test_rt_unaligned(25, channel);
-    test_rt_caching_behaviour(25, channel);
+}
+
+#[test_case]
+fn rejects_transfer_past_chunk() {
+    let result = ExecutiveBuilder::default()
+        .chunk(Some("dst"), 0, layout::<u32>(4), None)
+        .chunk(Some("src"), 0, layout::<u32>(4), None)
+        .ops([copy_op(Dst::data_ref(0, 8), Src::data_ref(1, 0), 16, None)])
+        .build::<0>();
+    assert_eq!(result.err(), Some(ExecutiveError::BadDataRef(0)));
 }
@@ -0,0 +1,260 @@
+use core::fmt::{Display, Formatter};
+
+use alloc::vec::Vec;
+use sulfur::dilf::{OpFieldId, OpFieldRef};
+
+use crate::{
+    arch::dsb,
+    dma::{CB, Executive, Footprint, Timing, raw, run_chain},
+};
+
+/// Tracing stops after this many steps, so that a routine that loops forever still produces a
+/// trace.
+pub const MAX_TRACE_STEPS: usize = 1024;
+
+/// The channel's registers, as read back after a step completed.
+#[derive(Debug, Copy, Clone)]
+pub struct ChannelSnapshot {
+    pub cs: u32,
+    pub conblk_ad: u32,
+    pub ti: u32,
+    pub source_ad: u32,
+    pub dest_ad: u32,
+    pub txfr_len: u32,
+    pub stride: u32,
+    pub nextconbk: u32,
+    pub debug: u32,
+}
+impl ChannelSnapshot {
+    fn capture(channel: usize) -> Self {
+        let base = raw::channel_ptr(channel);
+        let read = |offset: usize| unsafe { base.byte_add(offset).read_volatile() };
+        dsb();
+        let snapshot = Self {
+            cs: read(0x00),
+            conblk_ad: read(0x04),
+            ti: read(0x08),
+            source_ad: read(0x0c),
+            dest_ad: read(0x10),
+            txfr_len: read(0x14),
+            stride: read(0x18),
+            nextconbk: read(0x1c),
+            debug: read(0x20),
+        };
+        dsb();
+        snapshot
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Region {
+    Chunk(usize),
+    Op(usize),
+}
+
+/// A contiguous run of bytes that a step changed.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub region: Region,
+    pub offset: usize,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    /// The op that ran, or `None` if the chain left the executive's CBs, in which case the rest
+    /// of the chain was run in one go.
+    pub op: Option<usize>,
+    pub cb_vc_addr: u32,
+    pub registers: ChannelSnapshot,
+    pub timing: Timing,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    /// Set if tracing gave up after [`MAX_TRACE_STEPS`] steps.
+    pub truncated: bool,
+}
+
+impl Executive {
+    /// Run `routine` one control block at a time. Before each step, the CB's `nextconbk` is
+    /// patched to zero so that the channel stops after it; afterwards the original link is put
+    /// back, unless the step itself rewrote it (in which case the rewritten value is kept, as it
+    /// would have been in a normal run). The chain then continues from the original link, since
+    /// that is what the engine would have loaded.
+    ///
+    /// A CB that deliberately writes zero over its own `nextconbk` is indistinguishable from one
+    /// that left it alone, and will have its link restored.
+    pub fn trace(&mut self, routine: &str, channel: usize) -> Trace {
+        let (entry, mut footprint) = self.routine(routine);
+        let mut next = self.op_to_vc(entry);
+        let mut steps = Vec::new();
+
+        while next != 0 {
+            if steps.len() == MAX_TRACE_STEPS {
+                return Trace {
+                    steps,
+                    truncated: true,
+                };
+            }
+            let cb_vc_addr = next;
+            let Some(op) = self.vc_to_op(cb_vc_addr) else {
+                self.clean_and_invalidate_footprint(&footprint);
+                let timing = run_chain(channel, cb_vc_addr);
+                unsafe { self.invalidate_footprint(&footprint) };
+                steps.push(TraceStep {
+                    op: None,
+                    cb_vc_addr,
+                    registers: ChannelSnapshot::capture(channel),
+                    timing,
+                    changes: Vec::new(),
+                });
+                break;
+            };
+
+            // an op reached through a fixed address isn't in the footprint, but its link is about
+            // to be patched, so it (and whatever it uses) has to be cleaned like everything else
+            if !footprint.ops.contains(&op) {
+                footprint.merge(self.footprint(op));
+            }
+
+            let link = self.resolve_op_field_ref(OpFieldRef {
+                op: op as u32,
+                field_id: OpFieldId::Nxt,
+            });
+            let original_link = unsafe { link.read_volatile() };
+            unsafe { link.write_volatile(0) };
+
+            let before = self.snapshot_footprint(&footprint);
+            self.clean_and_invalidate_footprint(&footprint);
+            let timing = run_chain(channel, cb_vc_addr);
+            let registers = ChannelSnapshot::capture(channel);
+            // SAFETY: the CPU has not touched the footprint since it was cleaned.
+            unsafe { self.invalidate_footprint(&footprint) };
+            let after = self.snapshot_footprint(&footprint);
+
+            if unsafe { link.read_volatile() } == 0 {
+                unsafe { link.write_volatile(original_link) };
+            }
+
+            steps.push(TraceStep {
+                op: Some(op),
+                cb_vc_addr,
+                registers,
+                timing,
+                changes: diff(&footprint, &before, &after),
+            });
+            next = original_link;
+        }
+
+        Trace {
+            steps,
+            truncated: false,
+        }
+    }
+
+    /// Copy out every op and chunk in `footprint`, in footprint order (ops first).
+    fn snapshot_footprint(&self, footprint: &Footprint) -> Vec<Vec<u8>> {
+        let copy = |ptr: *const u8, len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|i| unsafe { ptr.add(i).read_volatile() })
+                .collect()
+        };
+        let mut regions = Vec::with_capacity(footprint.ops.len() + footprint.chunks.len());
+        for &op in footprint.ops.iter() {
+            let cb = self.resolve_op_ref(op as u32);
+            regions.push(copy(cb.as_ptr().cast(), size_of::<CB>()));
+        }
+        for &chunk in footprint.chunks.iter() {
+            let chunk = &self.chunk_map[chunk];
+            regions.push(copy(chunk.base.as_ptr(), chunk.layout.size()));
+        }
+        regions
+    }
+}
+
+fn diff(footprint: &Footprint, before: &[Vec<u8>], after: &[Vec<u8>]) -> Vec<Change> {
+    let regions = footprint
+        .ops
+        .iter()
+        .map(|&op| Region::Op(op))
+        .chain(footprint.chunks.iter().map(|&chunk| Region::Chunk(chunk)));
+    let mut changes = Vec::new();
+    for ((region, before), after) in regions.zip(before).zip(after) {
+        let mut i = 0;
+        while i < before.len() {
+            if before[i] == after[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < before.len() && before[i] != after[i] {
+                i += 1;
+            }
+            changes.push(Change {
+                region,
+                offset: start,
+                before: before[start..i].to_vec(),
+                after: after[start..i].to_vec(),
+            });
+        }
+    }
+    changes
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Region::Chunk(chunk) => write!(f, "chunk {chunk}"),
+            Region::Op(op) => write!(f, "op {op}"),
+        }
+    }
+}
+
+impl Display for TraceStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.op {
+            Some(op) => write!(f, "op {op:<4}")?,
+            None => write!(f, "(rest) ")?,
+        }
+        let r = &self.registers;
+        writeln!(
+            f,
+            " @{:08x} {}cy cs={:08x} ti={:08x} src={:08x} dst={:08x} len={:08x} \
+             stride={:08x} next={:08x} debug={:08x}",
+            self.cb_vc_addr,
+            self.timing.cycles(),
+            r.cs,
+            r.ti,
+            r.source_ad,
+            r.dest_ad,
+            r.txfr_len,
+            r.stride,
+            r.nextconbk,
+            r.debug,
+        )?;
+        for change in self.changes.iter() {
+            writeln!(
+                f,
+                "\t{}+{:#x}: {:02x?} -> {:02x?}",
+                change.region, change.offset, change.before, change.after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            write!(f, "{i:>4}: {step}")?;
+        }
+        if self.truncated {
+            writeln!(f, "(truncated after {MAX_TRACE_STEPS} steps)")?;
+        }
+        Ok(())
+    }
+}
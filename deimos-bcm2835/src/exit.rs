@@ -0,0 +1,25 @@
+use crate::println;
+
+/// Printed, followed by the status code, as the last line before the board resets. Host-side
+/// scripts (see `qemu-bcm2835.sh`) look for it to recover an exit status.
+pub const EXIT_MARKER: &str = "deimos: exit status ";
+
+/// Report `status` over the console and reset the board through the watchdog, or stop for good
+/// if the command line said `deimos.reboot=0`. With the `semihosting` feature, the emulator exits
+/// with `status` instead.
+pub fn exit(status: u32) -> ! {
+    println!("{EXIT_MARKER}{status}");
+    #[cfg(feature = "semihosting")]
+    crate::semihosting::exit(status);
+    #[cfg(not(feature = "semihosting"))]
+    {
+        // interrupts still get serviced, so a buffered console keeps draining
+        if !crate::config::get().reboot {
+            loop {
+                crate::arch::wfi();
+            }
+        }
+        let peri = unsafe { bcm2835_lpa::Peripherals::steal() };
+        crate::watchdog::restart(&peri.PM);
+    }
+}
@@ -0,0 +1,317 @@
+use core::{
+    fmt::{Display, Formatter},
+    ptr::NonNull,
+};
+
+use blit::{Chain, Region};
+
+use crate::{
+    addr::BusAddr,
+    mailbox::{
+        MailboxError,
+        framebuffer::{self, PIXEL_ORDER_BGR, Size},
+    },
+};
+
+mod blit;
+pub mod console;
+mod font;
+#[cfg(test)]
+mod tests;
+
+// A 32-bit framebuffer allocated by the firmware. Pixels are native-endian `0x00rrggbb` words,
+// which is what BGR pixel order means once the bytes are read back as a little-endian word.
+//
+// The firmware hands back a bus address; the ARM reaches the same memory by stripping the alias.
+// The display reads straight from SDRAM, so the buffer must not be in the ARM's data cache
+// (which is off for now, see `main`) or writes won't show up until they are cleaned out.
+//
+// Fills, copies and scrolling can be handed to a DMA channel in 2D mode, one control block per
+// rectangle. Anything the control block can't describe (rows over 64KiB, more than 16K rows, or
+// a stride that doesn't fit in 16 bits) falls back to the CPU, as does a copy that overlaps
+// itself within a row, since the DMA engine only ever works left to right.
+
+const DEPTH: u32 = 32;
+
+/// Channels from here up are lite channels, which have no 2D mode.
+const FIRST_LITE_CHANNEL: usize = 7;
+
+/// Pack a colour into a pixel.
+pub const fn rgb(r: u8, g: u8, b: u8) -> u32 {
+    ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FramebufferError {
+    Mailbox(MailboxError),
+    /// The firmware gave a buffer of some other colour depth.
+    Depth(u32),
+    /// The firmware gave a buffer with RGB or some other pixel order.
+    PixelOrder(u32),
+    /// The buffer's bus address doesn't map to usable SDRAM.
+    Address(u32),
+    /// The pitch is too short for a row of `width` pixels.
+    Pitch {
+        pitch: u32,
+        width: u32,
+    },
+    /// The buffer is `size` bytes, less than the `needed` pitch times height.
+    TooSmall {
+        size: u32,
+        needed: usize,
+    },
+}
+impl Display for FramebufferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Mailbox(e) => write!(f, "{e}"),
+            Self::Depth(depth) => write!(f, "firmware gave {depth}-bit colour, not {DEPTH}-bit"),
+            Self::PixelOrder(order) => write!(f, "firmware gave pixel order {order}, not BGR"),
+            Self::Address(addr) => write!(f, "buffer at bus address {addr:#010x} isn't in SDRAM"),
+            Self::Pitch { pitch, width } => {
+                write!(f, "pitch of {pitch} bytes is too short for {width} pixels")
+            }
+            Self::TooSmall { size, needed } => {
+                write!(f, "buffer is {size} bytes, needs {needed}")
+            }
+        }
+    }
+}
+impl core::error::Error for FramebufferError {}
+impl From<MailboxError> for FramebufferError {
+    fn from(e: MailboxError) -> Self {
+        Self::Mailbox(e)
+    }
+}
+
+pub struct Framebuffer {
+    base: NonNull<u32>,
+    width: usize,
+    height: usize,
+    /// In pixels rather than bytes.
+    stride: usize,
+    dma_channel: Option<usize>,
+}
+
+// The framebuffer isn't tied to any particular context; it's only not `Send` because of the
+// pointer.
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// Ask the firmware for a `width`x`height` display and a buffer to go with it.
+    pub fn allocate(width: u32, height: u32) -> Result<Self, FramebufferError> {
+        let allocation = framebuffer::allocate(Size { width, height }, DEPTH, PIXEL_ORDER_BGR)?;
+        if allocation.depth != DEPTH {
+            return Err(FramebufferError::Depth(allocation.depth));
+        }
+        if allocation.pixel_order != PIXEL_ORDER_BGR {
+            return Err(FramebufferError::PixelOrder(allocation.pixel_order));
+        }
+        let base = BusAddr::new(allocation.buffer.base)
+            .to_phys()
+            .and_then(|base| NonNull::new(base.as_ptr()))
+            .ok_or(FramebufferError::Address(allocation.buffer.base))?;
+        let width = allocation.size.width as usize;
+        let height = allocation.size.height as usize;
+        let stride = allocation.pitch as usize / size_of::<u32>();
+        if stride < width {
+            return Err(FramebufferError::Pitch {
+                pitch: allocation.pitch,
+                width: allocation.size.width,
+            });
+        }
+        let needed = allocation.pitch as usize * height;
+        if needed > allocation.buffer.size as usize {
+            return Err(FramebufferError::TooSmall {
+                size: allocation.buffer.size,
+                needed,
+            });
+        }
+        Ok(Self {
+            base,
+            width,
+            height,
+            stride,
+            dma_channel: None,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Distance between the starts of consecutive rows, in pixels.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    pub fn as_ptr(&self) -> *mut u32 {
+        self.base.as_ptr()
+    }
+
+    /// Do fills, copies and scrolling with the DMA engine on `channel`, or on the CPU if `None`.
+    pub fn set_dma_channel(&mut self, channel: Option<usize>) {
+        if let Some(channel) = channel {
+            assert!(
+                channel < FIRST_LITE_CHANNEL,
+                "DMA channel {channel} is a lite channel, which can't do 2D transfers"
+            );
+        }
+        self.dma_channel = channel;
+    }
+    pub fn dma_channel(&self) -> Option<usize> {
+        self.dma_channel
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> *mut u32 {
+        debug_assert!(x < self.width && y < self.height);
+        unsafe { self.base.as_ptr().add(y * self.stride + x) }
+    }
+
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        assert!(x < self.width && y < self.height);
+        unsafe { self.pixel(x, y).write_volatile(color) }
+    }
+
+    /// The rectangle at (`x`, `y`) as one side of a 2D transfer, starting from the bottom row and
+    /// working up if `upwards`.
+    fn region(&self, x: usize, y: usize, width: usize, height: usize, upwards: bool) -> Region {
+        let row_bytes = (self.stride * size_of::<u32>()) as isize;
+        let width_bytes = (width * size_of::<u32>()) as isize;
+        if upwards {
+            Region {
+                start: self.pixel(x, y + height - 1),
+                skip: -row_bytes - width_bytes,
+            }
+        } else {
+            Region {
+                start: self.pixel(x, y),
+                skip: row_bytes - width_bytes,
+            }
+        }
+    }
+
+    /// Fill a rectangle, clipped to the screen.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        if width == 0 || height == 0 {
+            return;
+        }
+        if let Some(channel) = self.dma_channel {
+            let mut chain = Chain::default();
+            if chain.fill(
+                self.region(x, y, width, height, false),
+                width * size_of::<u32>(),
+                height,
+                color,
+            ) {
+                chain.run(channel);
+                return;
+            }
+        }
+        self.fill_rect_cpu(x, y, width, height, color);
+    }
+
+    fn fill_rect_cpu(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for y in y..y + height {
+            for x in x..x + width {
+                unsafe { self.pixel(x, y).write_volatile(color) }
+            }
+        }
+    }
+
+    /// Copy the `width`x`height` rectangle at (`src_x`, `src_y`) to (`dst_x`, `dst_y`), clipped so
+    /// that both ends are on the screen. The two may overlap.
+    pub fn copy_rect(
+        &mut self,
+        (src_x, src_y): (usize, usize),
+        (dst_x, dst_y): (usize, usize),
+        width: usize,
+        height: usize,
+    ) {
+        let width = width.min(self.width.saturating_sub(src_x.max(dst_x)));
+        let height = height.min(self.height.saturating_sub(src_y.max(dst_y)));
+        if width == 0 || height == 0 || (src_x, src_y) == (dst_x, dst_y) {
+            return;
+        }
+        // rows have to be copied bottom-up if the destination is below the source, or the bottom
+        // of the source would be overwritten before it was read
+        let upwards = dst_y > src_y;
+        let overlaps_within_row = src_y == dst_y && src_x.abs_diff(dst_x) < width;
+        if let Some(channel) = self.dma_channel
+            && !overlaps_within_row
+        {
+            let mut chain = Chain::default();
+            if chain.copy(
+                self.region(dst_x, dst_y, width, height, upwards),
+                self.region(src_x, src_y, width, height, upwards),
+                width * size_of::<u32>(),
+                height,
+            ) {
+                chain.run(channel);
+                return;
+            }
+        }
+        let copy_row = |y: usize| unsafe {
+            core::ptr::copy(
+                self.pixel(src_x, src_y + y),
+                self.pixel(dst_x, dst_y + y),
+                width,
+            )
+        };
+        if upwards {
+            (0..height).rev().for_each(copy_row);
+        } else {
+            (0..height).for_each(copy_row);
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Move everything up by `rows` pixels, and fill the rows uncovered at the bottom with
+    /// `color`.
+    pub fn scroll_up(&mut self, rows: usize, color: u32) {
+        let rows = rows.min(self.height);
+        if rows == 0 {
+            return;
+        }
+        let kept = self.height - rows;
+        if let Some(channel) = self.dma_channel {
+            // the copy and the fill go in one chain, so the DMA engine is only started once
+            let mut chain = Chain::default();
+            let width_bytes = self.width * size_of::<u32>();
+            let fits = (kept == 0
+                || chain.copy(
+                    self.region(0, 0, self.width, kept, false),
+                    self.region(0, rows, self.width, kept, false),
+                    width_bytes,
+                    kept,
+                ))
+                && chain.fill(
+                    self.region(0, kept, self.width, rows, false),
+                    width_bytes,
+                    rows,
+                    color,
+                );
+            if fits {
+                chain.run(channel);
+                return;
+            }
+        }
+        // copying forwards one row at a time never reads a row that has already been overwritten
+        for y in 0..kept {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.pixel(0, y + rows),
+                    self.pixel(0, y),
+                    self.width,
+                )
+            };
+        }
+        self.fill_rect_cpu(0, kept, self.width, rows, color);
+    }
+}
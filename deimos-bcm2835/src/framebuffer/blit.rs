@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+use sulfur::dilf::{Dst, Len, Nxt, OP_FLAGS_SRC_NO_INC, OP_FLAGS_TDMODE, Op, Src};
+
+use crate::{addr::PhysAddr, dma::ExecutiveBuilder};
+
+// In 2D mode TXFR_LEN is YLENGTH (one less than the number of rows, 14 bits) over XLENGTH (bytes
+// per row, 16 bits), and STRIDE is the signed 16-bit number of bytes to add to the destination
+// (high half) and source (low half) addresses after each row.
+const MAX_ROW_BYTES: usize = 0xffff;
+const MAX_ROWS: usize = 0x4000;
+
+/// Where one side of a 2D transfer starts, and how many bytes to skip after each row. The skip is
+/// negative to work from the bottom row up.
+#[derive(Debug, Copy, Clone)]
+pub struct Region {
+    pub start: *mut u32,
+    pub skip: isize,
+}
+
+/// A chain of 2D transfers, built up op by op and then run in one go. Returns `false` from
+/// [`copy`](Self::copy) or [`fill`](Self::fill) if the transfer won't fit in a control block, in
+/// which case the caller should do that part on the CPU.
+#[derive(Default)]
+pub struct Chain {
+    ops: Vec<Op>,
+    color: Option<u32>,
+}
+
+fn txfr_len(row_bytes: usize, rows: usize) -> Option<usize> {
+    (0 < row_bytes && row_bytes <= MAX_ROW_BYTES && 0 < rows && rows <= MAX_ROWS)
+        .then(|| (rows - 1) << 16 | row_bytes)
+}
+
+fn stride(dst_skip: isize, src_skip: isize) -> Option<u32> {
+    let dst = i16::try_from(dst_skip).ok()? as u16 as u32;
+    let src = i16::try_from(src_skip).ok()? as u16 as u32;
+    Some(dst << 16 | src)
+}
+
+impl Chain {
+    pub fn copy(&mut self, dst: Region, src: Region, row_bytes: usize, rows: usize) -> bool {
+        let (Some(len), Some(stride)) = (txfr_len(row_bytes, rows), stride(dst.skip, src.skip))
+        else {
+            return false;
+        };
+        self.ops.push(Op {
+            flags: 0x5444 | OP_FLAGS_TDMODE,
+            dst: Dst {
+                fixed: PhysAddr::from_ptr(dst.start).as_u32(),
+            },
+            src: Src {
+                fixed: PhysAddr::from_ptr(src.start).as_u32(),
+            },
+            len: Len::fixed(len),
+            nxt: Nxt::end(),
+            stride,
+        });
+        true
+    }
+
+    /// Only one colour per chain: the source is a single word that the DMA engine reads over and
+    /// over.
+    pub fn fill(&mut self, dst: Region, row_bytes: usize, rows: usize, color: u32) -> bool {
+        let (Some(len), Some(stride)) = (txfr_len(row_bytes, rows), stride(dst.skip, 0)) else {
+            return false;
+        };
+        assert!(
+            self.color.is_none_or(|c| c == color),
+            "one fill colour per chain"
+        );
+        self.color = Some(color);
+        self.ops.push(Op {
+            flags: 0x5404 | OP_FLAGS_TDMODE | OP_FLAGS_SRC_NO_INC,
+            dst: Dst {
+                fixed: PhysAddr::from_ptr(dst.start).as_u32(),
+            },
+            src: Src::data_ref(0, 0),
+            len: Len::fixed(len),
+            nxt: Nxt::end(),
+            stride,
+        });
+        true
+    }
+
+    /// Run the chain on `channel`, which must not be a lite channel (they can't do 2D transfers),
+    /// and wait for it to finish.
+    pub fn run(mut self, channel: usize) {
+        if self.ops.is_empty() {
+            return;
+        }
+        let count = self.ops.len();
+        for (i, op) in self.ops[..count - 1].iter_mut().enumerate() {
+            // nxt: end -> op_ref
+            op.flags = op.flags & !0xf000 | 0x6000;
+            op.nxt = Nxt::op_ref(i + 1);
+        }
+        let color = self.color.unwrap_or(0).to_ne_bytes();
+        let (mut executive, []) = ExecutiveBuilder::default()
+            .chunk(None, 0, Layout::new::<u32>(), Some(&color))
+            .ops(self.ops)
+            .routine("blit", 0)
+            .build()
+            .expect("DMA pool should have room");
+        executive.execute("blit", channel);
+    }
+}
@@ -0,0 +1,142 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::framebuffer::{Framebuffer, font, rgb};
+
+pub const DEFAULT_FOREGROUND: u32 = rgb(0xc0, 0xc0, 0xc0);
+pub const DEFAULT_BACKGROUND: u32 = rgb(0x00, 0x00, 0x00);
+
+const TAB_WIDTH: usize = 8;
+
+/// A grid of character cells drawn on a framebuffer, which scrolls up when the cursor goes off the
+/// bottom.
+pub struct TextConsole {
+    fb: Framebuffer,
+    cols: usize,
+    rows: usize,
+    col: usize,
+    row: usize,
+    foreground: u32,
+    background: u32,
+}
+
+impl TextConsole {
+    /// Clear `fb` to `background` and put the cursor in the top left corner.
+    pub fn new(mut fb: Framebuffer, foreground: u32, background: u32) -> Self {
+        fb.clear(background);
+        Self {
+            cols: fb.width() / font::WIDTH,
+            rows: fb.height() / font::HEIGHT,
+            fb,
+            col: 0,
+            row: 0,
+            foreground,
+            background,
+        }
+    }
+
+    pub fn set_colors(&mut self, foreground: u32, background: u32) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.fb
+    }
+
+    /// Give the framebuffer back.
+    pub fn into_framebuffer(self) -> Framebuffer {
+        self.fb
+    }
+
+    fn draw(&mut self, c: char) {
+        let glyph = font::glyph(c);
+        let x0 = self.col * font::WIDTH;
+        let y0 = self.row * font::HEIGHT;
+        for (dy, &bits) in glyph.iter().enumerate() {
+            for dx in 0..font::WIDTH {
+                let color = if bits & (1 << dx) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.fb.put_pixel(x0 + dx, y0 + dy, color);
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.fb.scroll_up(font::HEIGHT, self.background);
+        }
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        if self.cols == 0 || self.rows == 0 {
+            return;
+        }
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.col = 0,
+            '\t' => {
+                let next = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.col < next.min(self.cols) {
+                    self.write_char(' ');
+                }
+            }
+            c => {
+                if self.col == self.cols {
+                    self.newline();
+                }
+                self.draw(c);
+                self.col += 1;
+            }
+        }
+    }
+}
+
+impl core::fmt::Write for TextConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+        Ok(())
+    }
+}
+
+// The console that `print!` and `println!` copy their output to, if any.
+static MIRROR: Mutex<RefCell<Option<TextConsole>>> = Mutex::new(RefCell::new(None));
+
+/// Start copying console output to `console`, replacing any console that was already being
+/// mirrored to.
+pub fn mirror(console: TextConsole) -> Option<TextConsole> {
+    critical_section::with(|cs| MIRROR.borrow_ref_mut(cs).replace(console))
+}
+
+pub fn stop_mirroring() -> Option<TextConsole> {
+    critical_section::with(|cs| MIRROR.borrow_ref_mut(cs).take())
+}
+
+/// Run `f` on the mirrored console, if there is one.
+pub fn with_mirror<R>(f: impl FnOnce(&mut TextConsole) -> R) -> Option<R> {
+    critical_section::with(|cs| MIRROR.borrow_ref_mut(cs).as_mut().map(f))
+}
+
+/// Copy `s` to the mirrored console, if there is one. Used by [`Console`](crate::print::Console).
+/// Output is dropped if the console is already in use, which only happens if drawing panicked and
+/// this is the panic message.
+pub fn write_mirror(s: &str) {
+    critical_section::with(|cs| {
+        if let Ok(mut mirror) = MIRROR.borrow(cs).try_borrow_mut()
+            && let Some(console) = mirror.as_mut()
+        {
+            for c in s.chars() {
+                console.write_char(c);
+            }
+        }
+    });
+}
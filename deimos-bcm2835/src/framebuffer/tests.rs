@@ -0,0 +1,88 @@
+use core::ptr::NonNull;
+
+use alloc::{vec, vec::Vec};
+
+use crate::framebuffer::{
+    Framebuffer,
+    console::{DEFAULT_BACKGROUND, DEFAULT_FOREGROUND, TextConsole},
+    font,
+};
+
+/// A console `cols`x`rows` cells in size, drawn into the returned pixels rather than onto the
+/// screen.
+fn console(cols: usize, rows: usize) -> (Vec<u32>, TextConsole) {
+    let (width, height) = (cols * font::WIDTH, rows * font::HEIGHT);
+    let mut pixels = vec![0; width * height];
+    let fb = Framebuffer {
+        base: NonNull::new(pixels.as_mut_ptr()).unwrap(),
+        width,
+        height,
+        stride: width,
+        dma_channel: None,
+    };
+    (
+        pixels,
+        TextConsole::new(fb, DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+    )
+}
+
+/// Whether the cell at (`col`, `row`) holds `c`.
+fn shows(console: &mut TextConsole, col: usize, row: usize, c: char) -> bool {
+    let fb = console.framebuffer();
+    font::glyph(c).iter().enumerate().all(|(dy, &bits)| {
+        (0..font::WIDTH).all(|dx| {
+            let expected = if bits & (1 << dx) != 0 {
+                DEFAULT_FOREGROUND
+            } else {
+                DEFAULT_BACKGROUND
+            };
+            let pixel = fb.pixel(col * font::WIDTH + dx, row * font::HEIGHT + dy);
+            unsafe { pixel.read_volatile() == expected }
+        })
+    })
+}
+
+#[test_case]
+fn allocates_framebuffer() {
+    let mut fb = Framebuffer::allocate(640, 480).unwrap();
+    assert_eq!((fb.width(), fb.height()), (640, 480));
+    assert!(fb.stride() >= fb.width());
+    fb.put_pixel(639, 479, 0x00ab_cdef);
+    assert_eq!(unsafe { fb.pixel(639, 479).read_volatile() }, 0x00ab_cdef);
+}
+
+#[test_case]
+fn wraps_at_end_of_row() {
+    let (_pixels, mut console) = console(4, 3);
+    for c in "abcde".chars() {
+        console.write_char(c);
+    }
+    assert!(shows(&mut console, 3, 0, 'd'));
+    assert!(shows(&mut console, 0, 1, 'e'));
+    assert!(shows(&mut console, 1, 1, ' '));
+}
+
+#[test_case]
+fn tabs_to_next_stop() {
+    let (_pixels, mut console) = console(12, 3);
+    for c in "a\tb\tc".chars() {
+        console.write_char(c);
+    }
+    assert!(shows(&mut console, 0, 0, 'a'));
+    assert!((1..8).all(|col| shows(&mut console, col, 0, ' ')));
+    assert!(shows(&mut console, 8, 0, 'b'));
+    // the second tab stops at the edge, so `c` wraps onto the next row
+    assert!((9..12).all(|col| shows(&mut console, col, 0, ' ')));
+    assert!(shows(&mut console, 0, 1, 'c'));
+}
+
+#[test_case]
+fn scrolls_at_bottom() {
+    let (_pixels, mut console) = console(4, 2);
+    for c in "a\nb\nc".chars() {
+        console.write_char(c);
+    }
+    assert!(shows(&mut console, 0, 0, 'b'));
+    assert!(shows(&mut console, 0, 1, 'c'));
+    assert!(shows(&mut console, 1, 1, ' '));
+}
@@ -0,0 +1,100 @@
+use core::arch::asm;
+
+use crate::{
+    arch::{dsb, vector_base_address},
+    board, uart,
+};
+
+// The legacy interrupt controller. IRQs 0-31 are the first bank of GPU interrupts, which is where
+// the AUX block (and so the Mini UART) lives.
+const IC_OFFSET: usize = 0xb200;
+const IRQ_PENDING_1_OFFSET: usize = 0x04;
+const ENABLE_IRQS_1_OFFSET: usize = 0x10;
+const DISABLE_IRQS_1_OFFSET: usize = 0x1c;
+
+pub const AUX_IRQ: usize = 29;
+
+unsafe extern "C" {
+    static __deimos_vectors: [u32; 8];
+}
+
+// Only IRQs are expected. Everything else (undefined instructions, aborts, SVCs that no debugger
+// intercepted) parks the core where a debugger can find it, since there is no stack set up for
+// those modes to run a panic on.
+//
+// The IRQ handler saves everything the AAPCS lets a callee clobber, including the caller-saved
+// VFP registers, since the Rust handler may use them. 104 bytes are pushed in total, which keeps
+// the IRQ stack 8-byte aligned.
+core::arch::global_asm!(
+    r#"
+.section ".text.vectors"
+.balign 32
+.globl __deimos_vectors
+__deimos_vectors:
+    b .
+    b .
+    b .
+    b .
+    b .
+    b .
+    b __deimos_irq
+    b .
+
+__deimos_irq:
+    sub lr, lr, #4
+    srsdb sp!, #{IRQ_MODE}
+    push {{r0-r3, r12, lr}}
+    vpush {{d0-d7}}
+    vmrs r0, fpscr
+    push {{r0, r1}}
+    bl {HANDLER}
+    pop {{r0, r1}}
+    vmsr fpscr, r0
+    vpop {{d0-d7}}
+    pop {{r0-r3, r12, lr}}
+    rfeia sp!
+"#,
+    IRQ_MODE = const 0b10010u32,
+    HANDLER = sym handle_irq,
+);
+
+/// Point the vector base address at our vector table. IRQs stay masked until [`enable`].
+pub fn init() {
+    unsafe { vector_base_address::write_raw((&raw const __deimos_vectors).addr() as u32) };
+    dsb();
+}
+
+/// Route GPU interrupt `irq` (0-31) to the ARM.
+pub fn enable_irq(irq: usize) {
+    assert!(irq < 32, "only the first bank of GPU IRQs is supported");
+    dsb();
+    unsafe { board::peripheral::<u32>(IC_OFFSET + ENABLE_IRQS_1_OFFSET).write_volatile(1 << irq) };
+    dsb();
+}
+
+pub fn disable_irq(irq: usize) {
+    assert!(irq < 32, "only the first bank of GPU IRQs is supported");
+    dsb();
+    unsafe { board::peripheral::<u32>(IC_OFFSET + DISABLE_IRQS_1_OFFSET).write_volatile(1 << irq) };
+    dsb();
+}
+
+/// Unmask IRQs in the CPSR.
+pub fn enable() {
+    unsafe { asm!("cpsie i") }
+}
+
+/// Mask IRQs in the CPSR.
+pub fn disable() {
+    unsafe { asm!("cpsid i") }
+}
+
+extern "C" fn handle_irq() {
+    dsb();
+    let pending =
+        unsafe { board::peripheral::<u32>(IC_OFFSET + IRQ_PENDING_1_OFFSET).read_volatile() };
+    if pending & (1 << AUX_IRQ) != 0 {
+        uart::handle_interrupt();
+    }
+    dsb();
+}
@@ -1,25 +1,86 @@
-use crate::println;
-use core::alloc::Layout;
-use core::arch::asm;
+use crate::addr::{BusAlias, PhysAddr};
+use crate::arch::dsb;
+use crate::dma::pool::DmaPool;
+use crate::{cache, println};
+use alloc::boxed::Box;
+use bcm2835_lpa::Peripherals;
+use bytemuck::{Pod, Zeroable};
+use core::alloc::AllocError;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use core::marker::PhantomData;
+use critical_section::Mutex;
 
+pub mod clocks;
+pub mod command_line;
 pub mod dma_channels;
+pub mod framebuffer;
+pub mod memory;
+pub mod power;
 pub mod revision;
+pub mod thermal;
+pub mod voltage;
+
+#[cfg(test)]
+mod tests;
 
 pub fn dump_configuration() {
     println!("Dumping board configuration:");
-    let rev = revision::query();
-    println!("Board revision: {rev}");
-    let mut mac = [0u8; 6];
-    send_message(0x0001_0003, &mut mac);
-    println!("MAC address={:X?}", mac);
-    let mut arm_mem = [0u32; 2];
-    send_message(0x0001_0005, bytemuck::cast_slice_mut(&mut arm_mem));
-    println!("ARM memory base address: {:08x}", arm_mem[0]);
-    println!("ARM memory size: {:08x}", arm_mem[1]);
-    let mut vc_mem = [0u32; 2];
-    send_message(0x0001_0006, bytemuck::cast_slice_mut(&mut vc_mem));
-    println!("VC memory base address: {:08x}", vc_mem[0]);
-    println!("VC memory size: {:08x}", vc_mem[1]);
+    let mut message = Message::new();
+    let rev = message.push::<revision::GetBoardRevision>(());
+    let mac = message.push::<GetMacAddress>(());
+    let arm_mem = message.push::<GetArmMemory>(());
+    let vc_mem = message.push::<GetVcMemory>(());
+    let arm_clock = message.push::<clocks::GetClockRate>(clocks::ClockId::Arm as u32);
+    let core_clock = message.push::<clocks::GetClockRate>(clocks::ClockId::Core as u32);
+    let temperature = message.push::<thermal::GetTemperature>(0);
+    let throttled = message.push::<thermal::GetThrottled>(0);
+    let reply = match message.send() {
+        Ok(reply) => reply,
+        Err(e) => {
+            println!("mailbox request failed: {e}");
+            return;
+        }
+    };
+    match reply.get(rev) {
+        Ok(rev) => match revision::BoardRevision::try_from(rev) {
+            Ok(rev) => println!("Board revision: {rev}"),
+            Err(e) => println!("Board revision: {e}"),
+        },
+        Err(e) => println!("Board revision: {e}"),
+    }
+    match reply.get(mac) {
+        Ok(mac) => println!("MAC address={:X?}", mac),
+        Err(e) => println!("MAC address: {e}"),
+    }
+    match reply.get(arm_mem) {
+        Ok(arm_mem) => {
+            println!("ARM memory base address: {:08x}", arm_mem.base);
+            println!("ARM memory size: {:08x}", arm_mem.size);
+        }
+        Err(e) => println!("ARM memory: {e}"),
+    }
+    match reply.get(vc_mem) {
+        Ok(vc_mem) => {
+            println!("VC memory base address: {:08x}", vc_mem.base);
+            println!("VC memory size: {:08x}", vc_mem.size);
+        }
+        Err(e) => println!("VC memory: {e}"),
+    }
+    for (name, slot) in [("ARM", arm_clock), ("Core", core_clock)] {
+        match reply.get(slot) {
+            Ok(clock) => println!("{name} clock: {} Hz", clock.rate),
+            Err(e) => println!("{name} clock: {e}"),
+        }
+    }
+    match reply.get(temperature) {
+        Ok(temperature) => println!("SoC temperature: {} m°C", temperature.value),
+        Err(e) => println!("SoC temperature: {e}"),
+    }
+    match reply.get(throttled) {
+        Ok(throttled) => println!("Throttling: {}", thermal::Throttled(throttled)),
+        Err(e) => println!("Throttling: {e}"),
+    }
 }
 
 // 16-byte aligned buffer
@@ -44,60 +105,281 @@ pub fn dump_configuration() {
 //                | 7fff_ffff => value length in bytes
 //  C => (value buffer)
 //  ? => pad to 4 bytes
+//
+// The value buffer is shared between the request and the response, so it is sized for whichever
+// is larger. If the firmware has more to say than fits, it truncates the value and reports the
+// length it wanted.
 
-#[repr(C)]
-struct MessageHeader {
-    size: u32,
-    code: u32,
+/// The largest message that can be sent, including the header and end tag.
+pub const MESSAGE_WORDS: usize = 256;
+const MESSAGE_HEADER_WORDS: usize = 2;
+const TAG_HEADER_WORDS: usize = 3;
+const CODE_REQUEST: u32 = 0x0000_0000;
+const CODE_SUCCESS: u32 = 0x8000_0000;
+const CODE_PARSE_ERROR: u32 = 0x8000_0001;
+const TAG_RESPONSE: u32 = 0x8000_0000;
+const END_TAG: u32 = 0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MailboxError {
+    /// The firmware couldn't parse the message buffer.
+    BadMessage,
+    /// The firmware replied with a code that is neither success nor a parse error.
+    UnexpectedCode(u32),
+    /// The tag's response bit wasn't set: the firmware doesn't know the tag, or didn't get to it.
+    NoResponse { tag: u32 },
+    /// The firmware wanted to send back `len` bytes, but the value buffer only had room for
+    /// `capacity`.
+    Truncated {
+        tag: u32,
+        len: usize,
+        capacity: usize,
+    },
+    /// The message would be more than [`MESSAGE_WORDS`] words long.
+    TooLarge,
+    /// The response was `len` bytes, less than the `expected` size of the response type.
+    TooShort {
+        tag: u32,
+        len: usize,
+        expected: usize,
+    },
+    /// The firmware answered the tag, but with a value that means it wouldn't do what was asked.
+    Refused { tag: u32 },
+}
+
+impl Display for MailboxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadMessage => write!(f, "firmware could not parse the message"),
+            Self::UnexpectedCode(code) => write!(f, "unexpected response code {code:#010x}"),
+            Self::NoResponse { tag } => write!(f, "no response to tag {tag:#010x}"),
+            Self::Truncated { tag, len, capacity } => write!(
+                f,
+                "response to tag {tag:#010x} truncated to {capacity} of {len} bytes"
+            ),
+            Self::TooLarge => write!(f, "message is larger than {MESSAGE_WORDS} words"),
+            Self::TooShort { tag, len, expected } => write!(
+                f,
+                "response to tag {tag:#010x} is {len} bytes, expected {expected}"
+            ),
+            Self::Refused { tag } => write!(f, "firmware refused tag {tag:#010x}"),
+        }
+    }
 }
+
+impl core::error::Error for MailboxError {}
+
+/// A property tag: an ID, and the types of the values that go to and come back from the firmware.
+pub trait Tag {
+    const ID: u32;
+    type Request: Pod;
+    type Response: Pod;
+}
+
+/// Base address and size of a region of memory.
 #[repr(C)]
-struct TagHeader {
-    id: u32,
-    value_size: u32,
-    code: u32,
-}
-
-pub fn send_message(tag_id: u32, value: &mut [u8]) {
-    let layout = Layout::from_size_align(
-        (size_of::<MessageHeader>() + size_of::<TagHeader>() + ((value.len() + 3) & !3) + 4 + 0)
-            & !0,
-        16,
-    )
-    .expect("layout is valid");
-    let message: *mut u32 = unsafe { alloc::alloc::alloc_zeroed(layout) }.cast();
-    // println!("Allocated message buffer");
-    assert!(!message.is_null());
-    unsafe {
-        message
-            .offset(0)
-            .cast::<MessageHeader>()
-            .write_volatile(MessageHeader {
-                size: layout.size() as u32,
-                code: 0,
-            });
-        message
-            .offset(2)
-            .cast::<TagHeader>()
-            .write_volatile(TagHeader {
-                id: tag_id,
-                value_size: value.len() as u32,
-                code: 0,
-            });
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+unsafe impl Zeroable for MemoryRegion {}
+unsafe impl Pod for MemoryRegion {}
+
+pub struct GetMacAddress;
+impl Tag for GetMacAddress {
+    const ID: u32 = 0x0001_0003;
+    type Request = ();
+    type Response = [u8; 6];
+}
+
+/// The part of SDRAM that the ARM gets.
+pub struct GetArmMemory;
+impl Tag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    type Request = ();
+    type Response = MemoryRegion;
+}
+
+/// The part of SDRAM that the VideoCore keeps for itself.
+pub struct GetVcMemory;
+impl Tag for GetVcMemory {
+    const ID: u32 = 0x0001_0006;
+    type Request = ();
+    type Response = MemoryRegion;
+}
+
+/// Where a tag went in a [`Message`], for getting its response back out of the [`Reply`].
+pub struct Slot<T: Tag> {
+    offset: usize,
+    _tag: PhantomData<T>,
+}
+impl<T: Tag> Clone for Slot<T> {
+    fn clone(&self) -> Self {
+        *self
     }
-    // println!("Wrote message initials");
-    if !send_message_raw(message, layout.size()) {
-        panic!("mailbox message failure");
+}
+impl<T: Tag> Copy for Slot<T> {}
+
+/// A property message under construction. Tags are processed by the firmware in the order they
+/// were pushed.
+pub struct Message {
+    words: [u32; MESSAGE_WORDS],
+    len: usize,
+    overflowed: bool,
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Message {
+    pub const fn new() -> Self {
+        Self {
+            words: [0; MESSAGE_WORDS],
+            len: MESSAGE_HEADER_WORDS,
+            overflowed: false,
+        }
+    }
+
+    /// Add a tag to the message. If it doesn't fit, [`send`](Self::send) fails with
+    /// [`MailboxError::TooLarge`].
+    pub fn push<T: Tag>(&mut self, request: T::Request) -> Slot<T> {
+        let offset = self.len;
+        let capacity = value_capacity::<T>();
+        let value = offset + TAG_HEADER_WORDS;
+        // leave room for the end tag
+        if value + capacity / 4 + 1 > MESSAGE_WORDS {
+            self.overflowed = true;
+        } else {
+            self.words[offset..value].copy_from_slice(&[T::ID, capacity as u32, CODE_REQUEST]);
+            let request = bytemuck::bytes_of(&request);
+            bytemuck::cast_slice_mut::<u32, u8>(&mut self.words[value..])[..request.len()]
+                .copy_from_slice(request);
+            self.len = value + capacity / 4;
+        }
+        Slot {
+            offset,
+            _tag: PhantomData,
+        }
+    }
+
+    /// Send the message and wait for the firmware to answer it.
+    pub fn send(mut self) -> Result<Reply, MailboxError> {
+        if self.overflowed {
+            return Err(MailboxError::TooLarge);
+        }
+        self.words[self.len] = END_TAG;
+        let len = (self.len + 1).next_multiple_of(4).min(MESSAGE_WORDS);
+        self.words[0] = (len * 4) as u32;
+        self.words[1] = CODE_REQUEST;
+        match transport(&mut self.words[..len]) {
+            CODE_SUCCESS => Ok(Reply { words: self.words }),
+            CODE_PARSE_ERROR => Err(MailboxError::BadMessage),
+            code => Err(MailboxError::UnexpectedCode(code)),
+        }
+    }
+}
+
+/// The firmware's answer to a [`Message`].
+pub struct Reply {
+    words: [u32; MESSAGE_WORDS],
+}
+
+impl Reply {
+    /// The response to the tag at `slot`, which must have come from the message this is the reply
+    /// to.
+    pub fn get<T: Tag>(&self, slot: Slot<T>) -> Result<T::Response, MailboxError> {
+        let value = self.value(slot)?;
+        let expected = size_of::<T::Response>();
+        if value.len() < expected {
+            return Err(MailboxError::TooShort {
+                tag: T::ID,
+                len: value.len(),
+                expected,
+            });
+        }
+        Ok(bytemuck::pod_read_unaligned(&value[..expected]))
     }
-    // println!("Received return message");
-    for i in 0..value.len() {
-        let x = unsafe { message.offset(5).cast::<u8>().byte_add(i) };
-        // println!("v[i] <- {x:?}");
-        let y = unsafe { x.read_volatile() };
-        value[i] = y;
+
+    /// The value of the response to the tag at `slot`, as long as the firmware made it. For tags
+    /// like [`command_line::GetCommandLine`] whose responses vary in length, so that
+    /// `T::Response` is only an upper bound.
+    pub fn value<T: Tag>(&self, slot: Slot<T>) -> Result<&[u8], MailboxError> {
+        let &[id, capacity, code] = &self.words[slot.offset..slot.offset + TAG_HEADER_WORDS] else {
+            unreachable!()
+        };
+        debug_assert_eq!(id, T::ID, "slot is from a different message");
+        let capacity = capacity as usize;
+        if code & TAG_RESPONSE == 0 {
+            return Err(MailboxError::NoResponse { tag: T::ID });
+        }
+        let len = (code & !TAG_RESPONSE) as usize;
+        if len > capacity {
+            return Err(MailboxError::Truncated {
+                tag: T::ID,
+                len,
+                capacity,
+            });
+        }
+        let value = slot.offset + TAG_HEADER_WORDS;
+        let value: &[u8] = bytemuck::cast_slice(&self.words[value..value + capacity / 4]);
+        Ok(&value[..len])
     }
-    // println!("Copied response to buffer");
-    unsafe { alloc::alloc::dealloc(message.cast(), layout) };
-    // println!("Deallocated message");
+}
+
+/// Send a message with just the one tag in it.
+pub fn query<T: Tag>(request: T::Request) -> Result<T::Response, MailboxError> {
+    let mut message = Message::new();
+    let slot = message.push::<T>(request);
+    message.send()?.get(slot)
+}
+
+fn value_capacity<T: Tag>() -> usize {
+    size_of::<T::Request>()
+        .max(size_of::<T::Response>())
+        .next_multiple_of(4)
+}
+
+// Whole cache lines, so that cleaning and invalidating the buffer can't touch anything else.
+#[repr(C, align(32))]
+struct Buffer([u32; MESSAGE_WORDS]);
+
+// Messages are copied into a buffer to be sent, rather than sent from wherever the caller built
+// them, so that the buffer is known to be aligned and in SDRAM, and so that only one message is in
+// flight at a time. Once the DMA pool is up the buffer comes from there, along with everything
+// else the VideoCore reads; until then, and if that allocation fails, the static one is used, so
+// the mailbox works before the heap is up, from the panic handler, and from interrupt handlers.
+static EARLY_BUFFER: Mutex<RefCell<Buffer>> = Mutex::new(RefCell::new(Buffer([0; MESSAGE_WORDS])));
+static DMA_BUFFER: Mutex<RefCell<Option<Box<Buffer, DmaPool>>>> = Mutex::new(RefCell::new(None));
+
+/// Move the message buffer into the DMA pool. Call after `dma::pool::init`.
+pub fn init_dma_buffer() -> Result<(), AllocError> {
+    let buffer = Box::try_new_in(Buffer([0; MESSAGE_WORDS]), DmaPool)?;
+    critical_section::with(|cs| *DMA_BUFFER.borrow_ref_mut(cs) = Some(buffer));
+    Ok(())
+}
+
+/// Send `words`, copy the firmware's answer back over them, and return the response code.
+fn transport(words: &mut [u32]) -> u32 {
+    critical_section::with(|cs| {
+        let mut dma_buffer = DMA_BUFFER.borrow_ref_mut(cs);
+        let mut early_buffer;
+        let buffer = match dma_buffer.as_deref_mut() {
+            Some(buffer) => buffer,
+            None => {
+                early_buffer = EARLY_BUFFER.borrow_ref_mut(cs);
+                &mut *early_buffer
+            }
+        };
+        let message = &mut buffer.0[..words.len()];
+        message.copy_from_slice(words);
+        let code = send_message_raw(message.as_mut_ptr(), size_of_val(message));
+        words.copy_from_slice(message);
+        code
+    })
 }
 
 // There are two mailboxes:
@@ -105,61 +387,43 @@ pub fn send_message(tag_id: u32, value: &mut [u8]) {
 //  1 is ARM -> VC
 // ARM should never read MB 1 or write MB 0
 
-// On 2835, the ARM has no L2, so the ARM CPU is made to use the GPU L2 cache, which basically ends
-// up meaning that the VideoCore MMU maps the ARM's view of memory to the 0x4 alias.
-const BUS_ALIAS: usize = 0x4000_0000;
+// The VC reads the message through the same alias that the ARM's own accesses go through.
+const BUS_ALIAS: BusAlias = BusAlias::L2Coherent;
 
 const TAGS_CHANNEL: u32 = 0x0000_0008;
 const CHANNEL_MASK: u32 = 0x0000_000f;
+const STATUS_FULL: u32 = 0x8000_0000;
+const STATUS_EMPTY: u32 = 0x4000_0000;
 
-fn send_message_raw(message: *mut u32, len: usize) -> bool {
+fn send_message_raw(message: *mut u32, len: usize) -> u32 {
     assert!(len >= 2);
     assert!(message.is_aligned_to(16));
+    let bus_message = PhysAddr::from_ptr(message)
+        .to_bus(BUS_ALIAS)
+        .expect("message buffer should be in SDRAM")
+        .as_u32()
+        | TAGS_CHANNEL;
 
     // We need to:
     //  1. ensure that any existing bus transactions related to `message` have completed
-    //  2. ensure that L1 cache is flushed, since the VC can't see the ARM L1 cache
+    //  2. ensure that `message` is flushed from the L1 cache, since the VC can't see it
     //  3. ensure that the compiler knows that mechanisms beyond its purview may mutate `message`
-    const BASE: usize = 0x2000_b880;
-    unsafe {
-        asm!(r#"
-            mcr p15, 0, {z}, c7, c10, 4 // dsb
-            mcr p15, 0, {z}, c7, c14, 0 // clean and invalidate entire dcache
-        2:
-            ldr {t0}, [{base}, #{STATUS1}]
-            movs {t0}, {t0}
-            bmi 2b
-
-            orr {tmsg}, {msg}, {bus_alias}
-            orr {tmsg}, {tmsg}, #{TAGS_CHANNEL}
-            str {tmsg}, [{base}, #{WRITE1}]
-        3:
-            ldr {t0}, [{base}, #{STATUS0}]
-            movs {t0}, {t0}, lsl #1
-            bmi 3b
-
-            ldr {t0}, [{base}, #{READ0}]
-            and {t0}, {t0}, #{CHANNEL_MASK}
-            cmp {t0}, #8
-            bne 3b
-
-            mcr p15, 0, {z}, c7, c10, 4 // dsb
-
-            "#,
-            z = in(reg) 0u32,
-            t0 = out(reg) _,
-            msg = in(reg) message,
-            tmsg = out(reg) _,
-            base = in(reg) BASE,
-            READ0 = const 0x00,
-            STATUS0 = const 0x18,
-            WRITE1 = const 0x20,
-            STATUS1 = const 0x38,
-            bus_alias = const BUS_ALIAS,
-            CHANNEL_MASK = const CHANNEL_MASK,
-            TAGS_CHANNEL = const TAGS_CHANNEL,
-        )
-    }
-
-    unsafe { message.offset(1).read_volatile() == 0x8000_0000u32 }
+    //     (the `dsb`s are `asm!` blocks that may touch any memory, which takes care of this)
+    cache::clean_and_invalidate_range(message.cast(), len);
+    let mailbox = unsafe { Peripherals::steal() }.VCMAILBOX;
+    dsb();
+    while mailbox.status1().read().bits() & STATUS_FULL != 0 {}
+    mailbox.write().write(|w| unsafe { w.bits(bus_message) });
+    loop {
+        while mailbox.status0().read().bits() & STATUS_EMPTY != 0 {}
+        if mailbox.read().read().bits() & CHANNEL_MASK == TAGS_CHANNEL {
+            break;
+        }
+    }
+    dsb();
+
+    // SAFETY: the CPU has not written to `message` since it was cleaned.
+    unsafe { cache::invalidate_range(message.cast(), len) };
+
+    unsafe { message.offset(1).read_volatile() }
 }
@@ -0,0 +1,187 @@
+use bcm2835_lpa::Peripherals;
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    mailbox::{self, MailboxError, Tag},
+    uart,
+};
+
+/// Clock ids, as used by the clock property tags.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+}
+
+/// A clock and its rate in Hz.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClockRate {
+    pub id: u32,
+    pub rate: u32,
+}
+unsafe impl Zeroable for ClockRate {}
+unsafe impl Pod for ClockRate {}
+
+/// A clock and whether it is on.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClockState {
+    pub id: u32,
+    pub state: u32,
+}
+unsafe impl Zeroable for ClockState {}
+unsafe impl Pod for ClockState {}
+
+const STATE_ON: u32 = 1 << 0;
+const STATE_MISSING: u32 = 1 << 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SetRate {
+    pub id: u32,
+    pub rate: u32,
+    /// Non-zero to stop the firmware from also turning turbo on when the ARM clock goes above its
+    /// default rate.
+    pub skip_turbo: u32,
+}
+unsafe impl Zeroable for SetRate {}
+unsafe impl Pod for SetRate {}
+
+/// Takes a [`ClockId`].
+pub struct GetClockState;
+impl Tag for GetClockState {
+    const ID: u32 = 0x0003_0001;
+    type Request = u32;
+    type Response = ClockState;
+}
+
+pub struct SetClockState;
+impl Tag for SetClockState {
+    const ID: u32 = 0x0003_8001;
+    type Request = ClockState;
+    type Response = ClockState;
+}
+
+/// Takes a [`ClockId`].
+pub struct GetClockRate;
+impl Tag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    type Request = u32;
+    type Response = ClockRate;
+}
+
+pub struct SetClockRate;
+impl Tag for SetClockRate {
+    const ID: u32 = 0x0003_8002;
+    type Request = SetRate;
+    type Response = ClockRate;
+}
+
+/// Takes a [`ClockId`].
+pub struct GetMaxClockRate;
+impl Tag for GetMaxClockRate {
+    const ID: u32 = 0x0003_0004;
+    type Request = u32;
+    type Response = ClockRate;
+}
+
+/// Takes a [`ClockId`].
+pub struct GetMinClockRate;
+impl Tag for GetMinClockRate {
+    const ID: u32 = 0x0003_0007;
+    type Request = u32;
+    type Response = ClockRate;
+}
+
+/// The clock's current rate in Hz, or 0 if the firmware doesn't know about it.
+pub fn rate(clock: ClockId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetClockRate>(clock as u32)?.rate)
+}
+
+/// The highest rate the clock can be set to, in Hz.
+pub fn max_rate(clock: ClockId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetMaxClockRate>(clock as u32)?.rate)
+}
+
+/// The lowest rate the clock can be set to, in Hz.
+pub fn min_rate(clock: ClockId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetMinClockRate>(clock as u32)?.rate)
+}
+
+/// Ask for the clock to run at `rate` Hz, and return the rate it ended up at, which is clamped
+/// to the clock's limits. The Mini UART's baud rate comes from the core clock, so changing that
+/// drains UART1 first and [resyncs](uart::resync) it afterwards.
+pub fn set_rate(clock: ClockId, rate: u32) -> Result<u32, MailboxError> {
+    let uart1 = (clock == ClockId::Core).then(|| unsafe { Peripherals::steal() }.UART1);
+    if let Some(uart1) = &uart1 {
+        uart::flush(uart1);
+    }
+    let request = SetRate {
+        id: clock as u32,
+        rate,
+        skip_turbo: 1,
+    };
+    let response = mailbox::query::<SetClockRate>(request);
+    if let Some(uart1) = &uart1 {
+        uart::resync(uart1);
+    }
+    Ok(response?.rate)
+}
+
+/// Whether the clock is on, or `None` if this board doesn't have it.
+pub fn is_on(clock: ClockId) -> Result<Option<bool>, MailboxError> {
+    let state = mailbox::query::<GetClockState>(clock as u32)?.state;
+    Ok((state & STATE_MISSING == 0).then_some(state & STATE_ON != 0))
+}
+
+/// Turn the clock on or off, and return whether it is on afterwards, or `None` if this board
+/// doesn't have it.
+pub fn set_on(clock: ClockId, on: bool) -> Result<Option<bool>, MailboxError> {
+    let request = ClockState {
+        id: clock as u32,
+        state: if on { STATE_ON } else { 0 },
+    };
+    let state = mailbox::query::<SetClockState>(request)?.state;
+    Ok((state & STATE_MISSING == 0).then_some(state & STATE_ON != 0))
+}
+
+/// Holds a clock at a fixed rate, so that turbo and throttling can't move it, and puts the old
+/// rate back when dropped. Both go through [`set_rate`], so UART1 keeps up with the core clock.
+pub struct PinnedClock {
+    clock: ClockId,
+    previous: u32,
+    rate: u32,
+}
+
+impl PinnedClock {
+    pub fn new(clock: ClockId, rate: u32) -> Result<Self, MailboxError> {
+        let previous = self::rate(clock)?;
+        let rate = set_rate(clock, rate)?;
+        Ok(Self {
+            clock,
+            previous,
+            rate,
+        })
+    }
+
+    /// The rate the clock is pinned at, in Hz.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+}
+
+impl Drop for PinnedClock {
+    fn drop(&mut self) {
+        let _ = set_rate(self.clock, self.previous);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::mailbox::{MailboxError, Message, Tag};
+
+/// The longest command line [`query`] can fetch. The firmware's is usually a few hundred bytes;
+/// anything longer comes back as [`MailboxError::Truncated`].
+pub const CAPACITY: usize = 512;
+
+/// The command line the firmware would pass to Linux, from `cmdline.txt` plus whatever it adds.
+pub struct GetCommandLine;
+impl Tag for GetCommandLine {
+    const ID: u32 = 0x0005_0001;
+    type Request = ();
+    type Response = [u8; CAPACITY];
+}
+
+/// Fetch the command line into `buf`, and return the part of it that is used.
+pub fn query(buf: &mut [u8; CAPACITY]) -> Result<&str, MailboxError> {
+    let mut message = Message::new();
+    let slot = message.push::<GetCommandLine>(());
+    let reply = message.send()?;
+    let value = reply.value(slot)?;
+    // may or may not be NUL-terminated
+    let len = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    buf[..len].copy_from_slice(&value[..len]);
+    Ok(match core::str::from_utf8(&buf[..len]) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap(),
+    })
+}
@@ -1,4 +1,4 @@
-use crate::mailbox;
+use crate::mailbox::{self, MailboxError, Tag};
 use core::fmt::{Display, Formatter};
 
 pub struct DmaChannels {
@@ -66,14 +66,21 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-pub fn query() -> DmaChannels {
-    let mut dma = [0u32];
-    mailbox::send_message(0x0006_0001, bytemuck::cast_slice_mut(&mut dma));
+/// The DMA channels the firmware isn't using, as a bitmask.
+pub struct GetDmaChannels;
+impl Tag for GetDmaChannels {
+    const ID: u32 = 0x0006_0001;
+    type Request = ();
+    type Response = u32;
+}
+
+pub fn query() -> Result<DmaChannels, MailboxError> {
+    let dma = mailbox::query::<GetDmaChannels>(())?;
     let mut channels = DmaChannels {
         channels: [false; 16],
     };
     for i in 0..16 {
-        channels.channels[i] = (dma[0] & (1 << i)) != 0;
+        channels.channels[i] = (dma & (1 << i)) != 0;
     }
-    channels
+    Ok(channels)
 }
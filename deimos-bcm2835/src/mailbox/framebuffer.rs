@@ -0,0 +1,127 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::mailbox::{MailboxError, Message, Tag};
+
+/// A width and height in pixels.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+unsafe impl Zeroable for Size {}
+unsafe impl Pod for Size {}
+
+/// Bus address and size in bytes of the buffer the firmware set aside.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Buffer {
+    pub base: u32,
+    pub size: u32,
+}
+unsafe impl Zeroable for Buffer {}
+unsafe impl Pod for Buffer {}
+
+/// Takes the required alignment in bytes.
+pub struct AllocateBuffer;
+impl Tag for AllocateBuffer {
+    const ID: u32 = 0x0004_0001;
+    type Request = u32;
+    type Response = Buffer;
+}
+
+pub struct ReleaseBuffer;
+impl Tag for ReleaseBuffer {
+    const ID: u32 = 0x0004_8001;
+    type Request = ();
+    type Response = ();
+}
+
+/// The size of the display.
+pub struct SetPhysicalSize;
+impl Tag for SetPhysicalSize {
+    const ID: u32 = 0x0004_8003;
+    type Request = Size;
+    type Response = Size;
+}
+
+/// The size of the buffer, of which the display shows a window.
+pub struct SetVirtualSize;
+impl Tag for SetVirtualSize {
+    const ID: u32 = 0x0004_8004;
+    type Request = Size;
+    type Response = Size;
+}
+
+/// Bits per pixel.
+pub struct SetDepth;
+impl Tag for SetDepth {
+    const ID: u32 = 0x0004_8005;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// 0 for BGR, 1 for RGB.
+pub struct SetPixelOrder;
+impl Tag for SetPixelOrder {
+    const ID: u32 = 0x0004_8006;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// Bytes per row.
+pub struct GetPitch;
+impl Tag for GetPitch {
+    const ID: u32 = 0x0004_0008;
+    type Request = ();
+    type Response = u32;
+}
+
+/// Where the display's window into the virtual buffer starts.
+pub struct SetVirtualOffset;
+impl Tag for SetVirtualOffset {
+    const ID: u32 = 0x0004_8009;
+    type Request = Size;
+    type Response = Size;
+}
+
+pub const PIXEL_ORDER_BGR: u32 = 0;
+pub const PIXEL_ORDER_RGB: u32 = 1;
+
+/// What the firmware actually gave us, which may not be quite what was asked for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Allocation {
+    pub size: Size,
+    pub depth: u32,
+    pub pixel_order: u32,
+    pub pitch: u32,
+    pub buffer: Buffer,
+}
+
+/// Set the display mode and allocate a framebuffer for it, in one message. The virtual size is
+/// the same as the physical size.
+pub fn allocate(size: Size, depth: u32, pixel_order: u32) -> Result<Allocation, MailboxError> {
+    let mut message = Message::new();
+    let physical = message.push::<SetPhysicalSize>(size);
+    let _ = message.push::<SetVirtualSize>(size);
+    let _ = message.push::<SetVirtualOffset>(Size {
+        width: 0,
+        height: 0,
+    });
+    let depth = message.push::<SetDepth>(depth);
+    let pixel_order = message.push::<SetPixelOrder>(pixel_order);
+    let buffer = message.push::<AllocateBuffer>(16);
+    let pitch = message.push::<GetPitch>(());
+    let reply = message.send()?;
+    Ok(Allocation {
+        size: reply.get(physical)?,
+        depth: reply.get(depth)?,
+        pixel_order: reply.get(pixel_order)?,
+        pitch: reply.get(pitch)?,
+        buffer: reply.get(buffer)?,
+    })
+}
+
+pub fn release() -> Result<(), MailboxError> {
+    crate::mailbox::query::<ReleaseBuffer>(())
+}
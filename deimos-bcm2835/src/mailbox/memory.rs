@@ -0,0 +1,178 @@
+use core::ptr::NonNull;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    addr::{BusAddr, PhysAddr},
+    mailbox::{self, MailboxError, Tag},
+};
+
+// Memory handed out by the VideoCore from its own share of SDRAM (see `GetVcMemory`). An
+// allocation is named by a handle, and only has an address while it is locked: the firmware is
+// free to move it around in between.
+
+/// The firmware may throw the contents away while the allocation is unlocked.
+pub const MEM_FLAG_DISCARDABLE: u32 = 1 << 0;
+/// Bus addresses in the `0x0` (L1 and L2 cached) alias.
+pub const MEM_FLAG_NORMAL: u32 = 0 << 2;
+/// Bus addresses in the `0xc` (uncached) alias.
+pub const MEM_FLAG_DIRECT: u32 = 1 << 2;
+/// Bus addresses in the `0x8` (L2 cached, coherent with the DMA engine) alias.
+pub const MEM_FLAG_COHERENT: u32 = 2 << 2;
+/// Bus addresses in the `0x4` (L2 cached, not L1 allocating) alias.
+pub const MEM_FLAG_L1_NONALLOCATING: u32 = MEM_FLAG_DIRECT | MEM_FLAG_COHERENT;
+/// Zero the memory when it is allocated.
+pub const MEM_FLAG_ZERO: u32 = 1 << 4;
+/// Don't initialise the memory at all, not even from a previous owner's discarded contents.
+pub const MEM_FLAG_NO_INIT: u32 = 1 << 5;
+/// The allocation is likely to stay locked, so the firmware should put it out of the way.
+pub const MEM_FLAG_HINT_PERMALOCK: u32 = 1 << 6;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AllocationRequest {
+    pub size: u32,
+    pub align: u32,
+    pub flags: u32,
+}
+unsafe impl Zeroable for AllocationRequest {}
+unsafe impl Pod for AllocationRequest {}
+
+/// Returns a handle, or 0 if the firmware couldn't satisfy the request.
+pub struct AllocateMemory;
+impl Tag for AllocateMemory {
+    const ID: u32 = 0x0003_000c;
+    type Request = AllocationRequest;
+    type Response = u32;
+}
+
+/// Takes a handle and returns the allocation's bus address, or 0 if the handle is bad.
+pub struct LockMemory;
+impl Tag for LockMemory {
+    const ID: u32 = 0x0003_000d;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// Takes a handle and returns 0 on success.
+pub struct UnlockMemory;
+impl Tag for UnlockMemory {
+    const ID: u32 = 0x0003_000e;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// Takes a handle and returns 0 on success. Unlocks the allocation first if need be.
+pub struct ReleaseMemory;
+impl Tag for ReleaseMemory {
+    const ID: u32 = 0x0003_000f;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// A block of VideoCore memory, released when dropped.
+///
+/// It is locked when it is allocated, which is when it has an address that both the ARM and the
+/// DMA engine can use. It can be unlocked and locked again, but may well move in the meantime.
+#[derive(Debug)]
+pub struct VcBuffer {
+    handle: u32,
+    size: usize,
+    bus: Option<BusAddr>,
+}
+
+// Nothing about the buffer is tied to a particular context; the mailbox takes care of itself.
+unsafe impl Send for VcBuffer {}
+
+impl VcBuffer {
+    /// Allocate `size` bytes aligned to `align` with the given `MEM_FLAG_*`s, and lock them.
+    pub fn allocate(size: usize, align: usize, flags: u32) -> Result<Self, MailboxError> {
+        let request = AllocationRequest {
+            size: u32::try_from(size).map_err(|_| MailboxError::Refused {
+                tag: AllocateMemory::ID,
+            })?,
+            align: align as u32,
+            flags,
+        };
+        let handle = mailbox::query::<AllocateMemory>(request)?;
+        if handle == 0 {
+            return Err(MailboxError::Refused {
+                tag: AllocateMemory::ID,
+            });
+        }
+        let mut buffer = Self {
+            handle,
+            size,
+            bus: None,
+        };
+        // if locking fails, dropping the buffer releases it
+        buffer.lock()?;
+        Ok(buffer)
+    }
+
+    /// Lock the buffer in place, if it isn't already, and return its bus address.
+    pub fn lock(&mut self) -> Result<BusAddr, MailboxError> {
+        if let Some(bus) = self.bus {
+            return Ok(bus);
+        }
+        let bus = mailbox::query::<LockMemory>(self.handle)?;
+        if bus == 0 {
+            return Err(MailboxError::Refused {
+                tag: LockMemory::ID,
+            });
+        }
+        let bus = BusAddr::new(bus);
+        self.bus = Some(bus);
+        Ok(bus)
+    }
+
+    /// Let the firmware move the buffer around (or, if it is discardable, throw its contents
+    /// away) until it is next locked.
+    pub fn unlock(&mut self) -> Result<(), MailboxError> {
+        if self.bus.is_none() {
+            return Ok(());
+        }
+        if mailbox::query::<UnlockMemory>(self.handle)? != 0 {
+            return Err(MailboxError::Refused {
+                tag: UnlockMemory::ID,
+            });
+        }
+        self.bus = None;
+        Ok(())
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn is_locked(&self) -> bool {
+        self.bus.is_some()
+    }
+
+    /// Where the VideoCore and the DMA engine see the buffer, while it is locked.
+    pub fn bus_addr(&self) -> Option<BusAddr> {
+        self.bus
+    }
+
+    /// Where the ARM sees the buffer, while it is locked.
+    pub fn phys_addr(&self) -> Option<PhysAddr> {
+        let phys = self.bus?.to_phys();
+        debug_assert!(phys.is_some(), "VC memory should be in SDRAM");
+        phys
+    }
+
+    /// The buffer as the ARM sees it, while it is locked.
+    pub fn as_ptr(&self) -> Option<NonNull<u8>> {
+        NonNull::new(self.phys_addr()?.as_ptr())
+    }
+}
+
+impl Drop for VcBuffer {
+    fn drop(&mut self) {
+        // release unlocks the buffer too, and if it fails there's nothing more to be done: the
+        // memory stays with the VideoCore
+        let _ = mailbox::query::<ReleaseMemory>(self.handle);
+    }
+}
@@ -0,0 +1,66 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::mailbox::{self, MailboxError, Tag};
+
+/// Power domain ids, as used by the power property tags.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PowerDomain {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// A power domain and its state.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PowerState {
+    pub id: u32,
+    pub state: u32,
+}
+unsafe impl Zeroable for PowerState {}
+unsafe impl Pod for PowerState {}
+
+const STATE_ON: u32 = 1 << 0;
+/// In responses: the domain doesn't exist.
+const STATE_MISSING: u32 = 1 << 1;
+/// In requests: don't answer until the domain is stable.
+const STATE_WAIT: u32 = 1 << 1;
+
+/// Takes a [`PowerDomain`].
+pub struct GetPowerState;
+impl Tag for GetPowerState {
+    const ID: u32 = 0x0002_0001;
+    type Request = u32;
+    type Response = PowerState;
+}
+
+pub struct SetPowerState;
+impl Tag for SetPowerState {
+    const ID: u32 = 0x0002_8001;
+    type Request = PowerState;
+    type Response = PowerState;
+}
+
+/// Whether the domain is powered, or `None` if this board doesn't have it.
+pub fn is_on(domain: PowerDomain) -> Result<Option<bool>, MailboxError> {
+    let state = mailbox::query::<GetPowerState>(domain as u32)?.state;
+    Ok((state & STATE_MISSING == 0).then_some(state & STATE_ON != 0))
+}
+
+/// Power the domain up or down, waiting for it to settle, and return whether it is on afterwards,
+/// or `None` if this board doesn't have it.
+pub fn set_on(domain: PowerDomain, on: bool) -> Result<Option<bool>, MailboxError> {
+    let request = PowerState {
+        id: domain as u32,
+        state: STATE_WAIT | if on { STATE_ON } else { 0 },
+    };
+    let state = mailbox::query::<SetPowerState>(request)?.state;
+    Ok((state & STATE_MISSING == 0).then_some(state & STATE_ON != 0))
+}
@@ -1,26 +1,65 @@
-use crate::mailbox;
+use crate::mailbox::{self, MailboxError, Tag};
 use core::fmt::{Display, Formatter};
 
-pub fn query() -> BoardRevision {
-    let mut buf = [0u8; 4];
-    mailbox::send_message(0x0001_0002, &mut buf);
-    BoardRevision::from(u32::from_ne_bytes(buf))
+pub struct GetBoardRevision;
+impl Tag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    type Request = ();
+    type Response = u32;
 }
 
+pub fn query() -> Result<BoardRevision, RevisionError> {
+    let code = mailbox::query::<GetBoardRevision>(())?;
+    BoardRevision::try_from(code)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RevisionError {
+    Mailbox(MailboxError),
+    /// An old-style revision code that isn't in the table.
+    UnknownCode(u32),
+}
+impl Display for RevisionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Mailbox(e) => write!(f, "{e}"),
+            Self::UnknownCode(code) => write!(f, "unknown old-style revision code {code:#06x}"),
+        }
+    }
+}
+impl core::error::Error for RevisionError {}
+impl From<MailboxError> for RevisionError {
+    fn from(e: MailboxError) -> Self {
+        Self::Mailbox(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum BoardModel {
-    A = 0,
-    B = 1,
-    APlus = 2,
-    BPlus = 3,
-    _2B = 4,
-    Alpha = 5,
-    CM1 = 6,
-    _3B = 8,
-    Zero = 9,
-    CM3 = 10,
-    ZeroW = 12,
-    _3BPlus = 13,
-    _3APlus = 14,
+    A,
+    B,
+    APlus,
+    BPlus,
+    _2B,
+    Alpha,
+    CM1,
+    _3B,
+    Zero,
+    CM3,
+    ZeroW,
+    _3BPlus,
+    _3APlus,
+    CM3Plus,
+    _4B,
+    Zero2W,
+    _400,
+    CM4,
+    CM4S,
+    _5,
+    CM5,
+    _500,
+    CM5Lite,
+    Unknown(u32),
 }
 impl Display for BoardModel {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
@@ -41,6 +80,17 @@ impl Display for BoardModel {
                 Self::ZeroW => "Model Zero W",
                 Self::_3BPlus => "Model 3B+",
                 Self::_3APlus => "Model 3A+",
+                Self::CM3Plus => "Model CM3+",
+                Self::_4B => "Model 4B",
+                Self::Zero2W => "Model Zero 2 W",
+                Self::_400 => "Model 400",
+                Self::CM4 => "Model CM4",
+                Self::CM4S => "Model CM4S",
+                Self::_5 => "Model 5",
+                Self::CM5 => "Model CM5",
+                Self::_500 => "Model 500",
+                Self::CM5Lite => "Model CM5 Lite",
+                Self::Unknown(x) => return write!(f, "unknown model {x:#x}"),
             }
         )
     }
@@ -48,27 +98,41 @@ impl Display for BoardModel {
 impl From<u32> for BoardModel {
     fn from(value: u32) -> Self {
         match value {
-            0 => BoardModel::A,
-            1 => BoardModel::B,
-            2 => BoardModel::APlus,
-            3 => BoardModel::BPlus,
-            4 => BoardModel::_2B,
-            5 => BoardModel::Alpha,
-            6 => BoardModel::CM1,
-            8 => BoardModel::_3B,
-            9 => BoardModel::Zero,
-            10 => BoardModel::CM3,
-            12 => BoardModel::ZeroW,
-            13 => BoardModel::_3BPlus,
-            14 => BoardModel::_3APlus,
-            x => panic!("unknown board model: {}", x),
+            0x00 => BoardModel::A,
+            0x01 => BoardModel::B,
+            0x02 => BoardModel::APlus,
+            0x03 => BoardModel::BPlus,
+            0x04 => BoardModel::_2B,
+            0x05 => BoardModel::Alpha,
+            0x06 => BoardModel::CM1,
+            0x08 => BoardModel::_3B,
+            0x09 => BoardModel::Zero,
+            0x0a => BoardModel::CM3,
+            0x0c => BoardModel::ZeroW,
+            0x0d => BoardModel::_3BPlus,
+            0x0e => BoardModel::_3APlus,
+            0x10 => BoardModel::CM3Plus,
+            0x11 => BoardModel::_4B,
+            0x12 => BoardModel::Zero2W,
+            0x13 => BoardModel::_400,
+            0x14 => BoardModel::CM4,
+            0x15 => BoardModel::CM4S,
+            0x17 => BoardModel::_5,
+            0x18 => BoardModel::CM5,
+            0x19 => BoardModel::_500,
+            0x1a => BoardModel::CM5Lite,
+            x => BoardModel::Unknown(x),
         }
     }
 }
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum BoardProcessor {
-    Bcm2835 = 0,
-    Bcm2836 = 1,
-    Bcm2837 = 2,
+    Bcm2835,
+    Bcm2836,
+    Bcm2837,
+    Bcm2711,
+    Bcm2712,
+    Unknown(u32),
 }
 impl Display for BoardProcessor {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
@@ -79,6 +143,9 @@ impl Display for BoardProcessor {
                 BoardProcessor::Bcm2835 => "BCM2835",
                 BoardProcessor::Bcm2836 => "BCM2836",
                 BoardProcessor::Bcm2837 => "BCM2837",
+                BoardProcessor::Bcm2711 => "BCM2711",
+                BoardProcessor::Bcm2712 => "BCM2712",
+                BoardProcessor::Unknown(x) => return write!(f, "unknown processor {x}"),
             }
         )
     }
@@ -89,16 +156,22 @@ impl From<u32> for BoardProcessor {
             0 => BoardProcessor::Bcm2835,
             1 => BoardProcessor::Bcm2836,
             2 => BoardProcessor::Bcm2837,
-            x => panic!("unknown processor: {x}"),
+            3 => BoardProcessor::Bcm2711,
+            4 => BoardProcessor::Bcm2712,
+            x => BoardProcessor::Unknown(x),
         }
     }
 }
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum BoardManufacturer {
-    SonyUk = 0,
-    Egoman = 1,
-    Embest = 2,
-    SonyJapan = 3,
-    Stadium = 5,
+    SonyUk,
+    Egoman,
+    Embest,
+    SonyJapan,
+    Stadium,
+    /// Only on old-style revisions.
+    Qisda,
+    Unknown(u32),
 }
 impl Display for BoardManufacturer {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
@@ -108,6 +181,8 @@ impl Display for BoardManufacturer {
             Self::Embest => write!(f, "Embest"),
             Self::SonyJapan => write!(f, "Sony JAPAN"),
             Self::Stadium => write!(f, "Stadium"),
+            Self::Qisda => write!(f, "Qisda"),
+            Self::Unknown(x) => write!(f, "unknown manufacturer {x}"),
         }
     }
 }
@@ -120,43 +195,137 @@ impl From<u32> for BoardManufacturer {
             3 => BoardManufacturer::SonyJapan,
             4 => BoardManufacturer::Embest,
             5 => BoardManufacturer::Stadium,
-            x => panic!("unknown manufacturer: {x}"),
+            x => BoardManufacturer::Unknown(x),
         }
     }
 }
+
+// New-style revision codes are bit fields:
+//
+//   NOQu uuWu FMMM CCCC PPPP TTTT TTTT RRRR
+//
+// N: overvoltage disallowed, O: OTP programming disallowed, Q: OTP reading disallowed,
+// W: warranty void, F: new-style flag, M: memory size, C: manufacturer, P: processor, T: model,
+// R: revision. Old-style codes are just an index into a table, with bit 24 set if the warranty
+// is void.
+const NEW_STYLE: u32 = 1 << 23;
+const WARRANTY_VOID: u32 = 1 << 25;
+const OLD_STYLE_WARRANTY_VOID: u32 = 1 << 24;
+const OTP_READ_DISALLOWED: u32 = 1 << 29;
+const OTP_PROGRAM_DISALLOWED: u32 = 1 << 30;
+const OVERVOLTAGE_DISALLOWED: u32 = 1 << 31;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct BoardRevision {
+    /// The code the firmware reported.
+    pub code: u32,
     pub model: BoardModel,
     pub processor: BoardProcessor,
+    pub revision_major: u8,
     pub revision_minor: u8,
-    pub ram_mb: u16,
+    pub ram_mb: u32,
     pub manufacturer: BoardManufacturer,
+    /// The board has been overclocked or overvolted.
+    pub warranty_void: bool,
+    pub otp_read_disallowed: bool,
+    pub otp_program_disallowed: bool,
+    pub overvoltage_disallowed: bool,
 }
 impl Display for BoardRevision {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "model:{},proc:{},rev:1.{},ram:{}MiB,manufacturer:{}",
-            self.model, self.processor, self.revision_minor, self.ram_mb, self.manufacturer
-        )
+            "model:{},proc:{},rev:{}.{},ram:{}MiB,manufacturer:{}",
+            self.model,
+            self.processor,
+            self.revision_major,
+            self.revision_minor,
+            self.ram_mb,
+            self.manufacturer
+        )?;
+        if self.warranty_void {
+            write!(f, ",warranty void")?;
+        }
+        Ok(())
     }
 }
-impl From<u32> for BoardRevision {
-    fn from(revision: u32) -> Self {
-        if (revision & 0x0080_0000) == 0 {
-            panic!("<BoardRevision as From<u32>>::from() does not support old-style revisions")
+
+// Old-style revision codes: code, model, revision, RAM size, manufacturer. All of them are
+// BCM2835 boards.
+type OldStyle = (u32, BoardModel, (u8, u8), u32, BoardManufacturer);
+#[rustfmt::skip]
+const OLD_STYLE: [OldStyle; 17] = {
+    use BoardManufacturer::*;
+    use BoardModel::*;
+    [
+        (0x0002, B, (1, 0), 256, Egoman),
+        (0x0003, B, (1, 0), 256, Egoman),
+        (0x0004, B, (2, 0), 256, SonyUk),
+        (0x0005, B, (2, 0), 256, Qisda),
+        (0x0006, B, (2, 0), 256, Egoman),
+        (0x0007, A, (2, 0), 256, Egoman),
+        (0x0008, A, (2, 0), 256, SonyUk),
+        (0x0009, A, (2, 0), 256, Qisda),
+        (0x000d, B, (2, 0), 512, Egoman),
+        (0x000e, B, (2, 0), 512, SonyUk),
+        (0x000f, B, (2, 0), 512, Egoman),
+        (0x0010, BPlus, (1, 2), 512, SonyUk),
+        (0x0011, CM1, (1, 0), 512, SonyUk),
+        (0x0012, APlus, (1, 1), 256, SonyUk),
+        (0x0013, BPlus, (1, 2), 512, Embest),
+        (0x0014, CM1, (1, 0), 512, Embest),
+        (0x0015, APlus, (1, 1), 256, Embest),
+    ]
+};
+
+impl TryFrom<u32> for BoardRevision {
+    type Error = RevisionError;
+
+    /// Only fails for old-style codes that aren't in the table; new-style codes always decode,
+    /// with `Unknown` for any field this doesn't know about.
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        let otp_read_disallowed = code & OTP_READ_DISALLOWED != 0;
+        let otp_program_disallowed = code & OTP_PROGRAM_DISALLOWED != 0;
+        let overvoltage_disallowed = code & OVERVOLTAGE_DISALLOWED != 0;
+
+        if code & NEW_STYLE == 0 {
+            let index = code & !OLD_STYLE_WARRANTY_VOID;
+            let &(_, model, (revision_major, revision_minor), ram_mb, manufacturer) = OLD_STYLE
+                .iter()
+                .find(|(old, ..)| *old == index)
+                .ok_or(RevisionError::UnknownCode(code))?;
+            return Ok(Self {
+                code,
+                model,
+                processor: BoardProcessor::Bcm2835,
+                revision_major,
+                revision_minor,
+                ram_mb,
+                manufacturer,
+                warranty_void: code & OLD_STYLE_WARRANTY_VOID != 0,
+                otp_read_disallowed,
+                otp_program_disallowed,
+                overvoltage_disallowed,
+            });
         }
 
-        let model = (revision >> 4) & 0xff;
-        let proc = (revision >> 12) & 0xf;
-        let rev_minor = revision & 0xf;
-        let ram_mb = 256 << ((revision >> 20) & 7);
-        let manufacturer = (revision >> 16) & 0xf;
-        Self {
+        let model = (code >> 4) & 0xff;
+        let proc = (code >> 12) & 0xf;
+        let rev_minor = code & 0xf;
+        let ram_mb = 256 << ((code >> 20) & 7);
+        let manufacturer = (code >> 16) & 0xf;
+        Ok(Self {
+            code,
             model: BoardModel::from(model),
             processor: BoardProcessor::from(proc),
+            revision_major: 1,
             revision_minor: rev_minor as u8,
-            ram_mb: ram_mb as u16,
+            ram_mb,
             manufacturer: BoardManufacturer::from(manufacturer),
-        }
+            warranty_void: code & WARRANTY_VOID != 0,
+            otp_read_disallowed,
+            otp_program_disallowed,
+            overvoltage_disallowed,
+        })
     }
 }
@@ -0,0 +1,66 @@
+use crate::mailbox::{
+    framebuffer::{self, PIXEL_ORDER_BGR, Size},
+    revision::{BoardManufacturer, BoardModel, BoardProcessor, BoardRevision, RevisionError},
+};
+
+#[test_case]
+fn allocates_framebuffer() {
+    // the same mode as the framebuffer console, so that one isn't disturbed
+    let size = Size {
+        width: 640,
+        height: 480,
+    };
+    let allocation = framebuffer::allocate(size, 32, PIXEL_ORDER_BGR).unwrap();
+    assert_eq!(allocation.size, size);
+    assert_eq!(allocation.depth, 32);
+    assert_eq!(allocation.pixel_order, PIXEL_ORDER_BGR);
+    assert!(allocation.pitch >= size.width * 4);
+    assert_ne!(allocation.buffer.base, 0);
+    assert!(allocation.buffer.size >= allocation.pitch * size.height);
+}
+
+#[test_case]
+fn decodes_old_style_revision() {
+    let revision = BoardRevision::try_from(0x0002).unwrap();
+    assert_eq!(revision.model, BoardModel::B);
+    assert_eq!(revision.processor, BoardProcessor::Bcm2835);
+    assert_eq!((revision.revision_major, revision.revision_minor), (1, 0));
+    assert_eq!(revision.ram_mb, 256);
+    assert_eq!(revision.manufacturer, BoardManufacturer::Egoman);
+    assert!(!revision.warranty_void);
+}
+
+#[test_case]
+fn decodes_old_style_warranty_bit() {
+    let revision = BoardRevision::try_from(0x100_0002).unwrap();
+    assert_eq!(revision.code, 0x100_0002);
+    assert_eq!(revision.model, BoardModel::B);
+    assert!(revision.warranty_void);
+}
+
+#[test_case]
+fn decodes_new_style_revision() {
+    // Zero W
+    let revision = BoardRevision::try_from(0x90_00c1).unwrap();
+    assert_eq!(revision.model, BoardModel::ZeroW);
+    assert_eq!(revision.processor, BoardProcessor::Bcm2835);
+    assert_eq!((revision.revision_major, revision.revision_minor), (1, 1));
+    assert_eq!(revision.ram_mb, 512);
+    assert_eq!(revision.manufacturer, BoardManufacturer::SonyUk);
+    assert!(!revision.warranty_void);
+
+    // 4B with 4GiB
+    let revision = BoardRevision::try_from(0xc0_3111).unwrap();
+    assert_eq!(revision.model, BoardModel::_4B);
+    assert_eq!(revision.processor, BoardProcessor::Bcm2711);
+    assert_eq!(revision.ram_mb, 4096);
+    assert_eq!(revision.manufacturer, BoardManufacturer::SonyUk);
+}
+
+#[test_case]
+fn rejects_unknown_old_style_revision() {
+    assert_eq!(
+        BoardRevision::try_from(0x0001),
+        Err(RevisionError::UnknownCode(0x0001))
+    );
+}
@@ -0,0 +1,108 @@
+use bytemuck::{Pod, Zeroable};
+use core::fmt::{Display, Formatter};
+
+use crate::mailbox::{self, MailboxError, Tag};
+
+/// The SoC's temperature sensor. There is only the one, with id 0.
+const SENSOR_ID: u32 = 0;
+
+/// A temperature sensor and its reading in thousandths of a degree Celsius.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Temperature {
+    pub id: u32,
+    pub value: u32,
+}
+unsafe impl Zeroable for Temperature {}
+unsafe impl Pod for Temperature {}
+
+/// Takes a sensor id.
+pub struct GetTemperature;
+impl Tag for GetTemperature {
+    const ID: u32 = 0x0003_0006;
+    type Request = u32;
+    type Response = Temperature;
+}
+
+/// The temperature at which the firmware starts throttling. Takes a sensor id.
+pub struct GetMaxTemperature;
+impl Tag for GetMaxTemperature {
+    const ID: u32 = 0x0003_000a;
+    type Request = u32;
+    type Response = Temperature;
+}
+
+pub struct GetThrottled;
+impl Tag for GetThrottled {
+    const ID: u32 = 0x0003_0046;
+    type Request = u32;
+    type Response = u32;
+}
+
+/// SoC temperature in thousandths of a degree Celsius.
+pub fn temperature() -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetTemperature>(SENSOR_ID)?.value)
+}
+
+/// Throttling threshold in thousandths of a degree Celsius.
+pub fn max_temperature() -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetMaxTemperature>(SENSOR_ID)?.value)
+}
+
+pub fn throttled() -> Result<Throttled, MailboxError> {
+    mailbox::query::<GetThrottled>(0).map(Throttled)
+}
+
+/// What the firmware is doing, or has done since boot, to protect the board. The low bits are
+/// the current state, and the same bits shifted up by 16 are sticky versions of them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Throttled(pub u32);
+
+impl Throttled {
+    const UNDER_VOLTAGE: u32 = 1 << 0;
+    const ARM_FREQ_CAPPED: u32 = 1 << 1;
+    const THROTTLED: u32 = 1 << 2;
+    const SOFT_TEMP_LIMIT: u32 = 1 << 3;
+    const OCCURRED_SHIFT: u32 = 16;
+    const NAMES: [(u32, &str); 4] = [
+        (Self::UNDER_VOLTAGE, "under-voltage"),
+        (Self::ARM_FREQ_CAPPED, "ARM frequency capped"),
+        (Self::THROTTLED, "throttled"),
+        (Self::SOFT_TEMP_LIMIT, "soft temperature limit"),
+    ];
+
+    pub fn under_voltage(&self) -> bool {
+        self.0 & Self::UNDER_VOLTAGE != 0
+    }
+    pub fn arm_freq_capped(&self) -> bool {
+        self.0 & Self::ARM_FREQ_CAPPED != 0
+    }
+    pub fn throttled(&self) -> bool {
+        self.0 & Self::THROTTLED != 0
+    }
+    pub fn soft_temp_limit(&self) -> bool {
+        self.0 & Self::SOFT_TEMP_LIMIT != 0
+    }
+    /// Whether any of the conditions have happened since boot, even if they have since cleared.
+    pub fn has_occurred(&self) -> bool {
+        self.0 >> Self::OCCURRED_SHIFT != 0
+    }
+}
+
+impl Display for Throttled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut any = false;
+        for (shift, suffix) in [(0, ""), (Self::OCCURRED_SHIFT, " (since boot)")] {
+            for (bit, name) in Self::NAMES {
+                if self.0 & (bit << shift) != 0 {
+                    if any {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{name}{suffix}")?;
+                    any = true;
+                }
+            }
+        }
+        if !any { write!(f, "<none>") } else { Ok(()) }
+    }
+}
@@ -0,0 +1,76 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::mailbox::{self, MailboxError, Tag};
+
+/// Voltage ids, as used by the voltage property tags.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VoltageId {
+    Core = 1,
+    SdramC = 2,
+    SdramP = 3,
+    SdramI = 4,
+}
+
+/// A voltage and its value. The tag documentation describes the value as an offset from 1.2V in
+/// 25mV steps, but current firmware answers in microvolts.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Voltage {
+    pub id: u32,
+    pub value: u32,
+}
+unsafe impl Zeroable for Voltage {}
+unsafe impl Pod for Voltage {}
+
+/// Takes a [`VoltageId`].
+pub struct GetVoltage;
+impl Tag for GetVoltage {
+    const ID: u32 = 0x0003_0003;
+    type Request = u32;
+    type Response = Voltage;
+}
+
+pub struct SetVoltage;
+impl Tag for SetVoltage {
+    const ID: u32 = 0x0003_8003;
+    type Request = Voltage;
+    type Response = Voltage;
+}
+
+/// Takes a [`VoltageId`].
+pub struct GetMaxVoltage;
+impl Tag for GetMaxVoltage {
+    const ID: u32 = 0x0003_0005;
+    type Request = u32;
+    type Response = Voltage;
+}
+
+/// Takes a [`VoltageId`].
+pub struct GetMinVoltage;
+impl Tag for GetMinVoltage {
+    const ID: u32 = 0x0003_0008;
+    type Request = u32;
+    type Response = Voltage;
+}
+
+pub fn voltage(id: VoltageId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetVoltage>(id as u32)?.value)
+}
+
+pub fn max_voltage(id: VoltageId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetMaxVoltage>(id as u32)?.value)
+}
+
+pub fn min_voltage(id: VoltageId) -> Result<u32, MailboxError> {
+    Ok(mailbox::query::<GetMinVoltage>(id as u32)?.value)
+}
+
+/// Ask for a new voltage, and return the one the firmware settled on.
+pub fn set_voltage(id: VoltageId, value: u32) -> Result<u32, MailboxError> {
+    let request = Voltage {
+        id: id as u32,
+        value,
+    };
+    Ok(mailbox::query::<SetVoltage>(request)?.value)
+}
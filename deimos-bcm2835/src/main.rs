@@ -2,7 +2,11 @@
 #![feature(pointer_is_aligned_to)]
 #![feature(slice_ptr_get)]
 #![feature(alloc_layout_extra)]
+#![feature(allocator_api)]
 #![feature(sync_unsafe_cell)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner::run)]
+#![reexport_test_harness_main = "test_main"]
 #![no_std]
 #![no_main]
 #![allow(dead_code)]
@@ -11,69 +15,161 @@ extern crate alloc;
 
 use bcm2835_lpa::Peripherals;
 
+mod addr;
 mod alloc_support;
 mod arch;
+mod bench;
+mod board;
+mod boot;
+mod cache;
+mod config;
 mod coprocessor;
 mod critical_section;
 mod dma;
+mod exit;
+mod framebuffer;
+mod interrupts;
 mod mailbox;
 mod mmu_support;
+mod pl011;
 mod print;
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "semihosting")]
+mod semihosting;
 mod start;
+#[cfg(test)]
+mod test_runner;
 mod timing;
 mod uart;
 mod watchdog;
 
 #[unsafe(no_mangle)]
-pub extern "C" fn __kernel_start() -> ! {
+pub extern "C" fn __kernel_start(r0: u32, machine_type: u32, atags_or_dtb: u32) -> ! {
     let peri = unsafe { Peripherals::steal() };
 
-    timing::delay_millis(&peri.SYSTMR, 100);
+    // The PAC only finds the peripherals on boards other than the BCM2835 once the MMU has mapped
+    // them where it expects, so this comes before anything else. Where they are comes from the
+    // device tree, if there is one.
+    let boot = unsafe { boot::init([r0, machine_type, atags_or_dtb]) };
+    let board = unsafe { board::init(boot.peripheral_base) };
+    unsafe { mmu_support::init() };
+    // the command line may have to come from the mailbox, which needs the MMU
+    let config = unsafe { config::init() };
+    unsafe { mmu_support::set_mmu_enabled_features(config.cache) };
 
-    uart::init(
-        &peri.GPIO,
-        &peri.AUX,
-        &peri.UART1,
-        uart::baud_to_clock_divider(115200),
-    );
+    timing::delay_millis(&peri.SYSTMR, 100);
 
-    unsafe extern "C" {
-        static __exec_end: [u32; 0];
+    // The command line beats everything. Otherwise UART1 is the remote link, and a feature
+    // choosing the console beats the firmware.
+    if let Some(console) = config.console.or(boot
+        .console
+        .filter(|_| !cfg!(any(feature = "remote", feature = "uart0-console"))))
+    {
+        print::select_console(console.uart, console.baud.unwrap_or(print::console_baud()));
     }
-    println!("executable area: 0..{:08x}", (&raw const __exec_end).addr());
-
-    unsafe {
-        mmu_support::init();
-        mmu_support::set_mmu_enabled_features(mmu_support::MmuConfig {
-            dcache: Some(false),
-            icache: Some(false),
-            brpdx: Some(false),
-        });
+    let console_baud = print::init_console(&peri);
+    interrupts::init();
+    if print::console_uart() == print::ConsoleUart::Uart1 {
+        uart::enable_buffered(&peri.UART1);
     }
+    interrupts::enable();
 
-    alloc_support::heap_init();
+    alloc_support::heap_init(&[dma::pool::reservation(config.dma_pool_size)]);
+    dma::pool::init();
+    if let Err(e) = mailbox::init_dma_buffer() {
+        println!("mailbox buffer not moved to the DMA pool: {e}");
+    }
+
+    #[cfg(feature = "framebuffer-console")]
+    match framebuffer::Framebuffer::allocate(640, 480) {
+        Ok(mut fb) => {
+            // scrolling a whole screen on the CPU is slow enough to hold up the UART
+            fb.set_dma_channel(
+                mailbox::dma_channels::query()
+                    .expect("mailbox should answer")
+                    .iter()
+                    .find(|c| (4..7).contains(c)),
+            );
+            framebuffer::console::mirror(framebuffer::console::TextConsole::new(
+                fb,
+                framebuffer::console::DEFAULT_FOREGROUND,
+                framebuffer::console::DEFAULT_BACKGROUND,
+            ));
+        }
+        Err(e) => println!("no framebuffer: {e}"),
+    }
 
     println!();
     println!("UART is up. {} booted.", env!("CARGO_BIN_NAME"));
+    match (print::console_uart(), console_baud) {
+        (_, Err(e)) => println!(
+            "UART0 unusable ({e}), console on UART1 at {}",
+            uart::baud_rate()
+        ),
+        (print::ConsoleUart::Uart0, Ok(baud)) => println!(
+            "console on UART0 at {baud} baud (asked for {})",
+            print::console_baud()
+        ),
+        (print::ConsoleUart::Uart1, Ok(_)) => {
+            println!("console on UART1 at {}", uart::baud_rate())
+        }
+    }
+    println!(
+        "running as {} (peripherals at {:08x})",
+        board.processor, board.peripheral_base
+    );
+    match boot.source {
+        boot::Source::DeviceTree | boot::Source::Atags => {
+            let blob = boot.blob.expect("a recognized blob has a location");
+            println!(
+                "boot info: {:?} at {:08x} ({} bytes)",
+                boot.source, blob.base, blob.size
+            );
+        }
+        boot::Source::None => match boot.error {
+            Some(e) => println!("boot info: bad device tree at {atags_or_dtb:08x}: {e}"),
+            None => println!("boot info: none (r2 = {atags_or_dtb:08x})"),
+        },
+    }
+    if let Some(memory) = boot.memory {
+        println!("ARM memory: {:08x}..{:08x}", memory.base, memory.end());
+    }
+    config::report();
+    match mailbox::revision::query() {
+        Ok(rev) if rev.processor != board.processor => println!(
+            "warning: the firmware says this is a {} with a {}",
+            rev.model, rev.processor
+        ),
+        Ok(_) => {}
+        Err(e) => println!("warning: couldn't identify the board: {e}"),
+    }
+
+    #[cfg(test)]
+    if config.suites.contains(config::Suites::TESTS) {
+        test_main();
+    }
 
     println!();
     mailbox::dump_configuration();
+    alloc_support::dump_memory_map();
 
     println!();
-    dma::run_all(&peri);
-
-    watchdog::restart(&peri.PM);
+    #[cfg(feature = "remote")]
+    remote::serve(&peri);
+    #[cfg(not(feature = "remote"))]
+    {
+        dma::run_all(&peri, &config);
+        exit::exit(0);
+    }
 }
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     let peri = unsafe { Peripherals::steal() };
-    uart::init(
-        &peri.GPIO,
-        &peri.AUX,
-        &peri.UART1,
-        uart::baud_to_clock_divider(115200),
-    );
+    // write out whatever was still queued, then go back to polling so that the message gets out
+    // even if we panicked with interrupts masked
+    print::recover_console(&peri);
     if let Some(loc) = info.location() {
         println!(
             "Panic occurred at file '{}' line {}:\n",
@@ -84,13 +180,16 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
         println!("Panic occurred at unknown location.\n");
     }
     let msg = info.message();
-    let mut proxy = print::UartProxy::new(&peri.UART1);
-    let _ = core::fmt::write(&mut proxy, format_args!("{}\n", msg));
+    let mut console = print::Console::get();
+    let _ = core::fmt::write(&mut console, format_args!("{}\n", msg));
 
     // wait for UART FIFO to drain
     timing::delay_millis(&peri.SYSTMR, 100);
 
-    watchdog::restart(&peri.PM);
+    #[cfg(test)]
+    test_runner::on_panic();
+
+    exit::exit(101);
 }
 
 #[unsafe(no_mangle)]
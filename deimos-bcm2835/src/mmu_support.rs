@@ -1,4 +1,8 @@
-use crate::arch::dsb;
+use crate::{
+    addr::PERIPHERAL_SIZE,
+    arch::dsb,
+    board::{self, PAC_PERIPHERAL_BASE},
+};
 use core::{arch::asm, cell::SyncUnsafeCell};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -145,11 +149,13 @@ unsafe fn init_mmu(ttb_ptr: *mut u32) {
 
 unsafe fn init_mmu_translation_table(ttb_ptr: *mut u32) {
     // uart1_sendln_bl!("__init_mmu_translation_table({ttb_ptr:p})");
+    // On the BCM2835:
     // 0000_0000..1f00_0000 is ARM SDRAM
     // 1f00_0000..1fff_ffff is VC SDRAM iff configured to support a mmap'd display (that is not the
     //                         case)
     // 2000_0000..20ff_ffff is peripheral memory
     // end of physical memory is 4000_0000 (1GB)
+    // The other boards move the peripherals up, and have more SDRAM below them (see `board`).
     // a full TTB is 16KB, so 4K entries, each entry represents 1MB
     // initially, we map:
     for ttei in 0..0x1000 {
@@ -175,30 +181,44 @@ unsafe fn init_mmu_translation_table(ttb_ptr: *mut u32) {
         }
     }
 
-    //
+    // each supersection is 16 consecutive entries, all pointing at the same 16MiB
+    let map = |ttei: u32, phys: u32, tex: u32, c: u32, b: u32| {
+        let entry_ptr = unsafe { ttb_ptr.offset(ttei as isize) };
+        let val = tt_supersection(phys >> 24, tex, c, b);
+        unsafe { entry_ptr.write_volatile(val) };
+    };
+    let board = board::current();
+    let pac_window = PAC_PERIPHERAL_BASE >> 20..(PAC_PERIPHERAL_BASE + PERIPHERAL_SIZE) >> 20;
 
-    //  0000_0000..1000_0000 to 0000_0000..1000_0000 (256)
-    for si in 0..512 {
-        let ttei = 0x000 + si;
-        let ssi = ((si + 0) >> 4) as u32;
-        let entry_ptr = unsafe { ttb_ptr.offset(ttei) };
+    //  0000_0000..sdram_end to 0000_0000..sdram_end, apart from the PAC's window
+    for si in 0..board.sdram_end >> 20 {
+        if pac_window.contains(&si) {
+            continue;
+        }
         // BB=11, write back, no allocate on write
         // AA=11, write back, no allocate on write
         // let val = tt_supersection(ssi, 0b111, 1, 1); // <-- with TEX Remap OFF
         // TEMP: uncached
         // restore to: 0b001, 0, 1
-        let val = tt_supersection(ssi, 0b001, 0, 0);
-        // uart1_sendln_bl!("BLK1 writing {val:08x} to {entry_ptr:p}");
-        unsafe { entry_ptr.write_volatile(val) };
+        map(si, si << 20, 0b001, 0, 0);
     }
-    //  2000_0000..2100_0000 to 2000_0000..2100_0000 (16)
-    for si in 0..16 {
-        let ttei = 0x200 + si;
-        let ssi = ((si + 512) >> 4) as u32;
-        let entry_ptr = unsafe { ttb_ptr.offset(ttei) };
-        let val = tt_supersection(ssi, 0b000, 0, 1);
-        // uart1_sendln_bl!("DEV writing {val:08x} to {entry_ptr:p}");
-        unsafe { entry_ptr.write_volatile(val) };
+    //  peripheral_base..+16MiB to itself, and the PAC's window (2000_0000..2100_0000) to the same
+    //  place
+    for si in 0..PERIPHERAL_SIZE >> 20 {
+        map(
+            (board.peripheral_base >> 20) + si,
+            board.peripheral_base + (si << 20),
+            0b000,
+            0,
+            1,
+        );
+        map(
+            pac_window.start + si,
+            board.peripheral_base + (si << 20),
+            0b000,
+            0,
+            1,
+        );
     }
 }
 
@@ -275,6 +295,12 @@ struct RawTT([u8; TT_SIZE]);
 const _: () = assert!(align_of::<RawTT>() == TT_SIZE);
 static TT: SyncUnsafeCell<RawTT> = SyncUnsafeCell::new(RawTT([0; TT_SIZE]));
 
+/// Where the translation table is. It lives in the kernel image.
+pub fn translation_table() -> core::ops::Range<usize> {
+    let start = TT.get().addr();
+    start..start + TT_SIZE
+}
+
 pub unsafe fn init() {
     let tt_ptr = unsafe { TT.get().as_mut() }.unwrap().0.as_mut_ptr();
     unsafe { init_mmu(tt_ptr.cast()) };
@@ -0,0 +1,391 @@
+use core::{
+    alloc::Layout,
+    fmt::{Display, Formatter},
+};
+
+use alloc::vec::Vec;
+use bcm2835_lpa::GPIO;
+use sulfur::dilf::{
+    Dst, Len, Nxt, OP_FLAGS_DST_DREQ, OP_FLAGS_DST_NO_INC, OP_FLAGS_PERMAP_OFFSET,
+    OP_FLAGS_SRC_DREQ, OP_FLAGS_SRC_NO_INC, Op, Src,
+};
+use tock_registers::LocalRegisterCopy;
+
+use crate::{
+    addr::PhysAddr,
+    arch::dsb,
+    board,
+    dma::{ExecutiveBuilder, ExecutiveError, Timing},
+    mailbox::{
+        MailboxError,
+        clocks::{self, ClockId},
+    },
+};
+
+// The PL011 "full" UART, UART0. Unlike the Mini UART, its baud rate comes from its own clock
+// (which the firmware sets, usually to 48MHz) rather than the core clock, and it has proper error
+// reporting, flow control and DMA support.
+//
+// Both UARTs come out on GPIO 14/15, the only pair on the header that either can use, so the PL011
+// can't be the console while UART1 carries the `remote` link.
+
+#[cfg(all(feature = "remote", feature = "uart0-console"))]
+compile_error!("the `remote` and `uart0-console` features both need GPIO 14/15");
+
+const UART0_OFFSET: usize = 0x20_1000;
+
+const DR_OFFSET: usize = 0x00;
+const RSRECR_OFFSET: usize = 0x04;
+const FR_OFFSET: usize = 0x18;
+const IBRD_OFFSET: usize = 0x24;
+const FBRD_OFFSET: usize = 0x28;
+const LCRH_OFFSET: usize = 0x2c;
+const CR_OFFSET: usize = 0x30;
+const IMSC_OFFSET: usize = 0x38;
+const ICR_OFFSET: usize = 0x44;
+const DMACR_OFFSET: usize = 0x48;
+
+/// DMA peripheral numbers (PERMAP) for UART0's DREQs.
+pub const DREQ_UART_TX: u32 = 12;
+pub const DREQ_UART_RX: u32 = 14;
+
+tock_registers::register_bitfields! {
+    u32,
+    /// Data Register. Reads return the character along with its error flags.
+    DR [
+        OE OFFSET(11) NUMBITS(1) [],
+        BE OFFSET(10) NUMBITS(1) [],
+        PE OFFSET(9) NUMBITS(1) [],
+        FE OFFSET(8) NUMBITS(1) [],
+        DATA OFFSET(0) NUMBITS(8) [],
+    ],
+    /// Flag Register
+    FR [
+        TXFE OFFSET(7) NUMBITS(1) [],
+        RXFF OFFSET(6) NUMBITS(1) [],
+        TXFF OFFSET(5) NUMBITS(1) [],
+        RXFE OFFSET(4) NUMBITS(1) [],
+        BUSY OFFSET(3) NUMBITS(1) [],
+        CTS OFFSET(0) NUMBITS(1) [],
+    ],
+    /// Line Control Register
+    LCRH [
+        SPS OFFSET(7) NUMBITS(1) [],
+        WLEN OFFSET(5) NUMBITS(2) [
+            FiveBit = 0,
+            SixBit = 1,
+            SevenBit = 2,
+            EightBit = 3,
+        ],
+        FEN OFFSET(4) NUMBITS(1) [],
+        STP2 OFFSET(3) NUMBITS(1) [],
+        EPS OFFSET(2) NUMBITS(1) [],
+        PEN OFFSET(1) NUMBITS(1) [],
+        BRK OFFSET(0) NUMBITS(1) [],
+    ],
+    /// Control Register
+    CR [
+        CTSEN OFFSET(15) NUMBITS(1) [],
+        RTSEN OFFSET(14) NUMBITS(1) [],
+        RTS OFFSET(11) NUMBITS(1) [],
+        RXE OFFSET(9) NUMBITS(1) [],
+        TXE OFFSET(8) NUMBITS(1) [],
+        LBE OFFSET(7) NUMBITS(1) [],
+        UARTEN OFFSET(0) NUMBITS(1) [],
+    ],
+    /// DMA Control Register
+    DMACR [
+        DMAONERR OFFSET(2) NUMBITS(1) [],
+        TXDMAE OFFSET(1) NUMBITS(1) [],
+        RXDMAE OFFSET(0) NUMBITS(1) [],
+    ]
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    board::peripheral(UART0_OFFSET + offset)
+}
+
+/// ARM physical address of the data register, for use as a fixed address in DMA ops.
+pub fn dr_phys() -> u32 {
+    PhysAddr::from_ptr(reg(DR_OFFSET)).as_u32()
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { reg(offset).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { reg(offset).write_volatile(value) }
+}
+
+fn flags() -> LocalRegisterCopy<u32, FR::Register> {
+    LocalRegisterCopy::new(read(FR_OFFSET))
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    pub baud: u32,
+    /// Use RTS/CTS hardware flow control, on GPIO 17/16.
+    pub flow_control: bool,
+}
+
+/// The baud rate divisor, in 1/64ths.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Divisor {
+    pub integer: u16,
+    pub fraction: u8,
+}
+
+impl Divisor {
+    /// The divisor that gets closest to `baud` from a UART clock of `clock` Hz, if there is one.
+    pub fn for_baud(clock: u32, baud: u32) -> Option<Self> {
+        // divisor = clock / (16 * baud), in 6-bit fixed point, rounded to nearest
+        let div64 = (clock as u64 * 4 + baud as u64 / 2) / baud as u64;
+        let integer = div64 >> 6;
+        if integer == 0 || integer > 0xffff {
+            return None;
+        }
+        Some(Self {
+            integer: integer as u16,
+            fraction: (div64 & 0x3f) as u8,
+        })
+    }
+
+    /// The baud rate this divisor actually produces from a UART clock of `clock` Hz.
+    pub fn baud(&self, clock: u32) -> u32 {
+        let div64 = ((self.integer as u64) << 6) | self.fraction as u64;
+        (clock as u64 * 4 / div64) as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pl011Error {
+    Mailbox(MailboxError),
+    /// The firmware reported a UART clock rate of 0.
+    NoClock,
+    /// No divisor gets `baud` from a UART clock of `clock` Hz.
+    Baud {
+        clock: u32,
+        baud: u32,
+    },
+}
+impl Display for Pl011Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Mailbox(e) => write!(f, "{e}"),
+            Self::NoClock => write!(f, "firmware doesn't know the UART clock rate"),
+            Self::Baud { clock, baud } => {
+                write!(f, "{baud} baud is out of range for a {clock} Hz UART clock")
+            }
+        }
+    }
+}
+impl core::error::Error for Pl011Error {}
+impl From<MailboxError> for Pl011Error {
+    fn from(e: MailboxError) -> Self {
+        Self::Mailbox(e)
+    }
+}
+
+/// Errors reported alongside a received character.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LineErrors {
+    pub overrun: bool,
+    pub break_condition: bool,
+    pub parity: bool,
+    pub framing: bool,
+}
+
+impl LineErrors {
+    fn from_dr(dr: LocalRegisterCopy<u32, DR::Register>) -> Self {
+        Self {
+            overrun: dr.is_set(DR::OE),
+            break_condition: dr.is_set(DR::BE),
+            parity: dr.is_set(DR::PE),
+            framing: dr.is_set(DR::FE),
+        }
+    }
+    pub fn any(&self) -> bool {
+        self.overrun || self.break_condition || self.parity || self.framing
+    }
+}
+
+/// Decode a word read from the data register.
+pub fn decode_dr(word: u32) -> Result<u8, LineErrors> {
+    let dr: LocalRegisterCopy<u32, DR::Register> = LocalRegisterCopy::new(word);
+    let errors = LineErrors::from_dr(dr);
+    if errors.any() {
+        Err(errors)
+    } else {
+        Ok(dr.read(DR::DATA) as u8)
+    }
+}
+
+/// Take the GPIO pins, and set up UART0 for 8N1 with FIFOs enabled. Returns the baud rate
+/// actually achieved, which may differ from the one asked for by a few percent. Leaves the UART
+/// and the pins alone if there is no usable divisor.
+pub fn init(gpio: &GPIO, config: Config) -> Result<u32, Pl011Error> {
+    let clock = clocks::rate(ClockId::Uart)?;
+    if clock == 0 {
+        return Err(Pl011Error::NoClock);
+    }
+    let divisor = Divisor::for_baud(clock, config.baud).ok_or(Pl011Error::Baud {
+        clock,
+        baud: config.baud,
+    })?;
+
+    dsb();
+
+    // disable, let the current character finish, and flush the TX FIFO by disabling it
+    write(CR_OFFSET, 0);
+    while flags().is_set(FR::BUSY) {}
+    write(LCRH_OFFSET, 0);
+
+    gpio.gpfsel1()
+        .modify(|_, w| w.fsel14().txd0().fsel15().rxd0());
+    if config.flow_control {
+        gpio.gpfsel1()
+            .modify(|_, w| w.fsel16().cts0().fsel17().rts0());
+    }
+
+    dsb();
+
+    write(IBRD_OFFSET, divisor.integer as u32);
+    write(FBRD_OFFSET, divisor.fraction as u32);
+    let mut lcrh: LocalRegisterCopy<u32, LCRH::Register> = LocalRegisterCopy::new(0);
+    lcrh.write(LCRH::WLEN::EightBit + LCRH::FEN::SET);
+    // the divisor only takes effect on a write to LCRH
+    write(LCRH_OFFSET, lcrh.get());
+
+    write(IMSC_OFFSET, 0);
+    write(ICR_OFFSET, 0x7ff);
+    write(DMACR_OFFSET, 0);
+    write(RSRECR_OFFSET, 0);
+
+    let mut cr: LocalRegisterCopy<u32, CR::Register> = LocalRegisterCopy::new(0);
+    cr.write(CR::UARTEN::SET + CR::TXE::SET + CR::RXE::SET);
+    if config.flow_control {
+        cr.modify(CR::RTSEN::SET + CR::CTSEN::SET);
+    }
+    write(CR_OFFSET, cr.get());
+
+    dsb();
+
+    Ok(divisor.baud(clock))
+}
+
+/// Write `bytes`, blocking while the TX FIFO is full (or, with flow control, while CTS is
+/// deasserted and the FIFO fills up).
+pub fn write_bytes(bytes: &[u8]) {
+    dsb();
+    for &b in bytes {
+        while flags().is_set(FR::TXFF) {}
+        write(DR_OFFSET, b as u32);
+    }
+    dsb();
+}
+
+/// Block until everything written so far has left the UART.
+pub fn flush() {
+    dsb();
+    while flags().is_set(FR::BUSY) {}
+    dsb();
+}
+
+/// Read a character if there is one in the RX FIFO.
+pub fn try_read_byte() -> Option<Result<u8, LineErrors>> {
+    dsb();
+    let result = (!flags().is_set(FR::RXFE)).then(|| decode_dr(read(DR_OFFSET)));
+    dsb();
+    result
+}
+
+/// Block until a character has been received, and return it.
+pub fn read_byte() -> Result<u8, LineErrors> {
+    loop {
+        if let Some(result) = try_read_byte() {
+            return result;
+        }
+    }
+}
+
+fn set_dma(tx: bool, rx: bool) {
+    let mut dmacr: LocalRegisterCopy<u32, DMACR::Register> = LocalRegisterCopy::new(0);
+    dmacr.write(DMACR::TXDMAE.val(tx as u32) + DMACR::RXDMAE.val(rx as u32));
+    dsb();
+    write(DMACR_OFFSET, dmacr.get());
+    dsb();
+}
+
+fn words(n: usize) -> Layout {
+    Layout::array::<u32>(n).expect("should not overflow")
+}
+
+// The DMA engine only moves 32- or 128-bit words, and the data register takes one character per
+// write (and gives one per read), so DMA transfers use a whole word per character.
+
+/// Send `bytes` with the DMA engine on `channel`, paced by UART0's TX DREQ.
+pub fn dma_write(bytes: &[u8], channel: usize) -> Result<Timing, ExecutiveError> {
+    assert!(!bytes.is_empty());
+    let chars: Vec<u32> = bytes.iter().map(|&b| b as u32).collect();
+    let (mut executive, []) = ExecutiveBuilder::default()
+        .chunk(
+            None,
+            0,
+            words(chars.len()),
+            Some(bytemuck::cast_slice(&chars)),
+        )
+        .ops([Op {
+            flags: 0x5404
+                | (DREQ_UART_TX << OP_FLAGS_PERMAP_OFFSET)
+                | OP_FLAGS_DST_DREQ
+                | OP_FLAGS_DST_NO_INC,
+            dst: Dst { fixed: dr_phys() },
+            src: Src::data_ref(0, 0),
+            len: Len::fixed(chars.len() * 4),
+            nxt: Nxt::end(),
+            stride: 0,
+        }])
+        .routine("tx", 0)
+        .build()?;
+
+    set_dma(true, false);
+    let timing = executive.execute("tx", channel);
+    set_dma(false, false);
+    Ok(timing)
+}
+
+/// Receive `len` characters with the DMA engine on `channel`, paced by UART0's RX DREQ. Doesn't
+/// return until all of them have arrived.
+pub fn dma_read(
+    len: usize,
+    channel: usize,
+) -> Result<(Vec<Result<u8, LineErrors>>, Timing), ExecutiveError> {
+    assert!(len > 0);
+    let (mut executive, []) = ExecutiveBuilder::default()
+        .chunk(Some("rx"), 0, words(len), None)
+        .ops([Op {
+            flags: 0x5440
+                | (DREQ_UART_RX << OP_FLAGS_PERMAP_OFFSET)
+                | OP_FLAGS_SRC_DREQ
+                | OP_FLAGS_SRC_NO_INC,
+            dst: Dst::data_ref(0, 0),
+            src: Src { fixed: dr_phys() },
+            len: Len::fixed(len * 4),
+            nxt: Nxt::end(),
+            stride: 0,
+        }])
+        .routine("rx", 0)
+        .build()?;
+
+    set_dma(false, true);
+    let timing = executive.execute("rx", channel);
+    set_dma(false, false);
+
+    let rx = executive.symbol("rx").unwrap();
+    let chars = rx
+        .chunks_exact(4)
+        .map(|word| decode_dr(u32::from_le_bytes(word.try_into().unwrap())))
+        .collect();
+    Ok((chars, timing))
+}
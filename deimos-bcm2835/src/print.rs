@@ -1,4 +1,6 @@
-use bcm2835_lpa::UART1;
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+
+use bcm2835_lpa::{Peripherals, UART1};
 use crate::arch::dsb;
 
 pub struct UartProxy<'a> {
@@ -15,6 +17,136 @@ impl<'a> UartProxy<'a> {
     }
 }
 
+/// Which UART the console is on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ConsoleUart {
+    /// The PL011.
+    Uart0 = 0,
+    /// The Mini UART.
+    Uart1 = 1,
+}
+
+static CONSOLE_UART: AtomicU8 = AtomicU8::new(if cfg!(feature = "uart0-console") {
+    ConsoleUart::Uart0 as u8
+} else {
+    ConsoleUart::Uart1 as u8
+});
+static CONSOLE_BAUD: AtomicU32 = AtomicU32::new(115200);
+
+/// Move the console to `uart` at `baud`. Takes effect at the next [`init_console`].
+pub fn select_console(uart: ConsoleUart, baud: u32) {
+    CONSOLE_UART.store(uart as u8, Ordering::Relaxed);
+    CONSOLE_BAUD.store(baud, Ordering::Relaxed);
+}
+
+pub fn console_uart() -> ConsoleUart {
+    match CONSOLE_UART.load(Ordering::Relaxed) {
+        0 => ConsoleUart::Uart0,
+        _ => ConsoleUart::Uart1,
+    }
+}
+
+/// The baud rate the console was asked to run at.
+pub fn console_baud() -> u32 {
+    CONSOLE_BAUD.load(Ordering::Relaxed)
+}
+
+/// Set up whichever UART the console is on, in polled mode, and return the baud rate it actually
+/// runs at. Both UARTs ask the mailbox for their clock rates. If UART0 can't be set up, the console
+/// falls back to UART1 and the error is returned once it is up, so that it can be printed.
+pub fn init_console(peri: &Peripherals) -> Result<u32, crate::pl011::Pl011Error> {
+    let baud = console_baud();
+    if console_uart() == ConsoleUart::Uart0 {
+        let config = crate::pl011::Config {
+            baud,
+            flow_control: false,
+        };
+        match crate::pl011::init(&peri.GPIO, config) {
+            Ok(achieved) => return Ok(achieved),
+            Err(e) => {
+                select_console(ConsoleUart::Uart1, baud);
+                crate::uart::configure(&peri.GPIO, &peri.AUX, &peri.UART1, baud);
+                return Err(e);
+            }
+        }
+    }
+    Ok(crate::uart::configure(&peri.GPIO, &peri.AUX, &peri.UART1, baud).achieved)
+}
+
+/// Get the console back into a state where the panic handler can write to it: drain and leave
+/// interrupt-driven mode, and reinitialize the Mini UART in case the panic came before it was set
+/// up. The mailbox may be what panicked, so the Mini UART gets its last known divider back rather
+/// than asking for the clock rate again. UART0 is always polled, so it is left alone.
+pub fn recover_console(peri: &Peripherals) {
+    if console_uart() == ConsoleUart::Uart1 {
+        crate::uart::disable_buffered(&peri.UART1);
+        crate::uart::init(
+            &peri.GPIO,
+            &peri.AUX,
+            &peri.UART1,
+            crate::uart::baud_rate().divider,
+        );
+    }
+}
+
+/// The destination of `print!` and `println!`: the selected UART, or the host console when the
+/// `semihosting` feature is enabled. Output is also copied to the framebuffer console, if one is
+/// being mirrored to.
+pub struct Console {
+    #[cfg(not(feature = "semihosting"))]
+    uart: UART1,
+}
+
+impl Console {
+    pub fn get() -> Self {
+        Self {
+            #[cfg(not(feature = "semihosting"))]
+            uart: unsafe { bcm2835_lpa::Peripherals::steal() }.UART1,
+        }
+    }
+    /// Wait for output to leave the UART, unless it is interrupt-driven, in which case there's no
+    /// need to.
+    pub fn flush(&mut self) {
+        #[cfg(not(feature = "semihosting"))]
+        match console_uart() {
+            ConsoleUart::Uart0 => crate::pl011::flush(),
+            ConsoleUart::Uart1 if !crate::uart::is_buffered() => {
+                crate::uart::flush_tx_fifo(&self.uart)
+            }
+            ConsoleUart::Uart1 => {}
+        }
+    }
+    /// Wait for all output to leave the UART, including anything still queued for the TX
+    /// interrupt.
+    pub fn drain(&mut self) {
+        #[cfg(not(feature = "semihosting"))]
+        match console_uart() {
+            ConsoleUart::Uart0 => crate::pl011::flush(),
+            ConsoleUart::Uart1 => crate::uart::flush(&self.uart),
+        }
+    }
+}
+
+impl core::fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        crate::framebuffer::console::write_mirror(s);
+        #[cfg(feature = "semihosting")]
+        {
+            crate::semihosting::write_str(s);
+            Ok(())
+        }
+        #[cfg(not(feature = "semihosting"))]
+        {
+            match console_uart() {
+                ConsoleUart::Uart0 => crate::pl011::write_bytes(s.as_bytes()),
+                ConsoleUart::Uart1 => crate::uart::write_bytes(&self.uart, s.as_bytes()),
+            }
+            Ok(())
+        }
+    }
+}
+
 impl<'a> core::fmt::Write for UartProxy<'a> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         dsb();
@@ -33,10 +165,9 @@ macro_rules! println {
     {
         #[allow(unused_imports)]
         use ::core::fmt::Write as _;
-        let out = &unsafe { ::bcm2835_lpa::Peripherals::steal() }.UART1;
-        let mut proxy = $crate::print::UartProxy::new(out);
-        let _ = ::core::writeln!(proxy, $($arg)*);
-        $crate::uart::flush_tx_fifo(out);
+        let mut console = $crate::print::Console::get();
+        let _ = ::core::writeln!(console, $($arg)*);
+        console.flush();
     }
 }
 }
@@ -47,10 +178,9 @@ macro_rules! print {
     {
         #[allow(unused_imports)]
         use ::core::fmt::Write as _;
-        let out = &unsafe { ::bcm2835_lpa::Peripherals::steal() }.UART1;
-        let mut proxy = $crate::print::UartProxy::new(out);
-        let _ = ::core::write!(proxy, $($arg)*);
-        $crate::uart::flush_tx_fifo(out);
+        let mut console = $crate::print::Console::get();
+        let _ = ::core::write!(console, $($arg)*);
+        console.flush();
     }
 }
 }
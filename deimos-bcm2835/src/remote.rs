@@ -0,0 +1,194 @@
+use alloc::{
+    string::{String, ToString as _},
+    vec::Vec,
+};
+use bcm2835_lpa::{Peripherals, UART1};
+use sulfur::{
+    dilf::Dilf,
+    link::{
+        Command, Crc32, FRAME_MAGIC, HEADER_LEN, Header, LinkError, MAX_PAYLOAD_LEN,
+        PROTOCOL_VERSION, PayloadReader, TRAILER_LEN, check_crc,
+    },
+};
+
+use crate::{
+    dma::{self, Executive},
+    exit, mailbox,
+    mailbox::dma_channels::DmaChannels,
+    println, uart,
+};
+
+// Serves requests from a host over UART1, using the protocol described in `sulfur::link`. The
+// kernel holds at most one loaded DILF file at a time.
+//
+// Anything that would make the executive panic (a bad op type or chunk flag, a reference out of
+// range) is rejected when the file is parsed, and running out of room is reported when it is
+// loaded, with the exception of fixed addresses that have no bus mapping, which still panic and
+// reset the board.
+
+type Reply = Result<(), String>;
+
+pub fn serve(peri: &Peripherals) -> ! {
+    dma::enable_cycle_counter();
+    let channels = mailbox::dma_channels::query().expect("mailbox should answer");
+    let uart = &peri.UART1;
+    let mut executive = None;
+
+    println!("remote: waiting for host (DMA channels: {channels})");
+    loop {
+        let (command, payload) = match read_frame(uart) {
+            Ok(frame) => frame,
+            Err(e) => {
+                send_error(uart, &e.to_string());
+                continue;
+            }
+        };
+        let mut reader = PayloadReader::new(&payload);
+        let reply = match command {
+            Command::Ping => {
+                send(uart, Command::Pong, &[&PROTOCOL_VERSION.to_le_bytes()]);
+                Ok(())
+            }
+            Command::Load => {
+                // free the old executive's memory before allocating the new one
+                executive = None;
+                load(uart, &payload).map(|loaded| executive = Some(loaded))
+            }
+            Command::Run => run(uart, executive.as_mut(), &channels, &mut reader),
+            Command::ReadSymbol => read_symbol(uart, executive.as_ref(), &mut reader),
+            Command::Exit => match reader.u32() {
+                Ok(status) => exit::exit(status),
+                Err(e) => Err(e.to_string()),
+            },
+            other => Err(alloc::format!("unexpected command {other:?}")),
+        };
+        if let Err(message) = reply {
+            send_error(uart, &message);
+        }
+    }
+}
+
+fn load(uart: &UART1, image: &[u8]) -> Result<Executive, String> {
+    let dilf = Dilf::parse(image).map_err(|e| e.to_string())?;
+    if dilf.op_count() == 0 {
+        return Err("file has no ops".to_string());
+    }
+    let executive = Executive::from_dilf(&dilf).map_err(|e| e.to_string())?;
+
+    let mut reply = Vec::new();
+    let routines: Vec<&str> = executive.routines().collect();
+    reply.extend_from_slice(&(routines.len() as u16).to_le_bytes());
+    for routine in routines {
+        reply.extend_from_slice(routine.as_bytes());
+        reply.push(0);
+    }
+    let symbols: Vec<(&str, usize)> = executive.symbols().collect();
+    reply.extend_from_slice(&(symbols.len() as u16).to_le_bytes());
+    for (symbol, size) in symbols {
+        reply.extend_from_slice(symbol.as_bytes());
+        reply.push(0);
+        reply.extend_from_slice(&(size as u32).to_le_bytes());
+    }
+    send(uart, Command::Loaded, &[&reply]);
+    Ok(executive)
+}
+
+fn run(
+    uart: &UART1,
+    executive: Option<&mut Executive>,
+    channels: &DmaChannels,
+    reader: &mut PayloadReader,
+) -> Reply {
+    let executive = executive.ok_or("nothing loaded")?;
+    let channel = reader.u8().map_err(|e| e.to_string())? as usize;
+    let iterations = reader.u16().map_err(|e| e.to_string())?;
+    let routine = reader.name().map_err(|e| e.to_string())?;
+    let mut symbols = Vec::new();
+    while !reader.is_empty() {
+        symbols.push(reader.name().map_err(|e| e.to_string())?);
+    }
+
+    if !channels.iter().any(|c| c == channel) {
+        return Err(alloc::format!("DMA channel {channel} is not available"));
+    }
+    if iterations == 0 {
+        return Err("iteration count must be nonzero".to_string());
+    }
+    if !executive.has_routine(routine) {
+        return Err(alloc::format!("unknown routine `{routine}`"));
+    }
+    for &symbol in symbols.iter() {
+        check_symbol(executive, symbol)?;
+    }
+
+    let cycles: Vec<u8> = (0..iterations)
+        .flat_map(|_| executive.execute(routine, channel).cycles().to_le_bytes())
+        .collect();
+    send(uart, Command::Timings, &[&cycles]);
+    for symbol in symbols {
+        send_symbol(uart, executive, symbol);
+    }
+    Ok(())
+}
+
+fn read_symbol(uart: &UART1, executive: Option<&Executive>, reader: &mut PayloadReader) -> Reply {
+    let executive = executive.ok_or("nothing loaded")?;
+    let symbol = reader.name().map_err(|e| e.to_string())?;
+    check_symbol(executive, symbol)?;
+    send_symbol(uart, executive, symbol);
+    Ok(())
+}
+
+fn check_symbol(executive: &Executive, symbol: &str) -> Reply {
+    match executive.symbol(symbol) {
+        None => Err(alloc::format!("unknown symbol `{symbol}`")),
+        Some(contents) if symbol.len() + 1 + contents.len() > MAX_PAYLOAD_LEN => {
+            Err(alloc::format!("symbol `{symbol}` is too large to send"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+fn send_symbol(uart: &UART1, executive: &Executive, symbol: &str) {
+    let contents = executive.symbol(symbol).unwrap();
+    send(uart, Command::Symbol, &[symbol.as_bytes(), &[0], contents]);
+}
+
+/// Wait for the next frame, skipping anything before its magic.
+fn read_frame(uart: &UART1) -> Result<(Command, Vec<u8>), LinkError> {
+    let mut previous = uart::read_byte(uart);
+    loop {
+        let b = uart::read_byte(uart);
+        if [previous, b] == FRAME_MAGIC {
+            break;
+        }
+        previous = b;
+    }
+    let mut header = [0u8; HEADER_LEN - 2];
+    header.fill_with(|| uart::read_byte(uart));
+    let header = Header::decode(header)?;
+    let mut payload = Vec::with_capacity(header.len);
+    payload.extend((0..header.len).map(|_| uart::read_byte(uart)));
+    let mut trailer = [0u8; TRAILER_LEN];
+    trailer.fill_with(|| uart::read_byte(uart));
+    check_crc(header.command, &payload, trailer)?;
+    Ok((header.command, payload))
+}
+
+/// Send one frame whose payload is the concatenation of `parts`.
+fn send(uart: &UART1, command: Command, parts: &[&[u8]]) {
+    let len = parts.iter().map(|part| part.len()).sum();
+    let mut crc = Crc32::new();
+    crc.update(&[command as u8]);
+    uart::write_bytes(uart, &Header { command, len }.encode());
+    for part in parts {
+        crc.update(part);
+        uart::write_bytes(uart, part);
+    }
+    uart::write_bytes(uart, &crc.finish().to_le_bytes());
+    uart::flush(uart);
+}
+
+fn send_error(uart: &UART1, message: &str) {
+    send(uart, Command::Error, &[message.as_bytes()]);
+}
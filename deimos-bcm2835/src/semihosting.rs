@@ -0,0 +1,68 @@
+use core::arch::asm;
+
+// ARM semihosting, as implemented by `qemu-system-arm -semihosting`. In ARM state the call is
+// `svc 0x123456` with the operation in r0 and its parameter in r1; the result comes back in r0.
+// Without a debugger or emulator to intercept it, this is just a supervisor call into an empty
+// vector table, so only enable the `semihosting` feature when running under emulation.
+
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x2_0026;
+
+unsafe fn call(op: u32, param: *const u8) -> u32 {
+    let result;
+    unsafe {
+        asm!(
+            "svc 0x123456",
+            inout("r0") op => result,
+            in("r1") param,
+            // a real trap would clobber lr_svc
+            out("lr") _,
+        );
+    }
+    result
+}
+
+/// Write a single byte to the host's console.
+pub fn write_c(c: u8) {
+    unsafe { call(SYS_WRITEC, &raw const c) };
+}
+
+/// Write a string to the host's console. NUL bytes are passed through one at a time; everything
+/// else goes in batches through `SYS_WRITE0`.
+pub fn write_str(s: &str) {
+    const CHUNK: usize = 64;
+    let mut buf = [0u8; CHUNK + 1];
+    for chunk in s.as_bytes().chunks(CHUNK) {
+        let mut len = 0;
+        for &b in chunk {
+            if b == 0 {
+                if len > 0 {
+                    buf[len] = 0;
+                    unsafe { call(SYS_WRITE0, buf.as_ptr()) };
+                    len = 0;
+                }
+                write_c(0);
+            } else {
+                buf[len] = b;
+                len += 1;
+            }
+        }
+        if len > 0 {
+            buf[len] = 0;
+            unsafe { call(SYS_WRITE0, buf.as_ptr()) };
+        }
+    }
+}
+
+/// Terminate the emulator, which exits with `status`.
+pub fn exit(status: u32) -> ! {
+    let block = [ADP_STOPPED_APPLICATION_EXIT, status];
+    unsafe { call(SYS_EXIT_EXTENDED, block.as_ptr().cast()) };
+    // The host ignored the request, so there is nothing left to do.
+    loop {
+        crate::arch::wfi();
+    }
+}
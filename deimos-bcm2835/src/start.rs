@@ -2,12 +2,18 @@ unsafe extern "C" {
     static __bss_start: [u32; 0];
     static __bss_end: [u32; 0];
     static __stack_init: [u32; 0];
+    static __svc_stack_end: [u32; 0];
 }
 
 core::arch::global_asm!(r#"
 .section ".text.start"
 .globl _start
 _start:
+    // The firmware passes r0 = 0, r1 = the machine type and r2 = the device tree or ATAGs (see
+    // `boot`). Keep them out of the way until they can be handed to the kernel.
+    mov r4, r0
+    mov r5, r1
+    mov r6, r2
     mrs r0, cpsr
     and r0, r0, {CLEAR_MODE_MASK}
     orr r0, r0, {SUPER_MODE}
@@ -26,18 +32,37 @@ _start:
     bne 2b
 3:
 
+    // The target is hard-float, so compiled code may use the VFP anywhere: grant full access to
+    // CP10/CP11 and switch the VFP on before calling into Rust.
+    mrc p15, 0, r0, c1, c0, 2
+    orr r0, r0, {CPACR_CP10_CP11_FULL}
+    mcr p15, 0, r0, c1, c0, 2
+    mov r0, #0
+    mcr p15, 0, r0, c7, c5, 4
+    mov r0, {FPEXC_EN}
+    vmsr fpexc, r0
+
+    // IRQ mode and supervisor mode each get a stack of their own (see bcm2835.ld).
+    cps #{IRQ_MODE}
     ldr sp, ={STACK_INIT}
-    add sp, sp, #0x20000
-    mov r0, sp
+    cps #{SUPER_MODE}
+
+    ldr sp, ={SVC_STACK_END}
+    mov r0, r4
+    mov r1, r5
+    mov r2, r6
     mov fp, #0
     bl {KERNEL_START}
 "#,
     CLEAR_MODE_MASK = const !0b11111u32,
     SUPER_MODE = const 0b10011u32,
+    IRQ_MODE = const 0b10010u32,
     CLEAR_MODE_IRQ_FIQ = const (1u32 << 7) | (1u32 << 6),
+    CPACR_CP10_CP11_FULL = const 0xfu32 << 20,
+    FPEXC_EN = const 1u32 << 30,
     BSS_START = sym __bss_start,
     BSS_END = sym __bss_end,
     STACK_INIT = sym __stack_init,
+    SVC_STACK_END = sym __svc_stack_end,
     KERNEL_START = sym crate::__kernel_start,
 );
-
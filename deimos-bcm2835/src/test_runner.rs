@@ -0,0 +1,117 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{exit, print, println};
+
+pub trait Testable {
+    fn name(&self) -> &'static str;
+    fn run(&self);
+}
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+    fn run(&self) {
+        self()
+    }
+}
+
+// A failed test is abandoned where it panicked, without running any destructors (see
+// `__deimos_recover`), so whatever it had borrowed stays borrowed: the mailbox buffer, the UART
+// buffers, the framebuffer mirror. The panic handler has also taken the console out of
+// interrupt-driven mode. Nothing run after that could be trusted to pass or fail on its own merits,
+// so the first failure ends the run.
+
+/// Entry point for `#![test_runner]`: runs the `#[test_case]`s in turn until one fails, reports a
+/// summary, and exits with status 0 if everything passed and 1 otherwise.
+pub fn run(tests: &[&dyn Testable]) {
+    println!();
+    println!("running {} tests", tests.len());
+    let mut passed = 0;
+    let mut failed = false;
+    for test in tests {
+        print!("test {} ... ", test.name());
+        match isolate(&|| test.run()) {
+            Ok(()) => {
+                println!("ok");
+                passed += 1;
+            }
+            Err(()) => {
+                println!("test {} FAILED", test.name());
+                failed = true;
+                break;
+            }
+        }
+    }
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed; {} not run",
+        if failed { "FAILED" } else { "ok" },
+        passed,
+        failed as usize,
+        tests.len() - passed - failed as usize
+    );
+    exit::exit(if failed { 1 } else { 0 });
+}
+
+/// Stack pointer to return to if the running test panics, or 0 if no test is running.
+static RECOVERY_SP: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" {
+    fn __deimos_try(f: extern "C" fn(*const u8), data: *const u8, recovery_sp: *mut usize) -> u32;
+    fn __deimos_recover(sp: usize) -> !;
+}
+
+// __deimos_try(f, data, recovery_sp) saves the callee-saved registers and CPSR on the stack,
+// stores the resulting stack pointer to *recovery_sp, and calls f(data), returning 0.
+// __deimos_recover(sp) unwinds straight back to that frame, restoring the saved registers and the
+// CPSR control bits (so a panic inside a critical section doesn't leave interrupts masked), and
+// makes __deimos_try return 1. Nothing in between is dropped, so a failed test leaks whatever it
+// had allocated or borrowed.
+core::arch::global_asm!(
+    r#"
+.section ".text.deimos_try"
+.globl __deimos_try
+__deimos_try:
+    mrs ip, cpsr
+    push {{r4-r11, ip, lr}}
+    vpush {{d8-d15}}
+    str sp, [r2]
+    mov r3, r0
+    mov r0, r1
+    blx r3
+    mov r0, #0
+    vpop {{d8-d15}}
+    pop {{r4-r11, ip, pc}}
+
+.globl __deimos_recover
+__deimos_recover:
+    mov sp, r0
+    vpop {{d8-d15}}
+    pop {{r4-r11, ip, lr}}
+    msr cpsr_c, ip
+    mov r0, #1
+    bx lr
+"#
+);
+
+/// Run `f`, turning a panic into `Err(())` instead of a reset. Whatever `f` had borrowed or set up
+/// when it panicked is left that way.
+pub fn isolate(f: &dyn Fn()) -> Result<(), ()> {
+    extern "C" fn trampoline(data: *const u8) {
+        let f = unsafe { *data.cast::<&dyn Fn()>() };
+        f()
+    }
+    let previous = RECOVERY_SP.load(Ordering::SeqCst);
+    let status = unsafe { __deimos_try(trampoline, (&raw const f).cast(), RECOVERY_SP.as_ptr()) };
+    RECOVERY_SP.store(previous, Ordering::SeqCst);
+    if status == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Called from the panic handler once the message has been printed. If a test is running, this
+/// does not return: control goes back to [`isolate`], which reports the failure.
+pub fn on_panic() {
+    let sp = RECOVERY_SP.swap(0, Ordering::SeqCst);
+    if sp != 0 {
+        unsafe { __deimos_recover(sp) }
+    }
+}
@@ -1,14 +1,127 @@
-use crate::arch::dsb;
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{arch, arch::dsb, interrupts};
+use alloc::{string::String, vec::Vec};
 use bcm2835_lpa::{AUX, GPIO, UART1};
+use critical_section::{CriticalSection, Mutex};
 
-const MINI_UART_CLOCK_RATE: u32 = 250_000_000;
+// The Mini UART is clocked from the core clock, which the firmware may run at something other than
+// the usual 250MHz (`core_freq`, turbo, throttling). `configure` and `resync` ask the firmware what
+// the rate actually is; this is only used if it won't say.
+const DEFAULT_CORE_CLOCK_RATE: u32 = 250_000_000;
 
-/// Calculate a value for the Mini UART clock divider from the desired baud rate.
+/// Calculate a value for the Mini UART clock divider from the desired baud rate, assuming the core
+/// clock is at its default rate.
 pub const fn baud_to_clock_divider(baud_rate: u32) -> u16 {
-    ((MINI_UART_CLOCK_RATE / (8 * baud_rate)) - 1) as u16
+    BaudRate::new(DEFAULT_CORE_CLOCK_RATE, baud_rate).divider
+}
+
+/// A clock divider setting, and the baud rate it gets from a particular core clock rate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BaudRate {
+    pub requested: u32,
+    pub achieved: u32,
+    pub core_clock: u32,
+    pub divider: u16,
+}
+
+impl BaudRate {
+    /// The divider that gets closest to `baud` from a core clock of `core_clock` Hz.
+    pub const fn new(core_clock: u32, baud: u32) -> Self {
+        // baud = core_clock / (8 * (divider + 1)), rounded to nearest
+        let divisor = (core_clock + 4 * baud) / (8 * baud);
+        let divisor = if divisor == 0 {
+            1
+        } else if divisor > 0x1_0000 {
+            0x1_0000
+        } else {
+            divisor
+        };
+        Self {
+            requested: baud,
+            achieved: core_clock / (8 * divisor),
+            core_clock,
+            divider: (divisor - 1) as u16,
+        }
+    }
+
+    /// How far off the achieved rate is, in percent.
+    pub fn error_percent(&self) -> f32 {
+        (self.achieved as f32 - self.requested as f32) * 100.0 / self.requested as f32
+    }
+}
+
+impl core::fmt::Display for BaudRate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} baud ({:+.2}% off {}) from a {} MHz core clock",
+            self.achieved,
+            self.error_percent(),
+            self.requested,
+            self.core_clock / 1_000_000
+        )
+    }
+}
+
+// The setting currently in effect, so that `resync` knows what was asked for and whether anything
+// changed, and so that the panic handler can reinitialize without going through the mailbox.
+static BAUD_RATE: Mutex<RefCell<BaudRate>> =
+    Mutex::new(RefCell::new(BaudRate::new(DEFAULT_CORE_CLOCK_RATE, 115200)));
+
+fn core_clock_rate() -> u32 {
+    match crate::mailbox::clocks::rate(crate::mailbox::clocks::ClockId::Core) {
+        Ok(0) | Err(_) => DEFAULT_CORE_CLOCK_RATE,
+        Ok(rate) => rate,
+    }
+}
+
+/// [`init`] for `baud` at the core clock's current rate, which it asks the mailbox for.
+pub fn configure(gpio: &GPIO, aux: &AUX, uart: &UART1, baud: u32) -> BaudRate {
+    let rate = BaudRate::new(core_clock_rate(), baud);
+    init(gpio, aux, uart, rate.divider);
+    critical_section::with(|cs| *BAUD_RATE.borrow_ref_mut(cs) = rate);
+    rate
+}
+
+/// Check the core clock rate again, and if it has changed since the divider was set, wait for
+/// pending output to go out and recompute the divider for the same baud rate. Returns the new
+/// setting if there was a change.
+pub fn resync(uart: &UART1) -> Option<BaudRate> {
+    if !is_configured() {
+        return None;
+    }
+    let current = baud_rate();
+    let rate = BaudRate::new(core_clock_rate(), current.requested);
+    if rate.core_clock == current.core_clock {
+        return None;
+    }
+    flush(uart);
+    if !set_clock(uart, rate.divider) {
+        return None;
+    }
+    critical_section::with(|cs| *BAUD_RATE.borrow_ref_mut(cs) = rate);
+    Some(rate)
+}
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`init`] has been called. Until it has, waiting for the UART to drain would never
+/// finish.
+pub fn is_configured() -> bool {
+    CONFIGURED.load(Ordering::Relaxed)
+}
+
+/// The setting last made by [`configure`] or [`resync`].
+pub fn baud_rate() -> BaudRate {
+    critical_section::with(|cs| *BAUD_RATE.borrow_ref(cs))
 }
 
 pub fn init(gpio: &GPIO, aux: &AUX, uart: &UART1, clock_divider: u16) {
+    CONFIGURED.store(true, Ordering::Relaxed);
     dsb();
 
     gpio.gpfsel1()
@@ -90,3 +203,261 @@ pub fn flush_tx_fifo(uart: &UART1) {
     flush_tx_fifo_unguarded(uart);
     dsb();
 }
+
+// Interrupt-driven operation. Once `enable_buffered` has been called, received bytes are moved
+// into RX_BUFFER_SIZE bytes of buffer by the AUX interrupt, and writes are queued in
+// TX_BUFFER_SIZE bytes of buffer and drained into the FIFO from the interrupt as it empties.
+//
+// Blocking operations wait with `wfi` inside a critical section, which still wakes on a pending
+// interrupt, and service the UART themselves before each wait. This means they keep working with
+// IRQs masked (inside a critical section, or from an interrupt handler), just less efficiently.
+
+pub const RX_BUFFER_SIZE: usize = 0x1000;
+pub const TX_BUFFER_SIZE: usize = 0x1000;
+
+// The datasheet has the RX and TX enable bits the wrong way round, and doesn't mention that bits
+// 3:2 need to be set for interrupts to be raised at all.
+const IER_RX: u8 = 1 << 0;
+const IER_TX: u8 = 1 << 1;
+const IER_REQUIRED: u8 = 0b11 << 2;
+
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+    fn push(&mut self, b: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = b;
+        self.len += 1;
+        true
+    }
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let b = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(b)
+    }
+}
+
+struct Buffers {
+    rx: Ring<RX_BUFFER_SIZE>,
+    tx: Ring<TX_BUFFER_SIZE>,
+    /// Bytes received while `rx` was full, and thrown away.
+    rx_dropped: u32,
+}
+
+static BUFFERED: AtomicBool = AtomicBool::new(false);
+static BUFFERS: Mutex<RefCell<Buffers>> = Mutex::new(RefCell::new(Buffers {
+    rx: Ring::new(),
+    tx: Ring::new(),
+    rx_dropped: 0,
+}));
+
+fn uart1() -> UART1 {
+    unsafe { bcm2835_lpa::Peripherals::steal() }.UART1
+}
+
+fn set_interrupts(uart: &UART1, tx: bool) {
+    let bits = IER_REQUIRED | IER_RX | if tx { IER_TX } else { 0 };
+    unsafe { uart.ier().write_with_zero(|w| w.bits(bits as _)) };
+}
+
+/// Move received bytes out of the RX FIFO, and queued bytes into the TX FIFO. The TX interrupt
+/// is left enabled exactly when there is something queued.
+fn service(uart: &UART1, buffers: &mut Buffers) {
+    dsb();
+    while uart.lsr().read().data_ready().bit_is_set() {
+        let b = uart.io().read().data().bits();
+        if !buffers.rx.push(b) {
+            buffers.rx_dropped += 1;
+        }
+    }
+    while !buffers.tx.is_empty() && uart.stat().read().tx_ready().bit_is_set() {
+        let b = buffers.tx.pop().unwrap();
+        uart.io().write(|w| unsafe { w.data().bits(b) });
+    }
+    set_interrupts(uart, !buffers.tx.is_empty());
+    dsb();
+}
+
+/// Run `f` on the buffers, after servicing the UART, until it returns `Some`, sleeping until the
+/// next interrupt in between.
+fn wait_for<T>(mut f: impl FnMut(&mut Buffers) -> Option<T>) -> T {
+    let uart = uart1();
+    loop {
+        let result = critical_section::with(|cs| {
+            let mut buffers = BUFFERS.borrow_ref_mut(cs);
+            service(&uart, &mut buffers);
+            let result = f(&mut buffers);
+            if result.is_none() {
+                arch::wfi();
+            }
+            result
+        });
+        if let Some(result) = result {
+            return result;
+        }
+    }
+}
+
+/// Switch UART1 to interrupt-driven operation. [`init`] must have been called first, and
+/// interrupts have to be [`enabled`](interrupts::enable) for the buffers to be serviced in the
+/// background.
+pub fn enable_buffered(uart: &UART1) {
+    if BUFFERED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    critical_section::with(|cs| service(uart, &mut BUFFERS.borrow_ref_mut(cs)));
+    interrupts::enable_irq(interrupts::AUX_IRQ);
+}
+
+/// Go back to polled operation, writing out anything still queued. Safe to call from the panic
+/// handler: if the panic happened while the buffers were in use, whatever was queued is lost.
+pub fn disable_buffered(uart: &UART1) {
+    if !BUFFERED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    interrupts::disable_irq(interrupts::AUX_IRQ);
+    critical_section::with(|cs| {
+        unsafe { uart.ier().write_with_zero(|w| w.bits(0)) };
+        if let Ok(mut buffers) = BUFFERS.borrow(cs).try_borrow_mut() {
+            while let Some(b) = buffers.tx.pop() {
+                while uart.stat().read().tx_ready().bit_is_clear() {}
+                uart.io().write(|w| unsafe { w.data().bits(b) });
+            }
+        }
+    });
+    flush_tx_fifo(uart);
+}
+
+pub fn is_buffered() -> bool {
+    BUFFERED.load(Ordering::SeqCst)
+}
+
+/// Called from the IRQ handler when the AUX interrupt is pending.
+pub fn handle_interrupt() {
+    // SAFETY: IRQs are masked while the handler runs, so this can't be interleaved with another
+    // critical section.
+    let cs = unsafe { CriticalSection::new() };
+    if let Ok(mut buffers) = BUFFERS.borrow(cs).try_borrow_mut() {
+        service(&uart1(), &mut buffers);
+    }
+}
+
+/// Number of received bytes thrown away so far because the RX buffer was full.
+pub fn rx_dropped() -> u32 {
+    critical_section::with(|cs| BUFFERS.borrow_ref(cs).rx_dropped)
+}
+
+/// Block until at least one byte has been received, then read as many as are available into
+/// `buf`. Returns the number of bytes read.
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    wait_for(|buffers| {
+        let mut n = 0;
+        while n < buf.len()
+            && let Some(b) = buffers.rx.pop()
+        {
+            buf[n] = b;
+            n += 1;
+        }
+        (n > 0).then_some(n)
+    })
+}
+
+/// Block until a whole line has been received, and append it to `line` without the line ending.
+/// Invalid UTF-8 is replaced.
+pub fn read_line(line: &mut String) {
+    let mut bytes = Vec::new();
+    loop {
+        let b = read_byte(&uart1());
+        if b == b'\n' {
+            break;
+        }
+        bytes.push(b);
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    line.push_str(&String::from_utf8_lossy(&bytes));
+}
+
+/// Block until a byte has been received, and return it.
+pub fn read_byte(uart: &UART1) -> u8 {
+    if is_buffered() {
+        let mut b = [0u8];
+        read(&mut b);
+        return b[0];
+    }
+    while uart.lsr().read().data_ready().bit_is_clear() {}
+    let b = uart.io().read().data().bits();
+    dsb();
+    b
+}
+
+/// Queue as much of `bytes` as fits without blocking, and return how much that was. Only
+/// available in buffered mode.
+pub fn try_write(bytes: &[u8]) -> usize {
+    assert!(is_buffered(), "try_write needs buffered mode");
+    let uart = uart1();
+    critical_section::with(|cs| {
+        let mut buffers = BUFFERS.borrow_ref_mut(cs);
+        let n = bytes.iter().take_while(|&&b| buffers.tx.push(b)).count();
+        service(&uart, &mut buffers);
+        n
+    })
+}
+
+/// Write all of `bytes`, blocking while the TX buffer (or in polled mode, the TX FIFO) is full.
+pub fn write_bytes(uart: &UART1, mut bytes: &[u8]) {
+    if is_buffered() {
+        while !bytes.is_empty() {
+            let n = try_write(bytes);
+            bytes = &bytes[n..];
+            if !bytes.is_empty() {
+                wait_for(|buffers| (!buffers.tx.is_full()).then_some(()));
+            }
+        }
+        return;
+    }
+    dsb();
+    for &b in bytes {
+        while uart.stat().read().tx_ready().bit_is_clear() {}
+        uart.io().write(|w| unsafe { w.data().bits(b) });
+    }
+    dsb();
+}
+
+/// Block until everything written so far has left the UART. Does nothing if it was never set up.
+pub fn flush(uart: &UART1) {
+    if !is_configured() {
+        return;
+    }
+    if is_buffered() {
+        wait_for(|buffers| buffers.tx.is_empty().then_some(()));
+    }
+    flush_tx_fifo(uart);
+}
@@ -0,0 +1,131 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use serialport::SerialPort;
+use sulfur::link::{
+    Command, Crc32, FRAME_MAGIC, HEADER_LEN, Header, LinkError, TRAILER_LEN, check_crc,
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serial(serialport::Error),
+    Link(LinkError),
+    /// The kernel answered with [`Command::Error`].
+    Remote(String),
+    Unexpected(Command),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Serial(e) => write!(f, "{e}"),
+            Error::Link(e) => write!(f, "{e}"),
+            Error::Remote(message) => write!(f, "kernel: {message}"),
+            Error::Unexpected(command) => write!(f, "unexpected reply {command:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+impl From<LinkError> for Error {
+    fn from(e: LinkError) -> Self {
+        Error::Link(e)
+    }
+}
+
+/// A connection to a kernel serving requests over its UART. Bytes that arrive outside of a frame
+/// are the kernel's console output, and are copied to `console` (stderr, unless testing).
+pub struct Link<P = Box<dyn SerialPort>, C = io::Stderr> {
+    port: P,
+    console: C,
+}
+
+impl Link {
+    /// Open a serial device, or a pty such as the one QEMU creates for `-serial pty`.
+    pub fn open(path: &str, baud: u32, timeout: Duration) -> Result<Self, Error> {
+        let port = serialport::new(path, baud).timeout(timeout).open()?;
+        Ok(Self {
+            port,
+            console: io::stderr(),
+        })
+    }
+}
+
+impl<P: Read + Write, C: Write> Link<P, C> {
+    pub fn send(&mut self, command: Command, payload: &[u8]) -> Result<(), Error> {
+        let mut crc = Crc32::new();
+        crc.update(&[command as u8]);
+        crc.update(payload);
+        let header = Header {
+            command,
+            len: payload.len(),
+        };
+        self.port.write_all(&header.encode())?;
+        self.port.write_all(payload)?;
+        self.port.write_all(&crc.finish().to_le_bytes())?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// Wait for the next frame. [`Command::Error`] frames are turned into [`Error::Remote`].
+    pub fn recv(&mut self) -> Result<(Command, Vec<u8>), Error> {
+        let mut previous = None;
+        loop {
+            let b = self.read_byte()?;
+            if previous == Some(FRAME_MAGIC[0]) && b == FRAME_MAGIC[1] {
+                break;
+            }
+            if let Some(previous) = previous {
+                self.console.write_all(&[previous])?;
+            }
+            previous = Some(b);
+        }
+        let mut header = [0u8; HEADER_LEN - 2];
+        self.port.read_exact(&mut header)?;
+        let header = Header::decode(header)?;
+        let mut payload = vec![0u8; header.len];
+        self.port.read_exact(&mut payload)?;
+        let mut trailer = [0u8; TRAILER_LEN];
+        self.port.read_exact(&mut trailer)?;
+        check_crc(header.command, &payload, trailer)?;
+        if header.command == Command::Error {
+            return Err(Error::Remote(
+                String::from_utf8_lossy(&payload).into_owned(),
+            ));
+        }
+        Ok((header.command, payload))
+    }
+
+    /// Wait for a frame, which must be a `command`.
+    pub fn expect(&mut self, command: Command) -> Result<Vec<u8>, Error> {
+        match self.recv()? {
+            (received, payload) if received == command => Ok(payload),
+            (received, _) => Err(Error::Unexpected(received)),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut b = [0u8];
+        self.port.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::Cursor;
+
+use super::*;
+
+const PROTOCOL_VERSION_BYTES: [u8; 2] = sulfur::link::PROTOCOL_VERSION.to_le_bytes();
+
+/// A port that reads from a canned byte string and records what is written to it.
+struct Loopback {
+    rx: Cursor<Vec<u8>>,
+    tx: Vec<u8>,
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rx.read(buf)
+    }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn link(rx: Vec<u8>) -> Link<Loopback, Vec<u8>> {
+    Link {
+        port: Loopback {
+            rx: Cursor::new(rx),
+            tx: Vec::new(),
+        },
+        console: Vec::new(),
+    }
+}
+
+/// The bytes `send` writes for one frame.
+fn frame(command: Command, payload: &[u8]) -> Vec<u8> {
+    let mut link = link(Vec::new());
+    link.send(command, payload).unwrap();
+    link.port.tx
+}
+
+#[test]
+fn frame_round_trips() {
+    // console output before the frame is passed on
+    let mut rx = b"booting\n".to_vec();
+    rx.extend(frame(Command::Run, b"\x02\x01\x00main\0"));
+    let mut link = link(rx);
+    let (command, payload) = link.recv().unwrap();
+    assert_eq!(command, Command::Run);
+    assert_eq!(payload, b"\x02\x01\x00main\0");
+    assert_eq!(link.console, b"booting\n");
+}
+
+#[test]
+fn receives_back_to_back_frames() {
+    let mut rx = frame(Command::Pong, &PROTOCOL_VERSION_BYTES);
+    rx.extend(frame(Command::Symbol, b"dst\0\x11\x22"));
+    let mut link = link(rx);
+    assert_eq!(link.expect(Command::Pong).unwrap(), PROTOCOL_VERSION_BYTES);
+    assert_eq!(link.expect(Command::Symbol).unwrap(), b"dst\0\x11\x22");
+}
+
+#[test]
+fn reports_remote_errors() {
+    let rx = frame(Command::Error, b"no such routine");
+    match link(rx).recv() {
+        Err(Error::Remote(message)) => assert_eq!(message, "no such routine"),
+        other => panic!("expected a remote error, got {other:?}"),
+    }
+}
+
+#[test]
+fn rejects_corrupted_frames() {
+    let mut rx = frame(Command::Load, b"abcd");
+    rx[HEADER_LEN] ^= 1;
+    assert!(matches!(
+        link(rx).recv(),
+        Err(Error::Link(LinkError::BadCrc { .. }))
+    ));
+}
+
+#[test]
+fn rejects_unexpected_replies() {
+    let rx = frame(Command::Pong, &PROTOCOL_VERSION_BYTES);
+    assert!(matches!(
+        link(rx).expect(Command::Loaded),
+        Err(Error::Unexpected(Command::Pong))
+    ));
+}
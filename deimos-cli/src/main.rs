@@ -0,0 +1,294 @@
+//! Host companion for a deimos kernel built with the `remote` feature: uploads DILF files over
+//! the kernel's UART, runs routines, and fetches symbol memory.
+//!
+//! The kernel itself still has to be flashed once (with `upload-bcm2835.sh`); after that, DMA
+//! programs can be iterated on without a reflash. Under QEMU, start the kernel with
+//! `-serial null -serial pty` and pass the pty QEMU prints as `--port`.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use sulfur::{
+    dilf::Dilf,
+    link::{Command, PROTOCOL_VERSION, PayloadReader},
+    stats::Stats,
+};
+
+use crate::link::Link;
+
+mod link;
+mod stats;
+#[cfg(test)]
+mod tests;
+
+/// Talk to a deimos kernel built with the `remote` feature.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Serial device or pty that the kernel's UART1 is attached to.
+    #[arg(short, long, env = "DEIMOS_PORT")]
+    port: String,
+    #[arg(short, long, default_value_t = 115200)]
+    baud: u32,
+    /// Seconds to wait for each reply.
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Check that the kernel is listening and speaks the same protocol version.
+    Ping,
+    /// Upload a DILF file, replacing whatever was loaded before.
+    Load { file: PathBuf },
+    /// Run a routine and report how many cycles it took.
+    Run {
+        routine: String,
+        /// Upload this DILF file first.
+        #[arg(short, long)]
+        load: Option<PathBuf>,
+        /// DMA channel to run on.
+        #[arg(short, long)]
+        channel: u8,
+        #[arg(short = 'n', long, default_value_t = 1)]
+        iterations: u16,
+        /// Print the contents of this symbol after the run. May be repeated.
+        #[arg(short, long)]
+        symbol: Vec<String>,
+        /// Print what the run changed in this symbol. May be repeated.
+        #[arg(short, long)]
+        diff: Vec<String>,
+        /// Append summary statistics to this CSV file.
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Fetch the current contents of a symbol.
+    Read {
+        symbol: String,
+        /// Write the raw bytes here instead of printing a hex dump.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Compare the current contents of a symbol against a file.
+    Diff { symbol: String, file: PathBuf },
+    /// Make the kernel exit with the given status.
+    Exit {
+        #[arg(default_value_t = 0)]
+        status: u32,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<ExitCode, Box<dyn Error>> {
+    let mut link = Link::open(&args.port, args.baud, Duration::from_secs(args.timeout))?;
+    match args.command {
+        Cmd::Ping => {
+            let reply = link_request(&mut link, Command::Ping, &[], Command::Pong)?;
+            let version = PayloadReader::new(&reply).u16()?;
+            println!("kernel speaks protocol version {version}");
+            if version != PROTOCOL_VERSION {
+                eprintln!("warning: this tool speaks version {PROTOCOL_VERSION}");
+            }
+        }
+        Cmd::Load { file } => load(&mut link, &file)?,
+        Cmd::Run {
+            routine,
+            load: file,
+            channel,
+            iterations,
+            symbol,
+            diff,
+            csv,
+        } => {
+            if let Some(file) = file {
+                load(&mut link, &file)?;
+            }
+            let mut before = HashMap::new();
+            for name in diff.iter() {
+                before.insert(name.clone(), read_symbol(&mut link, name)?);
+            }
+
+            let mut request = vec![channel];
+            request.extend_from_slice(&iterations.to_le_bytes());
+            push_name(&mut request, &routine);
+            let wanted: Vec<&String> = symbol.iter().chain(diff.iter()).collect();
+            for name in wanted.iter() {
+                push_name(&mut request, name);
+            }
+            let reply = link_request(&mut link, Command::Run, &request, Command::Timings)?;
+            let mut cycles: Vec<u32> = reply
+                .as_chunks::<4>()
+                .0
+                .iter()
+                .map(|&c| u32::from_le_bytes(c))
+                .collect();
+            let mut after = HashMap::new();
+            for _ in wanted.iter() {
+                let (name, contents) = parse_symbol(&link.expect(Command::Symbol)?)?;
+                after.insert(name, contents);
+            }
+
+            let stats = Stats::new(&mut cycles, true);
+            if cycles.len() == 1 {
+                println!("{routine}: {} cycles", cycles[0]);
+            } else {
+                println!(
+                    "{routine}: n={} median={} min={} max={} mean={:.2} stddev={:.2} ({} outliers)",
+                    stats.n,
+                    stats.median,
+                    stats.min,
+                    stats.max,
+                    stats.mean,
+                    stats.stddev,
+                    stats.rejected
+                );
+            }
+            if let Some(csv) = csv {
+                stats::append_csv(&csv, &routine, &stats)?;
+            }
+            for name in symbol.iter() {
+                println!("{name}:");
+                hexdump(&after[name]);
+            }
+            for name in diff.iter() {
+                println!("{name}:");
+                print_diff(&before[name], &after[name]);
+            }
+        }
+        Cmd::Read { symbol, output } => {
+            let contents = read_symbol(&mut link, &symbol)?;
+            match output {
+                Some(output) => fs::write(output, contents)?,
+                None => hexdump(&contents),
+            }
+        }
+        Cmd::Diff { symbol, file } => {
+            let expected = fs::read(file)?;
+            let actual = read_symbol(&mut link, &symbol)?;
+            if actual.len() != expected.len() {
+                println!(
+                    "{symbol} is {} bytes, file is {} bytes",
+                    actual.len(),
+                    expected.len()
+                );
+            }
+            if !print_diff(&expected, &actual) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Cmd::Exit { status } => link.send(Command::Exit, &status.to_le_bytes())?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn link_request(
+    link: &mut Link,
+    command: Command,
+    payload: &[u8],
+    reply: Command,
+) -> Result<Vec<u8>, link::Error> {
+    link.send(command, payload)?;
+    link.expect(reply)
+}
+
+fn load(link: &mut Link, file: &Path) -> Result<(), Box<dyn Error>> {
+    let image = fs::read(file)?;
+    // catch a bad file here, where the error can say which file it was
+    let dilf = Dilf::parse(&image).map_err(|e| format!("{}: {e}", file.display()))?;
+    let reply = link_request(link, Command::Load, &image, Command::Loaded)?;
+    println!(
+        "loaded {}: {} ops, {} chunks",
+        file.display(),
+        dilf.op_count(),
+        dilf.chunk_count()
+    );
+
+    let mut reader = PayloadReader::new(&reply);
+    let mut routines = Vec::new();
+    for _ in 0..reader.u16()? {
+        routines.push(reader.name()?);
+    }
+    routines.sort_unstable();
+    println!("routines: {}", routines.join(", "));
+    let mut symbols = Vec::new();
+    for _ in 0..reader.u16()? {
+        symbols.push((reader.name()?, reader.u32()?));
+    }
+    symbols.sort_unstable();
+    for (symbol, size) in symbols {
+        println!("\t{symbol}: {size} bytes");
+    }
+    Ok(())
+}
+
+fn read_symbol(link: &mut Link, symbol: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut request = Vec::new();
+    push_name(&mut request, symbol);
+    let reply = link_request(link, Command::ReadSymbol, &request, Command::Symbol)?;
+    Ok(parse_symbol(&reply)?.1)
+}
+
+fn parse_symbol(payload: &[u8]) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+    let mut reader = PayloadReader::new(payload);
+    let name = reader.name()?.to_string();
+    Ok((name, reader.rest().to_vec()))
+}
+
+fn push_name(payload: &mut Vec<u8>, name: &str) {
+    payload.extend_from_slice(name.as_bytes());
+    payload.push(0);
+}
+
+fn hexdump(bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{b:02x}")).collect();
+        println!("\t{:08x}: {}", i * 16, hex.join(" "));
+    }
+}
+
+/// Print each run of bytes that differs between `before` and `after`, and return whether they
+/// were identical.
+fn print_diff(before: &[u8], after: &[u8]) -> bool {
+    let len = before.len().min(after.len());
+    let mut identical = before.len() == after.len();
+    let mut i = 0;
+    while i < len {
+        if before[i] == after[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < len && before[i] != after[i] {
+            i += 1;
+        }
+        println!(
+            "\t+{start:#x}: {:02x?} -> {:02x?}",
+            &before[start..i],
+            &after[start..i]
+        );
+        identical = false;
+    }
+    if identical {
+        println!("\t(no changes)");
+    }
+    identical
+}
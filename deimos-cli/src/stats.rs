@@ -0,0 +1,17 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use sulfur::stats::{CSV_HEADER, Stats};
+
+#[cfg(test)]
+mod tests;
+
+/// Append a line for the routine `name` to the CSV file at `path`, writing the header first if the
+/// file is new or empty. The lines are the same as the kernel's benchmarks print, so the two can be
+/// mixed.
+pub fn append_csv(path: &Path, name: &str, stats: &Stats) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{CSV_HEADER}")?;
+    }
+    writeln!(file, "{}", stats.csv(name))
+}
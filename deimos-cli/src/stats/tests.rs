@@ -0,0 +1,23 @@
+use std::{env, fs, process};
+
+use super::*;
+
+#[test]
+fn appends_header_once() {
+    let path = env::temp_dir().join(format!("deimos-stats-{}.csv", process::id()));
+    let _ = fs::remove_file(&path);
+    let stats = Stats::new(&mut [3, 1, 2], true);
+    append_csv(&path, "a", &stats).unwrap();
+    append_csv(&path, "b", &stats).unwrap();
+    let csv = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            CSV_HEADER,
+            "a,3,0,1,1,1,2,3,3,3,2.00,0.82",
+            "b,3,0,1,1,1,2,3,3,3,2.00,0.82"
+        ]
+    );
+}
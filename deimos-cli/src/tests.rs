@@ -0,0 +1,30 @@
+use super::*;
+
+#[test]
+fn diff_of_identical_bytes() {
+    assert!(print_diff(b"abcd", b"abcd"));
+    assert!(print_diff(b"", b""));
+}
+
+#[test]
+fn diff_of_changed_bytes() {
+    assert!(!print_diff(b"abcd", b"abxd"));
+    assert!(!print_diff(b"abcd", b"xbcx"));
+}
+
+#[test]
+fn diff_of_different_lengths() {
+    // the common prefix is the same, but one side is longer
+    assert!(!print_diff(b"abc", b"abcd"));
+    assert!(!print_diff(b"abcd", b"abc"));
+}
+
+#[test]
+fn symbol_payload_round_trips() {
+    let mut payload = Vec::new();
+    push_name(&mut payload, "dst");
+    payload.extend_from_slice(&[1, 2, 3]);
+    let (name, contents) = parse_symbol(&payload).unwrap();
+    assert_eq!(name, "dst");
+    assert_eq!(contents, [1, 2, 3]);
+}
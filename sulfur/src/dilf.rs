@@ -1,5 +1,8 @@
 use core::{alloc::Layout, ptr::NonNull};
 
+#[cfg(test)]
+mod tests;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct Dilf32Header {
@@ -27,6 +30,15 @@ pub struct ChunkSpec {
     pub mem_size: u32,
     pub mem_align: u32,
 }
+/// The DMA engine should access the chunk through the L2-coherent bus alias rather than the
+/// uncached one.
+pub const CHUNK_FLAGS_L2_CACHED: u32 = 0x1;
+/// The chunk should be placed in memory allocated from the VideoCore rather than on the loader's
+/// heap.
+pub const CHUNK_FLAGS_VC_MEMORY: u32 = 0x2;
+/// Every chunk flag a loader is expected to understand. Chunks with any other flag set are
+/// rejected by [`Dilf::parse`].
+pub const CHUNK_FLAGS_KNOWN: u32 = CHUNK_FLAGS_L2_CACHED | CHUNK_FLAGS_VC_MEMORY;
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Op {
@@ -35,12 +47,32 @@ pub struct Op {
     pub src: Src,
     pub len: Len,
     pub nxt: Nxt,
+    /// Only for [`OP_FLAGS_TDMODE`] ops, 0 otherwise: bytes added to the destination (high 16
+    /// bits) and source (low 16 bits) addresses after each row, as signed 16-bit values.
+    pub stride: u32,
 }
 pub const OP_FLAGS_DST_OFFSET: u32 = 0x0;
 pub const OP_FLAGS_SRC_OFFSET: u32 = 0x4;
 pub const OP_FLAGS_LEN_OFFSET: u32 = 0x8;
 pub const OP_FLAGS_NXT_OFFSET: u32 = 0xc;
+/// Peripheral whose DREQ paces the transfer (the DMA engine's PERMAP field), 5 bits.
+pub const OP_FLAGS_PERMAP_OFFSET: u32 = 0x10;
+/// Wait for the peripheral's DREQ before each write.
+pub const OP_FLAGS_DST_DREQ: u32 = 1 << 0x15;
+/// Wait for the peripheral's DREQ before each read.
+pub const OP_FLAGS_SRC_DREQ: u32 = 1 << 0x16;
+/// Keep writing to the same address, as when writing to a peripheral's FIFO.
+pub const OP_FLAGS_DST_NO_INC: u32 = 1 << 0x17;
+/// Keep reading from the same address, as when reading from a peripheral's FIFO.
+pub const OP_FLAGS_SRC_NO_INC: u32 = 1 << 0x18;
+/// Two-dimensional transfer: the length is `YLENGTH << 16 | XLENGTH`, and the op moves YLENGTH + 1
+/// rows of XLENGTH bytes, applying [`Op::stride`] between them.
+pub const OP_FLAGS_TDMODE: u32 = 1 << 0x19;
+const OP_FLAGS_KNOWN: u32 = (1 << 0x1a) - 1;
 impl Op {
+    pub fn permap(&self) -> u32 {
+        (self.flags >> OP_FLAGS_PERMAP_OFFSET) & 0x1f
+    }
     pub fn dst(&self) -> OpField<'_> {
         let this = &self.dst;
         match (self.flags >> OP_FLAGS_DST_OFFSET) & 0xf {
@@ -93,6 +125,76 @@ impl Op {
             x => unreachable!("unknown type for Nxt: {x}"),
         }
     }
+
+    /// Whether the op's data refs start inside their chunks and, when the length is fixed, the
+    /// transfer through them ends inside them too. `chunk_size` gives the size of each chunk, or
+    /// `None` for one that doesn't exist.
+    pub fn data_refs_fit(&self, chunk_size: impl Fn(u32) -> Option<usize>) -> bool {
+        let len = match self.len() {
+            OpField::Fixed(len) => Some(*len),
+            _ => None,
+        };
+        let tdmode = self.flags & OP_FLAGS_TDMODE != 0;
+        let fits = |data_ref: &DataRef, no_inc: u32, stride: u32| {
+            let Some(size) = chunk_size(data_ref.chunk) else {
+                return false;
+            };
+            let (offset, size) = (data_ref.offset as i64, size as i64);
+            let Some(len) = len else {
+                return offset < size;
+            };
+            let (row, rows) = if tdmode {
+                (len & 0xffff, (len >> 16) + 1)
+            } else {
+                (len, 1)
+            };
+            // a fixed address is read or written a word at a time, and rows still move it by the
+            // stride
+            let inc = self.flags & no_inc == 0;
+            let row_bytes = if inc { row } else { row.min(4) } as i64;
+            let stride = if tdmode {
+                stride as u16 as i16 as i64
+            } else {
+                0
+            };
+            let advance = if inc { row as i64 } else { 0 } + stride;
+            let last_row = offset + (rows as i64 - 1) * advance;
+            offset < size && offset.min(last_row) >= 0 && offset.max(last_row) + row_bytes <= size
+        };
+        let dst_fits = match self.dst() {
+            OpField::DataRef(data_ref) => fits(data_ref, OP_FLAGS_DST_NO_INC, self.stride >> 16),
+            _ => true,
+        };
+        let src_fits = match self.src() {
+            OpField::DataRef(data_ref) => fits(data_ref, OP_FLAGS_SRC_NO_INC, self.stride),
+            // only the address is read from the chunk
+            OpField::DataRefIndirect(data_ref) => {
+                chunk_size(data_ref.chunk).is_some_and(|size| (data_ref.offset as usize) < size)
+            }
+            _ => true,
+        };
+        dst_fits && src_fits
+    }
+
+    /// The length of the transfer, if it is fixed and goes to or from the void.
+    pub fn void_len(&self) -> Option<usize> {
+        let uses_void = matches!(self.dst(), OpField::Hole(Hole::Void))
+            || matches!(self.src(), OpField::Hole(Hole::Void));
+        match self.len() {
+            OpField::Fixed(len) if uses_void => Some(*len as usize),
+            _ => None,
+        }
+    }
+
+    /// Whether the source is read through an indirection word.
+    pub fn has_indirect_src(&self) -> bool {
+        matches!(
+            self.src(),
+            OpField::DataRefIndirect(_)
+                | OpField::OpFieldRefIndirect(_)
+                | OpField::OpRefIndirect(_)
+        )
+    }
 }
 #[derive(Copy, Clone)]
 pub enum OpField<'op> {
@@ -130,6 +232,14 @@ impl Dst {
             },
         }
     }
+    pub fn op_field_ref(op: usize, field_id: OpFieldId) -> Self {
+        Self {
+            op_ref_field: OpFieldRef {
+                op: op as u32,
+                field_id,
+            },
+        }
+    }
 }
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -202,6 +312,9 @@ pub enum OpFieldId {
 }
 
 pub trait Loader {
+    /// Why a chunk or an op couldn't be loaded, such as the loader running out of memory.
+    type Error;
+
     // NOTE: THIS MUST BE CALLED IN THE CORRECT ORDER
     fn load_chunk(
         &mut self,
@@ -209,7 +322,418 @@ pub trait Loader {
         flags: u32,
         layout: Layout,
         backing: Option<&[u8]>,
-    ) -> NonNull<u8>;
-    fn load_ops<I: IntoIterator<Item = Op>>(&mut self, ops: I);
+    ) -> Result<NonNull<u8>, Self::Error>;
+    fn load_ops<I: IntoIterator<Item = Op>>(&mut self, ops: I) -> Result<(), Self::Error>;
     fn map_routine(&mut self, name: &str, op_idx: usize);
 }
+
+pub const DILF32_MAGIC: [u8; 8] = *b"DILF32\0\0";
+pub const DILF32_ARCH_BCM2835: u16 = 0x2835;
+/// Version 2 added [`Op::stride`]. Version 1 files, whose ops are 28 bytes and have no stride, are
+/// still accepted.
+pub const DILF32_VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = 40;
+const CHUNK_SPEC_SIZE: usize = 24;
+const OP_SIZE: usize = 32;
+const OP_SIZE_V1: usize = 28;
+const ROUTINE_SPEC_SIZE: usize = 8;
+/// `ChunkSpec::symbol_ref_offset` for a chunk without a symbol. Offset 0 is inside the header, so
+/// it can never point at a name.
+pub const NO_SYMBOL: u32 = 0;
+
+/// An entry in the routine map segment.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RoutineSpec {
+    /// File offset of the routine's NUL-terminated name.
+    pub name_offset: u32,
+    pub op_idx: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DilfError {
+    Truncated,
+    BadMagic,
+    UnsupportedArch(u16),
+    UnsupportedVersion(u16),
+    UnsupportedFlags(u32),
+    /// A segment's length is not a whole number of entries, or it runs past the end of the file.
+    BadSegment,
+    /// A string offset that runs off the end of the file, or a name that isn't valid UTF-8.
+    BadString(u32),
+    BadChunk(usize),
+    BadOp(usize),
+    BadRoutine(usize),
+}
+
+impl core::fmt::Display for DilfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DilfError::Truncated => write!(f, "file is truncated"),
+            DilfError::BadMagic => write!(f, "bad magic"),
+            DilfError::UnsupportedArch(arch) => write!(f, "unsupported arch {arch:#06x}"),
+            DilfError::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+            DilfError::UnsupportedFlags(flags) => write!(f, "unsupported flags {flags:08x}"),
+            DilfError::BadSegment => write!(f, "bad segment"),
+            DilfError::BadString(offset) => write!(f, "bad string at offset {offset:#x}"),
+            DilfError::BadChunk(idx) => write!(f, "bad chunk {idx}"),
+            DilfError::BadOp(idx) => write!(f, "bad op {idx}"),
+            DilfError::BadRoutine(idx) => write!(f, "bad routine {idx}"),
+        }
+    }
+}
+
+impl core::error::Error for DilfError {}
+
+/// A chunk as described by a DILF file, ready to be passed to [`Loader::load_chunk`].
+#[derive(Debug, Copy, Clone)]
+pub struct ChunkDef<'a> {
+    pub symbol: Option<&'a str>,
+    pub flags: u32,
+    pub layout: Layout,
+    pub backing: Option<&'a [u8]>,
+}
+
+/// A validated DILF file, borrowed from the bytes it was parsed from.
+///
+/// All values are little-endian. The file starts with a [`Dilf32Header`]; the data segment is an
+/// array of [`ChunkSpec`]s, the code segment an array of [`Op`]s in their `repr(C)` layout (32
+/// bytes each), and the routine map segment an array of [`RoutineSpec`]s. Names are
+/// NUL-terminated UTF-8 strings anywhere in the file. A chunk's `file_size` must be either 0, for
+/// a zero-filled chunk, or equal to its `mem_size`.
+///
+/// Parsing checks everything that [`Loader`] implementations would otherwise assert on: field
+/// types are valid for their position, holes and field ids are in range, data references (and
+/// fixed-length transfers through them) are inside their chunk, and op references are inside the
+/// code segment. Fixed addresses are not
+/// checked.
+#[derive(Debug, Copy, Clone)]
+pub struct Dilf<'a> {
+    bytes: &'a [u8],
+    header: Dilf32Header,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_segment(bytes: &[u8], offset: usize) -> SegmentSpec {
+    SegmentSpec {
+        offset: read_u32(bytes, offset),
+        len: read_u32(bytes, offset + 4),
+    }
+}
+
+fn hole(value: u32) -> Option<Hole> {
+    match value {
+        0 => Some(Hole::End),
+        1 => Some(Hole::Void),
+        2 => Some(Hole::Param),
+        3 => Some(Hole::Nil),
+        _ => None,
+    }
+}
+
+fn op_field_id(value: u32) -> Option<OpFieldId> {
+    match value {
+        0 => Some(OpFieldId::Dst),
+        1 => Some(OpFieldId::Src),
+        2 => Some(OpFieldId::Len),
+        3 => Some(OpFieldId::Nxt),
+        _ => None,
+    }
+}
+
+impl<'a> Dilf<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DilfError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DilfError::Truncated);
+        }
+        let header = Dilf32Header {
+            magic: bytes[0..8].try_into().unwrap(),
+            arch: read_u16(bytes, 8),
+            version: read_u16(bytes, 10),
+            flags: read_u32(bytes, 12),
+            code: read_segment(bytes, 16),
+            data: read_segment(bytes, 24),
+            routine_map: read_segment(bytes, 32),
+        };
+        if header.magic != DILF32_MAGIC {
+            return Err(DilfError::BadMagic);
+        }
+        if header.arch != DILF32_ARCH_BCM2835 {
+            return Err(DilfError::UnsupportedArch(header.arch));
+        }
+        let op_size = match header.version {
+            1 => OP_SIZE_V1,
+            DILF32_VERSION => OP_SIZE,
+            version => return Err(DilfError::UnsupportedVersion(version)),
+        };
+        if header.flags != 0 {
+            return Err(DilfError::UnsupportedFlags(header.flags));
+        }
+        for (segment, entry_size) in [
+            (header.code, op_size),
+            (header.data, CHUNK_SPEC_SIZE),
+            (header.routine_map, ROUTINE_SPEC_SIZE),
+        ] {
+            let end = (segment.offset as usize).checked_add(segment.len as usize);
+            if !(segment.len as usize).is_multiple_of(entry_size)
+                || end.is_none_or(|end| end > bytes.len())
+            {
+                return Err(DilfError::BadSegment);
+            }
+        }
+
+        let dilf = Self { bytes, header };
+        for idx in 0..dilf.chunk_count() {
+            dilf.parse_chunk(idx)?;
+        }
+        for idx in 0..dilf.op_count() {
+            dilf.parse_op(idx)?;
+        }
+        for idx in 0..dilf.routine_count() {
+            dilf.parse_routine(idx)?;
+        }
+        Ok(dilf)
+    }
+
+    pub fn header(&self) -> &Dilf32Header {
+        &self.header
+    }
+    pub fn chunk_count(&self) -> usize {
+        self.header.data.len as usize / CHUNK_SPEC_SIZE
+    }
+    pub fn op_count(&self) -> usize {
+        self.header.code.len as usize / self.op_size()
+    }
+    fn op_size(&self) -> usize {
+        if self.header.version == 1 {
+            OP_SIZE_V1
+        } else {
+            OP_SIZE
+        }
+    }
+    pub fn routine_count(&self) -> usize {
+        self.header.routine_map.len as usize / ROUTINE_SPEC_SIZE
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkDef<'a>> + '_ {
+        (0..self.chunk_count()).map(|idx| self.parse_chunk(idx).unwrap())
+    }
+    pub fn ops(&self) -> impl Iterator<Item = Op> + '_ {
+        (0..self.op_count()).map(|idx| self.parse_op(idx).unwrap())
+    }
+    /// Routine names and their entry ops.
+    pub fn routines(&self) -> impl Iterator<Item = (&'a str, usize)> + '_ {
+        (0..self.routine_count()).map(|idx| self.parse_routine(idx).unwrap())
+    }
+
+    /// The longest fixed-length transfer to or from the void, which a loader needs to size it.
+    pub fn max_void_len(&self) -> usize {
+        self.ops().filter_map(|op| op.void_len()).max().unwrap_or(0)
+    }
+
+    /// How many ops read their source through an indirection word, which a loader has to find
+    /// room for alongside the ops.
+    pub fn indirection_count(&self) -> usize {
+        self.ops().filter(Op::has_indirect_src).count()
+    }
+
+    /// Hand every chunk, op and routine to `loader`, in that order, stopping at the first error.
+    pub fn load<L: Loader>(&self, loader: &mut L) -> Result<(), L::Error> {
+        for chunk in self.chunks() {
+            loader.load_chunk(chunk.symbol, chunk.flags, chunk.layout, chunk.backing)?;
+        }
+        loader.load_ops(self.ops())?;
+        for (name, op_idx) in self.routines() {
+            loader.map_routine(name, op_idx);
+        }
+        Ok(())
+    }
+
+    fn string(&self, offset: u32) -> Result<&'a str, DilfError> {
+        let tail = self
+            .bytes
+            .get(offset as usize..)
+            .ok_or(DilfError::BadString(offset))?;
+        let len = tail
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(DilfError::BadString(offset))?;
+        core::str::from_utf8(&tail[..len]).map_err(|_| DilfError::BadString(offset))
+    }
+
+    fn parse_chunk(&self, idx: usize) -> Result<ChunkDef<'a>, DilfError> {
+        let at = self.header.data.offset as usize + idx * CHUNK_SPEC_SIZE;
+        let spec = ChunkSpec {
+            symbol_ref_offset: read_u32(self.bytes, at),
+            flags: read_u32(self.bytes, at + 4),
+            chunk_offset: read_u32(self.bytes, at + 8),
+            file_size: read_u32(self.bytes, at + 12),
+            mem_size: read_u32(self.bytes, at + 16),
+            mem_align: read_u32(self.bytes, at + 20),
+        };
+        let err = DilfError::BadChunk(idx);
+        let symbol = match spec.symbol_ref_offset {
+            NO_SYMBOL => None,
+            offset => Some(self.string(offset)?),
+        };
+        if spec.mem_size == 0 || spec.flags & !CHUNK_FLAGS_KNOWN != 0 {
+            return Err(err);
+        }
+        let layout = Layout::from_size_align(spec.mem_size as usize, spec.mem_align as usize)
+            .map_err(|_| err)?;
+        let backing = match spec.file_size {
+            0 => None,
+            size if size == spec.mem_size => {
+                let start = spec.chunk_offset as usize;
+                let end = start.checked_add(size as usize).ok_or(err)?;
+                Some(self.bytes.get(start..end).ok_or(err)?)
+            }
+            _ => return Err(err),
+        };
+        Ok(ChunkDef {
+            symbol,
+            flags: spec.flags,
+            layout,
+            backing,
+        })
+    }
+
+    fn chunk_size(&self, chunk: u32) -> Option<usize> {
+        let at = self.header.data.offset as usize + chunk as usize * CHUNK_SPEC_SIZE;
+        ((chunk as usize) < self.chunk_count()).then(|| read_u32(self.bytes, at + 16) as usize)
+    }
+
+    fn parse_op(&self, idx: usize) -> Result<Op, DilfError> {
+        let at = self.header.code.offset as usize + idx * self.op_size();
+        let word = |i: usize| read_u32(self.bytes, at + 4 * i);
+        let err = DilfError::BadOp(idx);
+        let flags = word(0);
+        if flags & !OP_FLAGS_KNOWN != 0 {
+            return Err(err);
+        }
+        let type_of = |offset: u32| (flags >> offset) & 0xf;
+
+        let data_ref = |chunk: u32, offset: u32| -> Result<DataRef, DilfError> {
+            match self.chunk_size(chunk) {
+                Some(size) if (offset as usize) < size => Ok(DataRef { chunk, offset }),
+                _ => Err(err),
+            }
+        };
+        let op_ref = |op: u32| -> Result<u32, DilfError> {
+            if (op as usize) < self.op_count() {
+                Ok(op)
+            } else {
+                Err(err)
+            }
+        };
+        let op_field_ref = |op: u32, field_id: u32| -> Result<OpFieldRef, DilfError> {
+            Ok(OpFieldRef {
+                op: op_ref(op)?,
+                field_id: op_field_id(field_id).ok_or(err)?,
+            })
+        };
+        let hole_of = |value: u32, allowed: &[u32]| -> Result<Hole, DilfError> {
+            match hole(value) {
+                Some(hole) if allowed.contains(&value) => Ok(hole),
+                _ => Err(err),
+            }
+        };
+
+        let dst = match type_of(OP_FLAGS_DST_OFFSET) {
+            0 => Dst {
+                data_ref: data_ref(word(1), word(2))?,
+            },
+            2 => Dst {
+                op_ref_field: op_field_ref(word(1), word(2))?,
+            },
+            4 => Dst { fixed: word(1) },
+            5 => Dst {
+                hole: hole_of(word(1), &[1, 2, 3])?,
+            },
+            _ => return Err(err),
+        };
+        let src = match type_of(OP_FLAGS_SRC_OFFSET) {
+            0 => Src {
+                data_ref: data_ref(word(3), word(4))?,
+            },
+            1 => Src {
+                data_ref_indirect: data_ref(word(3), word(4))?,
+            },
+            2 => Src {
+                op_ref_field: op_field_ref(word(3), word(4))?,
+            },
+            3 => Src {
+                op_ref_field_indirect: op_field_ref(word(3), word(4))?,
+            },
+            4 => Src { fixed: word(3) },
+            5 => Src {
+                hole: hole_of(word(3), &[1, 2, 3])?,
+            },
+            6 => Src {
+                op_ref_indirect: op_ref(word(3))?,
+            },
+            _ => return Err(err),
+        };
+        let len = match type_of(OP_FLAGS_LEN_OFFSET) {
+            4 => Len { fixed: word(5) },
+            5 => Len {
+                hole: hole_of(word(5), &[2, 3])?,
+            },
+            _ => return Err(err),
+        };
+        let nxt = match type_of(OP_FLAGS_NXT_OFFSET) {
+            4 => Nxt { fixed: word(6) },
+            5 => Nxt {
+                hole: hole_of(word(6), &[0, 2, 3])?,
+            },
+            6 => Nxt {
+                op_ref: op_ref(word(6))?,
+            },
+            _ => return Err(err),
+        };
+        let stride = if self.op_size() > OP_SIZE_V1 {
+            word(7)
+        } else {
+            0
+        };
+        if stride != 0 && flags & OP_FLAGS_TDMODE == 0 {
+            return Err(err);
+        }
+        let op = Op {
+            flags,
+            dst,
+            src,
+            len,
+            nxt,
+            stride,
+        };
+        if !op.data_refs_fit(|chunk| self.chunk_size(chunk)) {
+            return Err(err);
+        }
+        // the void needs a fixed, one-dimensional length to be sized from
+        let uses_void = matches!(op.dst(), OpField::Hole(Hole::Void))
+            || matches!(op.src(), OpField::Hole(Hole::Void));
+        if uses_void && (!matches!(op.len(), OpField::Fixed(_)) || flags & OP_FLAGS_TDMODE != 0) {
+            return Err(err);
+        }
+        Ok(op)
+    }
+
+    fn parse_routine(&self, idx: usize) -> Result<(&'a str, usize), DilfError> {
+        let at = self.header.routine_map.offset as usize + idx * ROUTINE_SPEC_SIZE;
+        let name = self.string(read_u32(self.bytes, at))?;
+        let op_idx = read_u32(self.bytes, at + 4) as usize;
+        if op_idx >= self.op_count() {
+            return Err(DilfError::BadRoutine(idx));
+        }
+        Ok((name, op_idx))
+    }
+}
@@ -0,0 +1,136 @@
+extern crate std;
+
+use std::vec::Vec;
+
+use super::*;
+
+/// A version 1 file with two 16-byte chunks, one op copying the second into the first, and a
+/// routine `main` starting at that op.
+fn copy_image() -> Vec<u8> {
+    let mut file = DILF32_MAGIC.to_vec();
+    let mut words = |words: &[u32]| {
+        for w in words {
+            file.extend_from_slice(&w.to_le_bytes());
+        }
+    };
+    words(&[DILF32_ARCH_BCM2835 as u32 | 1 << 16, 0]);
+    // segments: code at 88 (1 op), data at 40 (2 chunks), routine map at 116 (1 routine)
+    words(&[88, 28, 40, 48, 116, 8]);
+    // chunks: symbol, flags, file offset, file size, mem size, align
+    words(&[124, 0, 0, 0, 16, 4]);
+    words(&[128, 0, 140, 16, 16, 4]);
+    // op: dst = chunk 0, src = chunk 1, len = 16, nxt = end
+    words(&[0x5400, 0, 0, 1, 0, 16, 0]);
+    // routine: name, entry
+    words(&[132, 0]);
+    file.extend_from_slice(b"dst\0src\0main\0\0\0\0");
+    file.extend_from_slice(&[0x11; 16]);
+    assert_eq!(file.len(), 156);
+    file
+}
+
+fn patch(mut image: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    image
+}
+
+#[test]
+fn parses_valid_image() {
+    let image = copy_image();
+    let dilf = Dilf::parse(&image).unwrap();
+    assert_eq!(dilf.op_count(), 1);
+    let chunks: Vec<_> = dilf.chunks().collect();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].symbol, Some("dst"));
+    assert!(chunks[0].backing.is_none());
+    assert_eq!(chunks[1].backing, Some(&[0x11; 16][..]));
+    assert_eq!(dilf.routines().collect::<Vec<_>>(), [("main", 0)]);
+}
+
+#[test]
+fn rejects_truncated_header() {
+    let image = copy_image();
+    assert_eq!(
+        Dilf::parse(&image[..HEADER_SIZE - 1]).unwrap_err(),
+        DilfError::Truncated
+    );
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut image = copy_image();
+    image[0] = b'E';
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadMagic);
+}
+
+#[test]
+fn rejects_unknown_version() {
+    let image = patch(copy_image(), 8, DILF32_ARCH_BCM2835 as u32 | 3 << 16);
+    assert_eq!(
+        Dilf::parse(&image).unwrap_err(),
+        DilfError::UnsupportedVersion(3)
+    );
+}
+
+#[test]
+fn rejects_segment_past_end() {
+    // the routine map claims a second entry, which would run past the end of the file
+    let image = patch(copy_image(), 36, 160);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadSegment);
+}
+
+#[test]
+fn rejects_partial_segment_entry() {
+    let image = patch(copy_image(), 20, 27);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadSegment);
+}
+
+#[test]
+fn rejects_overflowing_segment() {
+    let image = patch(copy_image(), 32, u32::MAX);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadSegment);
+}
+
+#[test]
+fn rejects_unknown_chunk_flags() {
+    let image = patch(copy_image(), 68, CHUNK_FLAGS_L2_CACHED | 1 << 31);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadChunk(1));
+}
+
+#[test]
+fn accepts_known_chunk_flags() {
+    let image = patch(copy_image(), 68, CHUNK_FLAGS_KNOWN);
+    let dilf = Dilf::parse(&image).unwrap();
+    assert_eq!(dilf.chunks().nth(1).unwrap().flags, CHUNK_FLAGS_KNOWN);
+}
+
+#[test]
+fn rejects_transfer_running_past_chunk() {
+    // the source starts inside its 16-byte chunk, but 16 bytes from offset 8 run past the end
+    let image = patch(copy_image(), 104, 8);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadOp(0));
+    let image = patch(copy_image(), 96, 4);
+    assert_eq!(Dilf::parse(&image).unwrap_err(), DilfError::BadOp(0));
+    // and 8 bytes from there fit
+    let image = patch(patch(copy_image(), 104, 8), 108, 8);
+    Dilf::parse(&image).unwrap();
+}
+
+#[test]
+fn checks_every_row_of_2d_transfer() {
+    // two rows of 8 bytes, the second 8 bytes after the first ends: 24 bytes in all
+    let op = |stride: u32| Op {
+        flags: 0x5400 | OP_FLAGS_TDMODE,
+        dst: Dst::data_ref(0, 0),
+        src: Src::data_ref(1, 0),
+        len: Len::fixed(1 << 16 | 8),
+        nxt: Nxt::end(),
+        stride,
+    };
+    let sizes = |size: usize| move |_| Some(size);
+    assert!(op(8 << 16 | 8).data_refs_fit(sizes(24)));
+    assert!(!op(8 << 16 | 8).data_refs_fit(sizes(23)));
+    // going back up from the second row would start before the chunk
+    assert!(!op(8 << 16 | (-16i16 as u16 as u32)).data_refs_fit(sizes(24)));
+    assert!(!op(0).data_refs_fit(|_| None));
+}
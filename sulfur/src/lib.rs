@@ -1,3 +1,5 @@
 #![no_std]
 
-pub mod dilf;
\ No newline at end of file
+pub mod dilf;
+pub mod link;
+pub mod stats;
\ No newline at end of file
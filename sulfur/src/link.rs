@@ -0,0 +1,230 @@
+//! The framed protocol spoken over the UART between a host and a running kernel.
+//!
+//! Every frame, in either direction, is
+//!
+//! ```text
+//! magic: [u8; 2] | len: u32 | command: u8 | payload: [u8; len] | crc: u32
+//! ```
+//!
+//! with integers little-endian, `len` counting only the payload, and `crc` the CRC-32 (IEEE) of
+//! the command byte and the payload. Anything between frames is ignored, so the kernel's ordinary
+//! console output can share the line.
+//!
+//! Requests and their replies:
+//!
+//! - [`Command::Ping`], empty; replies [`Command::Pong`] with the protocol version as a `u16`.
+//! - [`Command::Load`], a DILF file; replies [`Command::Loaded`] with a `u16` routine count and
+//!   that many names, then a `u16` symbol count and that many names each followed by a `u32`
+//!   size. Replaces whatever was loaded before.
+//! - [`Command::Run`], a `u8` channel, a `u16` iteration count, the routine name, and any number
+//!   of symbol names; replies [`Command::Timings`] with one `u32` cycle count per iteration, then
+//!   one [`Command::Symbol`] per requested symbol.
+//! - [`Command::ReadSymbol`], a symbol name; replies [`Command::Symbol`] with the name followed
+//!   by the symbol's contents.
+//! - [`Command::Exit`], a `u32` exit status; doesn't reply.
+//!
+//! Names are NUL-terminated. Any request may instead be answered with [`Command::Error`], whose
+//! payload is a UTF-8 message.
+
+#[cfg(test)]
+mod tests;
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+pub const FRAME_MAGIC: [u8; 2] = [0xd5, 0x1f];
+pub const HEADER_LEN: usize = 7;
+pub const TRAILER_LEN: usize = 4;
+/// Frames claiming a longer payload are rejected, so that a corrupted length can't make either
+/// side wait for megabytes that are never coming.
+pub const MAX_PAYLOAD_LEN: usize = 0x10_0000;
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Command {
+    Ping = 0x01,
+    Load = 0x02,
+    Run = 0x03,
+    ReadSymbol = 0x04,
+    Exit = 0x05,
+
+    Pong = 0x81,
+    Loaded = 0x82,
+    Timings = 0x83,
+    Symbol = 0x84,
+    Error = 0xff,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = LinkError;
+
+    fn try_from(value: u8) -> Result<Self, LinkError> {
+        Ok(match value {
+            0x01 => Command::Ping,
+            0x02 => Command::Load,
+            0x03 => Command::Run,
+            0x04 => Command::ReadSymbol,
+            0x05 => Command::Exit,
+            0x81 => Command::Pong,
+            0x82 => Command::Loaded,
+            0x83 => Command::Timings,
+            0x84 => Command::Symbol,
+            0xff => Command::Error,
+            x => return Err(LinkError::UnknownCommand(x)),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LinkError {
+    TooLong(usize),
+    UnknownCommand(u8),
+    BadCrc {
+        expected: u32,
+        actual: u32,
+    },
+    /// A payload that ended early or didn't have the expected shape.
+    Malformed,
+}
+
+impl core::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LinkError::TooLong(len) => write!(f, "payload too long: {len} bytes"),
+            LinkError::UnknownCommand(command) => write!(f, "unknown command {command:#04x}"),
+            LinkError::BadCrc { expected, actual } => {
+                write!(f, "bad CRC: expected {expected:08x}, got {actual:08x}")
+            }
+            LinkError::Malformed => write!(f, "malformed payload"),
+        }
+    }
+}
+
+impl core::error::Error for LinkError {}
+
+/// The part of a frame after the magic and before the payload.
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub command: Command,
+    pub len: usize,
+}
+
+impl Header {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let len = (self.len as u32).to_le_bytes();
+        [
+            FRAME_MAGIC[0],
+            FRAME_MAGIC[1],
+            len[0],
+            len[1],
+            len[2],
+            len[3],
+            self.command as u8,
+        ]
+    }
+
+    /// Decode the five bytes following the magic.
+    pub fn decode(bytes: [u8; HEADER_LEN - 2]) -> Result<Self, LinkError> {
+        let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(LinkError::TooLong(len));
+        }
+        Ok(Self {
+            command: Command::try_from(bytes[4])?,
+            len,
+        })
+    }
+}
+
+/// Incremental CRC-32 (IEEE 802.3, reflected, as used by zlib).
+#[derive(Debug, Copy, Clone)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn frame_crc(command: Command, payload: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&[command as u8]);
+    crc.update(payload);
+    crc.finish()
+}
+
+pub fn check_crc(
+    command: Command,
+    payload: &[u8],
+    trailer: [u8; TRAILER_LEN],
+) -> Result<(), LinkError> {
+    let expected = u32::from_le_bytes(trailer);
+    let actual = frame_crc(command, payload);
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(LinkError::BadCrc { expected, actual })
+    }
+}
+
+/// Reads the fields of a payload front to back.
+pub struct PayloadReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], LinkError> {
+        if len > self.bytes.len() {
+            return Err(LinkError::Malformed);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+    /// Everything that hasn't been read yet.
+    pub fn rest(&mut self) -> &'a [u8] {
+        core::mem::take(&mut self.bytes)
+    }
+    pub fn u8(&mut self) -> Result<u8, LinkError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, LinkError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, LinkError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    pub fn name(&mut self) -> Result<&'a str, LinkError> {
+        let len = self
+            .bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(LinkError::Malformed)?;
+        let name = core::str::from_utf8(self.bytes(len)?).map_err(|_| LinkError::Malformed)?;
+        self.bytes(1)?;
+        Ok(name)
+    }
+}
@@ -0,0 +1,96 @@
+extern crate std;
+
+use std::vec::Vec;
+
+use super::*;
+
+fn frame(command: Command, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Header {
+        command,
+        len: payload.len(),
+    }
+    .encode()
+    .to_vec();
+    frame.extend_from_slice(payload);
+    frame.extend_from_slice(&frame_crc(command, payload).to_le_bytes());
+    frame
+}
+
+#[test]
+fn crc32_matches_check_value() {
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.finish(), 0xcbf4_3926);
+}
+
+#[test]
+fn crc32_is_incremental() {
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xcbf4_3926);
+    assert_eq!(Crc32::new().finish(), 0);
+}
+
+#[test]
+fn frame_round_trips() {
+    let payload = b"main\0";
+    let frame = frame(Command::Run, payload);
+    assert_eq!(frame[..2], FRAME_MAGIC);
+    let header = Header::decode(frame[2..HEADER_LEN].try_into().unwrap()).unwrap();
+    assert_eq!(header.command, Command::Run);
+    assert_eq!(header.len, payload.len());
+    let body = &frame[HEADER_LEN..HEADER_LEN + header.len];
+    let trailer = frame[HEADER_LEN + header.len..].try_into().unwrap();
+    assert_eq!(check_crc(header.command, body, trailer), Ok(()));
+}
+
+#[test]
+fn rejects_corrupted_payload() {
+    let mut frame = frame(Command::Load, b"abcd");
+    frame[HEADER_LEN] ^= 1;
+    let trailer = frame[HEADER_LEN + 4..].try_into().unwrap();
+    assert!(matches!(
+        check_crc(Command::Load, &frame[HEADER_LEN..HEADER_LEN + 4], trailer),
+        Err(LinkError::BadCrc { .. })
+    ));
+}
+
+#[test]
+fn rejects_bad_header() {
+    let mut header = [0; HEADER_LEN - 2];
+    header[4] = 0x42;
+    assert_eq!(
+        Header::decode(header).unwrap_err(),
+        LinkError::UnknownCommand(0x42)
+    );
+    header[..4].copy_from_slice(&(MAX_PAYLOAD_LEN as u32 + 1).to_le_bytes());
+    header[4] = Command::Load as u8;
+    assert_eq!(
+        Header::decode(header).unwrap_err(),
+        LinkError::TooLong(MAX_PAYLOAD_LEN + 1)
+    );
+}
+
+#[test]
+fn reads_payload_fields() {
+    let mut payload = Vec::from([7, 0x34, 0x12]);
+    payload.extend_from_slice(&0xdead_beefu32.to_le_bytes());
+    payload.extend_from_slice(b"main\0rest");
+    let mut reader = PayloadReader::new(&payload);
+    assert_eq!(reader.u8(), Ok(7));
+    assert_eq!(reader.u16(), Ok(0x1234));
+    assert_eq!(reader.u32(), Ok(0xdead_beef));
+    assert_eq!(reader.name(), Ok("main"));
+    assert_eq!(reader.rest(), b"rest");
+    assert!(reader.is_empty());
+    assert_eq!(reader.u8(), Err(LinkError::Malformed));
+}
+
+#[test]
+fn rejects_unterminated_name() {
+    assert_eq!(
+        PayloadReader::new(b"main").name(),
+        Err(LinkError::Malformed)
+    );
+}
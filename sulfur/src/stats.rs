@@ -0,0 +1,124 @@
+//! Summary statistics over benchmark samples, shared by the kernel's benchmarks and the host tool
+//! so that the CSV lines they write can be mixed.
+
+use core::fmt::{Display, Formatter};
+
+#[cfg(test)]
+mod tests;
+
+pub const CSV_HEADER: &str = "name,n,rejected,min,p5,p25,median,p75,p95,max,mean,stddev";
+
+/// Summary statistics over the samples that survived outlier rejection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stats {
+    pub n: usize,
+    pub rejected: usize,
+    pub min: u32,
+    pub p5: u32,
+    pub p25: u32,
+    pub median: u32,
+    pub p75: u32,
+    pub p95: u32,
+    pub max: u32,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    /// Compute statistics over `samples`, which are sorted in place. If `reject_outliers`,
+    /// anything outside Tukey's fences (1.5 IQR beyond the quartiles) is discarded first.
+    /// `samples` must not be empty.
+    pub fn new(samples: &mut [u32], reject_outliers: bool) -> Self {
+        samples.sort_unstable();
+
+        let mut kept = &samples[..];
+        if reject_outliers {
+            let q1 = percentile(kept, 25) as i64;
+            let q3 = percentile(kept, 75) as i64;
+            let fence = (q3 - q1) * 3 / 2;
+            let (lo, hi) = (q1 - fence, q3 + fence);
+            // sorted, so whatever is inside the fences is contiguous
+            let start = kept.partition_point(|&s| (s as i64) < lo);
+            let end = kept.partition_point(|&s| s as i64 <= hi);
+            kept = &kept[start..end];
+        }
+        let rejected = samples.len() - kept.len();
+
+        let n = kept.len();
+        let mean = kept.iter().map(|&s| s as f64).sum::<f64>() / n as f64;
+        let variance = kept
+            .iter()
+            .map(|&s| (s as f64 - mean) * (s as f64 - mean))
+            .sum::<f64>()
+            / n as f64;
+
+        Self {
+            n,
+            rejected,
+            min: kept[0],
+            p5: percentile(kept, 5),
+            p25: percentile(kept, 25),
+            median: percentile(kept, 50),
+            p75: percentile(kept, 75),
+            p95: percentile(kept, 95),
+            max: kept[n - 1],
+            mean,
+            stddev: sqrt(variance),
+        }
+    }
+
+    /// A line matching [`CSV_HEADER`], without the line ending.
+    pub fn csv<'a>(&'a self, name: &'a str) -> Csv<'a> {
+        Csv { name, stats: self }
+    }
+}
+
+/// Formats as one CSV line; see [`Stats::csv`].
+pub struct Csv<'a> {
+    name: &'a str,
+    stats: &'a Stats,
+}
+
+impl Display for Csv<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let s = self.stats;
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{:.2},{:.2}",
+            self.name,
+            s.n,
+            s.rejected,
+            s.min,
+            s.p5,
+            s.p25,
+            s.median,
+            s.p75,
+            s.p95,
+            s.max,
+            s.mean,
+            s.stddev
+        )
+    }
+}
+
+/// Nearest-rank percentile of an already-sorted, non-empty slice.
+fn percentile(sorted: &[u32], p: usize) -> u32 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+// core has no float sqrt, so Newton-Raphson it.
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let mut r = if x > 1.0 { x / 2.0 } else { 1.0 };
+    for _ in 0..64 {
+        let next = (r + x / r) / 2.0;
+        if next == r {
+            break;
+        }
+        r = next;
+    }
+    r
+}
@@ -0,0 +1,51 @@
+extern crate std;
+
+use std::string::ToString;
+
+use super::*;
+
+#[test]
+fn summarises_samples() {
+    let mut samples = [5, 1, 4, 2, 3];
+    let stats = Stats::new(&mut samples, true);
+    assert_eq!(samples, [1, 2, 3, 4, 5]);
+    assert_eq!((stats.n, stats.rejected), (5, 0));
+    assert_eq!((stats.min, stats.median, stats.max), (1, 3, 5));
+    assert_eq!((stats.p5, stats.p25, stats.p75, stats.p95), (1, 2, 4, 5));
+    assert_eq!(stats.mean, 3.0);
+    assert!((stats.stddev - 2.0f64.sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn rejects_outliers_on_both_sides() {
+    let mut samples = [1, 100, 100, 101, 102, 102, 103, 1000];
+    let stats = Stats::new(&mut samples, true);
+    assert_eq!((stats.n, stats.rejected), (6, 2));
+    assert_eq!((stats.min, stats.max), (100, 103));
+}
+
+#[test]
+fn keeps_outliers_when_asked() {
+    let mut samples = [1, 100, 100, 101, 102, 102, 103, 1000];
+    let stats = Stats::new(&mut samples, false);
+    assert_eq!((stats.n, stats.rejected), (8, 0));
+    assert_eq!((stats.min, stats.max), (1, 1000));
+}
+
+#[test]
+fn single_sample() {
+    let stats = Stats::new(&mut [42], true);
+    assert_eq!(
+        (stats.n, stats.min, stats.median, stats.max),
+        (1, 42, 42, 42)
+    );
+    assert_eq!((stats.mean, stats.stddev), (42.0, 0.0));
+}
+
+#[test]
+fn formats_csv_line() {
+    let stats = Stats::new(&mut [1, 2, 3, 4], true);
+    let line = stats.csv("dma.copy").to_string();
+    assert_eq!(line, "dma.copy,4,0,1,1,1,2,3,4,4,2.50,1.12");
+    assert_eq!(line.split(',').count(), CSV_HEADER.split(',').count());
+}